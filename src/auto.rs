@@ -0,0 +1,337 @@
+//! Drives the brightness from an ambient light sensor.
+//!
+//! The illuminance is read every interval from a Linux IIO light sensor, a
+//! file or the output of a command, mapped to a brightness by a curve and
+//! smoothed before it is written.
+
+use crate::cli_error::CliError;
+use crate::config::Config;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use ws170120::{LuxCurve, LuxPoint, Smoother};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_HYSTERESIS: u8 = 5;
+const DEFAULT_RATE: f64 = 2.0;
+
+/// Channel names of IIO light sensors, with and without an index.
+const CHANNELS: [&str; 2] = ["in_illuminance", "in_illuminance0"];
+
+#[derive(clap::Args)]
+pub struct AutoArgs {
+    /// IIO light sensor, either its directory like
+    /// /sys/bus/iio/devices/iio:device0 or its in_illuminance_raw file
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["command", "file"])]
+    pub sensor: Option<PathBuf>,
+
+    /// Shell command printing the illuminance in lux
+    #[arg(long, conflicts_with = "file")]
+    pub command: Option<String>,
+
+    /// File containing the illuminance in lux
+    #[arg(long, value_name = "PATH")]
+    pub file: Option<PathBuf>,
+
+    /// Point of the curve from illuminance to brightness, e.g. 500:80; may
+    /// be given several times
+    #[arg(long = "point", value_name = "LUX:PERCENT")]
+    pub points: Vec<LuxPoint>,
+
+    /// How often the light is read [default: 1s]
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub interval: Option<Duration>,
+
+    /// Ignore changes of fewer percentage points than this [default: 5]
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub hysteresis: Option<u8>,
+
+    /// Change the brightness by at most this many percentage points per
+    /// second, 0 for no limit [default: 2]
+    #[arg(long)]
+    pub rate: Option<f64>,
+}
+
+/// Where the illuminance comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Sensor(PathBuf),
+    Command(String),
+    File(PathBuf),
+}
+
+impl Source {
+    /// Reads the illuminance in lux.
+    fn read(&self) -> Result<f64, String> {
+        match self {
+            Source::Sensor(path) => read_sensor(path),
+            Source::Command(command) => run_command(command),
+            Source::File(path) => read_number(path),
+        }
+    }
+}
+
+fn read_number(path: &Path) -> Result<f64, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    match contents.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(format!(
+            "{}: '{}' is not a number",
+            path.display(),
+            contents.trim()
+        )),
+    }
+}
+
+fn read_sensor(path: &Path) -> Result<f64, String> {
+    if !path.is_dir() {
+        return read_raw(path);
+    }
+    // Sensors that report lux directly have an input attribute
+    for channel in CHANNELS {
+        let input = path.join(format!("{channel}_input"));
+        if input.exists() {
+            return read_number(&input);
+        }
+        let raw = path.join(format!("{channel}_raw"));
+        if raw.exists() {
+            return read_raw(&raw);
+        }
+    }
+    Err(format!("{} has no illuminance channel", path.display()))
+}
+
+/// Reads a raw IIO value and converts it to lux using the offset and scale
+/// attributes next to it, if there are any.
+fn read_raw(raw: &Path) -> Result<f64, String> {
+    let value = read_number(raw)?;
+    let channel = raw
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_suffix("_raw"));
+    let attribute = |name: &str| match channel {
+        Some(channel) => {
+            let path = raw.with_file_name(format!("{channel}_{name}"));
+            path.exists().then(|| read_number(&path)).transpose()
+        }
+        None => Ok(None),
+    };
+    let offset = attribute("offset")?.unwrap_or(0.0);
+    let scale = attribute("scale")?.unwrap_or(1.0);
+    Ok((value + offset) * scale)
+}
+
+fn run_command(command: &str) -> Result<f64, String> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    }
+    .map_err(|e| format!("running '{command}' failed: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "'{command}' failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(format!(
+            "'{command}' printed '{}' instead of a number",
+            stdout.trim()
+        )),
+    }
+}
+
+/// The options of [`AutoArgs`] with the defaults from the configuration
+/// filled in.
+pub struct Settings {
+    pub source: Source,
+    pub curve: LuxCurve,
+    pub interval: Duration,
+    pub hysteresis: u8,
+    /// Percentage points per second, `None` for no limit.
+    pub rate: Option<f64>,
+}
+
+impl Settings {
+    pub fn new(args: &AutoArgs, config: &Config) -> Result<Self, CliError> {
+        let auto = &config.auto;
+        let source = if let Some(path) = &args.sensor {
+            Source::Sensor(path.clone())
+        } else if let Some(command) = &args.command {
+            Source::Command(command.clone())
+        } else if let Some(path) = &args.file {
+            Source::File(path.clone())
+        } else {
+            auto.source.clone().ok_or_else(|| {
+                CliError::InvalidArgument(
+                    "No light source: give --sensor, --command or --file, or set one in the [auto] table of the configuration file.".to_string(),
+                )
+            })?
+        };
+        let curve = if args.points.is_empty() {
+            auto.curve.clone().unwrap_or_default()
+        } else {
+            LuxCurve::new(args.points.clone())
+                .map_err(|e| CliError::InvalidArgument(format!("Invalid curve: {e}.")))?
+        };
+        let rate = args.rate.or(auto.rate).unwrap_or(DEFAULT_RATE);
+        if !rate.is_finite() || rate < 0.0 {
+            return Err(CliError::InvalidArgument(format!(
+                "Invalid rate {rate}, expected a number of percentage points per second of at least 0."
+            )));
+        }
+        Ok(Settings {
+            source,
+            curve,
+            interval: args.interval.or(auto.interval).unwrap_or(DEFAULT_INTERVAL),
+            hysteresis: args
+                .hysteresis
+                .or(auto.hysteresis)
+                .unwrap_or(DEFAULT_HYSTERESIS),
+            rate: (rate > 0.0).then_some(rate),
+        })
+    }
+}
+
+/// Follows the ambient light forever, calling `apply` whenever the
+/// brightness changes.
+///
+/// Failures to read the light or to write the brightness are reported and
+/// retried on the next tick.
+pub fn run(
+    settings: &Settings,
+    verbose: u8,
+    mut apply: impl FnMut(u8) -> Result<(), CliError>,
+) -> ! {
+    let mut smoother = Smoother::new(settings.hysteresis, settings.rate);
+    let mut applied = None;
+    let mut last_read: Option<Instant> = None;
+    loop {
+        match settings.source.read() {
+            Ok(lux) => {
+                let now = Instant::now();
+                let elapsed = last_read.map_or(Duration::ZERO, |last| now - last);
+                last_read = Some(now);
+                let brightness = smoother.update(settings.curve.brightness(lux), elapsed);
+                if verbose > 1 {
+                    println!("Ambient light is {:.1} lux.", lux);
+                }
+                if applied != Some(brightness) {
+                    if verbose > 0 {
+                        println!(
+                            "Ambient light is {:.0} lux, brightness {}%.",
+                            lux, brightness
+                        );
+                    }
+                    applied = match apply(brightness) {
+                        Ok(()) => Some(brightness),
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            None
+                        }
+                    };
+                }
+            }
+            Err(e) => eprintln!("Reading the ambient light failed: {}", e),
+        }
+        thread::sleep(settings.interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    /// A directory standing in for an IIO device in sysfs.
+    struct FakeSensor(TestDir);
+
+    impl FakeSensor {
+        fn new(name: &str, attributes: &[(&str, &str)]) -> Self {
+            let dir = TestDir::new(&format!("auto-{name}"));
+            for (attribute, value) in attributes {
+                dir.write(attribute, &format!("{value}\n"));
+            }
+            FakeSensor(dir)
+        }
+
+        fn read(&self) -> Result<f64, String> {
+            Source::Sensor(self.0.path().to_path_buf()).read()
+        }
+    }
+
+    #[test]
+    fn applies_offset_and_scale_to_raw_values() {
+        let sensor = FakeSensor::new(
+            "scaled",
+            &[
+                ("in_illuminance_raw", "100"),
+                ("in_illuminance_offset", "10"),
+                ("in_illuminance_scale", "0.5"),
+            ],
+        );
+        assert_eq!(sensor.read(), Ok(55.0));
+        // The raw file itself works as well
+        let raw = Source::Sensor(sensor.0.join("in_illuminance_raw"));
+        assert_eq!(raw.read(), Ok(55.0));
+    }
+
+    #[test]
+    fn raw_values_default_to_lux() {
+        let sensor = FakeSensor::new("unscaled", &[("in_illuminance0_raw", "42")]);
+        assert_eq!(sensor.read(), Ok(42.0));
+    }
+
+    #[test]
+    fn prefers_the_input_attribute() {
+        let sensor = FakeSensor::new(
+            "input",
+            &[
+                ("in_illuminance_input", "123.5"),
+                ("in_illuminance_raw", "7"),
+                ("in_illuminance_scale", "2"),
+            ],
+        );
+        assert_eq!(sensor.read(), Ok(123.5));
+    }
+
+    #[test]
+    fn reports_missing_and_invalid_values() {
+        let empty = FakeSensor::new("empty", &[]);
+        let e = empty.read().unwrap_err();
+        assert!(e.contains("has no illuminance channel"), "{e}");
+
+        let garbage = FakeSensor::new("garbage", &[("in_illuminance_raw", "bright")]);
+        let e = garbage.read().unwrap_err();
+        assert!(e.contains("'bright' is not a number"), "{e}");
+
+        let bad_scale = FakeSensor::new(
+            "bad-scale",
+            &[("in_illuminance_raw", "1"), ("in_illuminance_scale", "inf")],
+        );
+        assert!(bad_scale.read().is_err());
+    }
+
+    #[test]
+    fn reads_files() {
+        let dir = FakeSensor::new("file", &[("lux", " 250.5 ")]);
+        assert_eq!(Source::File(dir.0.join("lux")).read(), Ok(250.5));
+        assert!(Source::File(dir.0.join("missing")).read().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_commands() {
+        assert_eq!(Source::Command("echo 12.5".to_string()).read(), Ok(12.5));
+        let e = Source::Command("echo dark".to_string()).read().unwrap_err();
+        assert!(e.contains("instead of a number"), "{e}");
+        let e = Source::Command("exit 3".to_string()).read().unwrap_err();
+        assert!(e.contains("failed"), "{e}");
+    }
+}
@@ -0,0 +1,426 @@
+//! Errors of the command line tool, wrapping those of the library.
+
+use std::path::PathBuf;
+use std::{fmt, io};
+use ws170120::ErrorKind;
+
+#[derive(Debug)]
+pub enum CliError {
+    /// An error from talking to a display directly.
+    Display(ws170120::Error),
+    /// An error reported by the daemon on our behalf.
+    Daemon { kind: Kind, message: String },
+    /// Using the control socket failed.
+    Io(io::Error),
+    /// A file given to the tool could not be read or understood.
+    InvalidFile { path: PathBuf, message: String },
+    /// Writing a file failed, e.g. the udev rules or the configuration.
+    Install { path: PathBuf, source: io::Error },
+    /// A command line argument that clap could not check, e.g. an unknown
+    /// preset name.
+    InvalidArgument(String),
+    /// `doctor` found this many problems.
+    ProblemsFound(usize),
+    /// Registering or serving the D-Bus service failed.
+    #[cfg(feature = "dbus")]
+    DBus(zbus::Error),
+    /// Starting the HTTP server failed.
+    #[cfg(any(feature = "http", feature = "metrics"))]
+    Http(Box<dyn std::error::Error + Send + Sync>),
+    /// Creating or feeding the virtual touch screen failed.
+    #[cfg(all(target_os = "linux", feature = "uinput"))]
+    Uinput(io::Error),
+}
+
+/// Exit status for errors without a more specific one.
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit status for invalid arguments or files, as used by clap.
+pub const EXIT_INVALID_ARGUMENT: i32 = 2;
+/// Exit status when no matching display is attached.
+pub const EXIT_NOT_CONNECTED: i32 = 3;
+/// Exit status when the display may not be accessed, e.g. without udev rule.
+pub const EXIT_ACCESS_DENIED: i32 = 4;
+/// Exit status when the HID API could not be initialized.
+pub const EXIT_HID_INIT: i32 = 5;
+/// Exit status when a report was only partially written.
+pub const EXIT_SHORT_WRITE: i32 = 6;
+/// Exit status when both the output and the feature report failed.
+pub const EXIT_WRITE_FAILED: i32 = 7;
+
+/// What went wrong, whether it happened here or in the daemon.
+///
+/// Rendered as a short, stable identifier, e.g. `not-connected`, in the
+/// daemon protocol and the JSON output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// An error of the library, named by it.
+    Display(ErrorKind),
+    Io,
+    InvalidFile,
+    InvalidArgument,
+    /// The daemon could not parse a request.
+    InvalidRequest,
+    ProblemsFound,
+    DBus,
+    Http,
+    Uinput,
+    /// A kind relayed by a daemon that this version does not know.
+    Other(String),
+}
+
+impl Kind {
+    /// The kinds of errors of the tool itself, i.e. all but `Display` and
+    /// `Other`.
+    const OWN: [Kind; 8] = [
+        Kind::Io,
+        Kind::InvalidFile,
+        Kind::InvalidArgument,
+        Kind::InvalidRequest,
+        Kind::ProblemsFound,
+        Kind::DBus,
+        Kind::Http,
+        Kind::Uinput,
+    ];
+
+    /// Returns the identifier of this kind.
+    pub fn name(&self) -> &str {
+        match self {
+            Kind::Display(kind) => kind.name(),
+            Kind::Io => "io",
+            Kind::InvalidFile => "invalid-file",
+            Kind::InvalidArgument => "invalid-argument",
+            Kind::InvalidRequest => "invalid-request",
+            Kind::ProblemsFound => "problems-found",
+            Kind::DBus => "dbus",
+            Kind::Http => "http",
+            Kind::Uinput => "uinput",
+            Kind::Other(name) => name,
+        }
+    }
+
+    /// Reads an identifier back, e.g. one relayed by the daemon.
+    pub fn from_name(name: &str) -> Self {
+        if let Some(kind) = ErrorKind::from_name(name) {
+            return Kind::Display(kind);
+        }
+        Self::OWN
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .unwrap_or_else(|| Kind::Other(name.to_string()))
+    }
+
+    /// The exit status of the tool when failing with this kind of error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Kind::Display(ErrorKind::NotConnected | ErrorKind::NoTouchInterface) => {
+                EXIT_NOT_CONNECTED
+            }
+            Kind::Display(ErrorKind::AccessDenied) => EXIT_ACCESS_DENIED,
+            Kind::Display(ErrorKind::HidInit) => EXIT_HID_INIT,
+            Kind::Display(ErrorKind::ShortWrite) => EXIT_SHORT_WRITE,
+            Kind::Display(ErrorKind::WriteFailed) => EXIT_WRITE_FAILED,
+            Kind::Display(ErrorKind::InvalidBrightness)
+            | Kind::InvalidArgument
+            | Kind::InvalidFile
+            | Kind::InvalidRequest => EXIT_INVALID_ARGUMENT,
+            Kind::Display(
+                ErrorKind::Device
+                | ErrorKind::ReadFailed
+                | ErrorKind::InvalidResponse
+                | ErrorKind::InvalidDescriptor,
+            )
+            | Kind::Io
+            | Kind::ProblemsFound
+            | Kind::DBus
+            | Kind::Http
+            | Kind::Uinput
+            | Kind::Other(_) => EXIT_FAILURE,
+        }
+    }
+}
+
+impl From<&ws170120::Error> for Kind {
+    fn from(e: &ws170120::Error) -> Self {
+        Kind::Display(e.kind())
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl CliError {
+    /// The kind of error, e.g. [`Kind::NotConnected`].
+    pub fn kind(&self) -> Kind {
+        match self {
+            CliError::Display(e) => e.into(),
+            CliError::Daemon { kind, .. } => kind.clone(),
+            CliError::Io(_) => Kind::Io,
+            CliError::InvalidFile { .. } => Kind::InvalidFile,
+            CliError::Install { source, .. }
+                if source.kind() == io::ErrorKind::PermissionDenied =>
+            {
+                Kind::Display(ErrorKind::AccessDenied)
+            }
+            CliError::Install { .. } => Kind::Io,
+            CliError::InvalidArgument(_) => Kind::InvalidArgument,
+            CliError::ProblemsFound(_) => Kind::ProblemsFound,
+            #[cfg(feature = "dbus")]
+            CliError::DBus(_) => Kind::DBus,
+            #[cfg(any(feature = "http", feature = "metrics"))]
+            CliError::Http(_) => Kind::Http,
+            #[cfg(all(target_os = "linux", feature = "uinput"))]
+            CliError::Uinput(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                Kind::Display(ErrorKind::AccessDenied)
+            }
+            #[cfg(all(target_os = "linux", feature = "uinput"))]
+            CliError::Uinput(_) => Kind::Uinput,
+        }
+    }
+
+    /// The exit status of the tool when failing with this error.
+    ///
+    /// Errors relayed by the daemon map to the same status as if the display
+    /// had been used directly.
+    pub fn exit_code(&self) -> i32 {
+        self.kind().exit_code()
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Display(e) => write!(f, "{e}"),
+            CliError::Daemon { message, .. } => write!(f, "{message}"),
+            CliError::Io(e) => write!(f, "Control socket error: {e}"),
+            CliError::InvalidFile { path, message } => {
+                write!(f, "Invalid file {}: {message}", path.display())
+            }
+            CliError::Install { path, source } => {
+                write!(f, "Writing {} failed: {source}", path.display())?;
+                if source.kind() == io::ErrorKind::PermissionDenied {
+                    write!(f, ". Try running with elevated privileges (sudo).")?;
+                }
+                Ok(())
+            }
+            CliError::InvalidArgument(message) => write!(f, "{message}"),
+            CliError::ProblemsFound(1) => write!(f, "Found 1 problem."),
+            CliError::ProblemsFound(count) => write!(f, "Found {count} problems."),
+            #[cfg(feature = "dbus")]
+            CliError::DBus(e) => write!(f, "D-Bus error: {e}"),
+            #[cfg(any(feature = "http", feature = "metrics"))]
+            CliError::Http(e) => write!(f, "HTTP server error: {e}"),
+            #[cfg(all(target_os = "linux", feature = "uinput"))]
+            CliError::Uinput(e) => {
+                write!(f, "uinput error: {e}")?;
+                if e.kind() == io::ErrorKind::PermissionDenied {
+                    write!(f, ". Access to /dev/uinput is needed; try running as root.")?;
+                } else if e.kind() == io::ErrorKind::NotFound {
+                    write!(f, ". Is the uinput kernel module loaded (modprobe uinput)?")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for CliError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CliError::Display(e) => Some(e),
+            CliError::Io(e) | CliError::Install { source: e, .. } => Some(e),
+            #[cfg(any(feature = "http", feature = "metrics"))]
+            CliError::Http(e) => Some(e.as_ref()),
+            #[cfg(all(target_os = "linux", feature = "uinput"))]
+            CliError::Uinput(e) => Some(e),
+            #[cfg(feature = "dbus")]
+            CliError::DBus(e) => Some(e),
+            CliError::Daemon { .. }
+            | CliError::InvalidFile { .. }
+            | CliError::InvalidArgument(_)
+            | CliError::ProblemsFound(_) => None,
+        }
+    }
+}
+
+impl From<ws170120::Error> for CliError {
+    fn from(e: ws170120::Error) -> Self {
+        CliError::Display(e)
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(e: io::Error) -> Self {
+        CliError::Io(e)
+    }
+}
+
+#[cfg(feature = "dbus")]
+impl From<zbus::Error> for CliError {
+    fn from(e: zbus::Error) -> Self {
+        CliError::DBus(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hidapi::HidError;
+    use ws170120::Error;
+
+    fn hid_error() -> HidError {
+        HidError::HidApiError {
+            message: "failed".to_string(),
+        }
+    }
+
+    fn io_error(kind: io::ErrorKind) -> io::Error {
+        io::Error::new(kind, "failed")
+    }
+
+    /// Every error the tool can fail with, and its exit status.
+    fn cases() -> Vec<(CliError, i32)> {
+        // Only extended below when built with some of the features
+        #[allow(unused_mut)]
+        let mut cases = vec![
+            (Error::HidInit(hid_error()).into(), EXIT_HID_INIT),
+            (Error::NotConnected.into(), EXIT_NOT_CONNECTED),
+            (
+                Error::AccessDenied {
+                    context: "open".to_string(),
+                    source: hid_error(),
+                }
+                .into(),
+                EXIT_ACCESS_DENIED,
+            ),
+            (
+                Error::Device {
+                    context: "open".to_string(),
+                    source: hid_error(),
+                }
+                .into(),
+                EXIT_FAILURE,
+            ),
+            (Error::InvalidBrightness(101).into(), EXIT_INVALID_ARGUMENT),
+            (
+                Error::ShortWrite {
+                    written: 3,
+                    expected: 8,
+                }
+                .into(),
+                EXIT_SHORT_WRITE,
+            ),
+            (
+                Error::WriteFailed {
+                    output: hid_error(),
+                    feature: hid_error(),
+                }
+                .into(),
+                EXIT_WRITE_FAILED,
+            ),
+            (Error::ReadFailed(hid_error()).into(), EXIT_FAILURE),
+            (Error::InvalidResponse(vec![0]).into(), EXIT_FAILURE),
+            (Error::NoTouchInterface.into(), EXIT_NOT_CONNECTED),
+            (
+                Error::InvalidDescriptor {
+                    offset: 0,
+                    message: "truncated".to_string(),
+                }
+                .into(),
+                EXIT_FAILURE,
+            ),
+            (
+                CliError::Daemon {
+                    kind: Kind::InvalidRequest,
+                    message: "empty request".to_string(),
+                },
+                EXIT_INVALID_ARGUMENT,
+            ),
+            (
+                CliError::Daemon {
+                    kind: Kind::from_name("from-the-future"),
+                    message: "failed".to_string(),
+                },
+                EXIT_FAILURE,
+            ),
+            (
+                io_error(io::ErrorKind::ConnectionRefused).into(),
+                EXIT_FAILURE,
+            ),
+            (
+                CliError::InvalidFile {
+                    path: PathBuf::from("schedule.toml"),
+                    message: "failed".to_string(),
+                },
+                EXIT_INVALID_ARGUMENT,
+            ),
+            (
+                CliError::Install {
+                    path: PathBuf::from("/etc/udev/rules.d"),
+                    source: io_error(io::ErrorKind::PermissionDenied),
+                },
+                EXIT_ACCESS_DENIED,
+            ),
+            (
+                CliError::Install {
+                    path: PathBuf::from("/etc/udev/rules.d"),
+                    source: io_error(io::ErrorKind::NotFound),
+                },
+                EXIT_FAILURE,
+            ),
+            (
+                CliError::InvalidArgument("failed".to_string()),
+                EXIT_INVALID_ARGUMENT,
+            ),
+            (CliError::ProblemsFound(2), EXIT_FAILURE),
+        ];
+        #[cfg(feature = "dbus")]
+        cases.push((CliError::DBus(zbus::Error::Unsupported), EXIT_FAILURE));
+        #[cfg(any(feature = "http", feature = "metrics"))]
+        cases.push((CliError::Http("failed".into()), EXIT_FAILURE));
+        #[cfg(all(target_os = "linux", feature = "uinput"))]
+        cases.extend([
+            (
+                CliError::Uinput(io_error(io::ErrorKind::PermissionDenied)),
+                EXIT_ACCESS_DENIED,
+            ),
+            (
+                CliError::Uinput(io_error(io::ErrorKind::NotFound)),
+                EXIT_FAILURE,
+            ),
+        ]);
+        cases
+    }
+
+    #[test]
+    fn exit_codes() {
+        for (e, code) in cases() {
+            assert_eq!(e.exit_code(), code, "{e:?}");
+        }
+    }
+
+    #[test]
+    fn relayed_errors_keep_their_exit_code() {
+        for (e, code) in cases() {
+            let relayed = CliError::Daemon {
+                kind: Kind::from_name(&e.kind().to_string()),
+                message: e.to_string(),
+            };
+            assert_eq!(relayed.exit_code(), code, "{e:?}");
+        }
+    }
+
+    #[test]
+    fn reads_names_back() {
+        for (e, _) in cases() {
+            let kind = e.kind();
+            assert_eq!(Kind::from_name(kind.name()), kind);
+        }
+        assert_eq!(
+            Kind::from_name("from-the-future"),
+            Kind::Other("from-the-future".to_string())
+        );
+    }
+}
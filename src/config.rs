@@ -0,0 +1,766 @@
+//! Settings read from the configuration file.
+//!
+//! ```toml
+//! fade = "500ms"
+//! curve = "perceptual"
+//! verbose = 1
+//!
+//! [presets]
+//! day = 90
+//! night = 20
+//!
+//! [devices.lobby]
+//! serial = "WS0001"
+//!
+//! [touch]
+//! transform = [0.0, 1.0, 0.0, -1.0, 0.0, 4095.0]
+//!
+//! [idle]
+//! after = "10m"
+//! level = 5
+//! inputs = ["/dev/input/by-id/usb-Logitech_USB_Keyboard-event-kbd"]
+//!
+//! [auto]
+//! sensor = "/sys/bus/iio/devices/iio:device0"
+//! points = [[0, 10], [100, 40], [1000, 80], [10000, 100]]
+//! hysteresis = 5
+//! rate = 2
+//!
+//! [mapping]
+//! curve = "perceptual"
+//! ```
+
+use crate::auto::Source;
+use crate::cli_error::CliError;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, fs, io};
+use ws170120::touch::Transform;
+use ws170120::{Curve, LuxCurve, LuxPoint, Mapping, Selector, MAX_BRIGHTNESS};
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+const DEFAULT_GAMMA: f64 = 2.2;
+
+/// The contents of the configuration file. Every setting is optional.
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Fade duration used when `--fade` is not given.
+    pub fade: Option<Duration>,
+    /// Easing curve used when `--curve` is not given.
+    pub curve: Option<Curve>,
+    /// Verbosity used when no `-v` is given.
+    pub verbose: u8,
+    /// Named brightness levels, e.g. `night = 20`.
+    pub presets: BTreeMap<String, u8>,
+    /// Names for displays, usable with `--device`.
+    pub devices: BTreeMap<String, Selector>,
+    /// Maps raw touch coordinates onto the picture, see `touch calibrate`.
+    pub touch_transform: Option<Transform>,
+    /// Defaults for the `idle` command.
+    pub idle: IdleConfig,
+    /// Defaults for the `auto` command.
+    pub auto: AutoConfig,
+    /// Translates percentages into the values written to the displays.
+    pub mapping: Mapping,
+}
+
+/// The `[idle]` table of the configuration file.
+#[derive(Debug, Default)]
+pub struct IdleConfig {
+    /// Time without activity before dimming.
+    pub after: Option<Duration>,
+    /// Brightness while idle.
+    pub level: Option<u8>,
+    /// Input devices whose events count as activity, besides the touch screen.
+    pub inputs: Vec<PathBuf>,
+}
+
+/// The `[auto]` table of the configuration file.
+#[derive(Debug, Default)]
+pub struct AutoConfig {
+    /// Where the illuminance is read from.
+    pub source: Option<Source>,
+    /// Maps the illuminance to a brightness.
+    pub curve: Option<LuxCurve>,
+    /// Time between readings.
+    pub interval: Option<Duration>,
+    /// Smallest change in percentage points that is followed.
+    pub hysteresis: Option<u8>,
+    /// Largest change in percentage points per second.
+    pub rate: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    fade: Option<String>,
+    curve: Option<String>,
+    #[serde(default)]
+    verbose: u8,
+    #[serde(default)]
+    presets: BTreeMap<String, u8>,
+    #[serde(default)]
+    devices: BTreeMap<String, DeviceFile>,
+    #[serde(default)]
+    touch: TouchFile,
+    #[serde(default)]
+    idle: IdleFile,
+    #[serde(default)]
+    auto: AutoFile,
+    #[serde(default)]
+    mapping: MappingFile,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeviceFile {
+    serial: Option<String>,
+    path: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct TouchFile {
+    transform: Option<[f64; 6]>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct IdleFile {
+    after: Option<String>,
+    level: Option<u8>,
+    #[serde(default)]
+    inputs: Vec<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct AutoFile {
+    sensor: Option<PathBuf>,
+    command: Option<String>,
+    file: Option<PathBuf>,
+    points: Option<Vec<(f64, u8)>>,
+    interval: Option<String>,
+    hysteresis: Option<u8>,
+    rate: Option<f64>,
+}
+
+impl AutoFile {
+    fn into_config(self) -> Result<AutoConfig, String> {
+        let source = match (self.sensor, self.command, self.file) {
+            (Some(path), None, None) => Some(Source::Sensor(path)),
+            (None, Some(command), None) => Some(Source::Command(command)),
+            (None, None, Some(path)) => Some(Source::File(path)),
+            (None, None, None) => None,
+            _ => return Err("auto needs only one of sensor, command and file".to_string()),
+        };
+        let curve = self
+            .points
+            .map(|points| {
+                let points = points
+                    .into_iter()
+                    .map(|(lux, brightness)| LuxPoint { lux, brightness })
+                    .collect();
+                LuxCurve::new(points).map_err(|e| format!("invalid auto points: {e}"))
+            })
+            .transpose()?;
+        let interval = self
+            .interval
+            .map(|interval| {
+                humantime::parse_duration(&interval)
+                    .map_err(|e| format!("invalid auto interval '{interval}': {e}"))
+            })
+            .transpose()?;
+        if let Some(hysteresis) = self
+            .hysteresis
+            .filter(|hysteresis| *hysteresis > MAX_BRIGHTNESS)
+        {
+            return Err(format!(
+                "auto hysteresis {hysteresis} is invalid, expected a value between 0 and {MAX_BRIGHTNESS}"
+            ));
+        }
+        if let Some(rate) = self.rate.filter(|rate| !rate.is_finite() || *rate < 0.0) {
+            return Err(format!(
+                "auto rate {rate} is invalid, expected a value of at least 0"
+            ));
+        }
+        Ok(AutoConfig {
+            source,
+            curve,
+            interval,
+            hysteresis: self.hysteresis,
+            rate: self.rate,
+        })
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct MappingFile {
+    curve: Option<String>,
+    gamma: Option<f64>,
+    max: Option<u8>,
+    table: Option<Vec<(u8, u8)>>,
+    nits: Option<Vec<(u8, f64)>>,
+}
+
+impl MappingFile {
+    fn into_mapping(self) -> Result<Mapping, String> {
+        let curve = self.curve.as_deref();
+        if self.gamma.is_some() && curve != Some("gamma") {
+            return Err("mapping gamma needs curve = \"gamma\"".to_string());
+        }
+        if self.max.is_some() && !matches!(curve, Some("gamma" | "perceptual")) {
+            return Err("mapping max needs curve = \"gamma\" or \"perceptual\"".to_string());
+        }
+        let mapping = match (curve, self.table, self.nits) {
+            (None | Some("linear"), None, None) => Ok(Mapping::linear()),
+            (Some("perceptual"), None, None) => {
+                Ok(Mapping::perceptual(self.max.unwrap_or(MAX_BRIGHTNESS)))
+            }
+            (Some("gamma"), None, None) => Mapping::gamma(
+                self.gamma.unwrap_or(DEFAULT_GAMMA),
+                self.max.unwrap_or(MAX_BRIGHTNESS),
+            ),
+            (Some(curve), None, None) => {
+                return Err(format!(
+                    "unknown mapping curve '{curve}', expected linear, perceptual or gamma"
+                ))
+            }
+            (None, Some(table), None) => Mapping::from_table(&table),
+            (None, None, Some(nits)) => Mapping::from_nits(&nits),
+            _ => return Err("mapping needs only one of curve, table and nits".to_string()),
+        };
+        mapping.map_err(|e| format!("invalid mapping: {e}"))
+    }
+}
+
+impl ConfigFile {
+    fn into_config(self) -> Result<Config, String> {
+        let fade = self
+            .fade
+            .map(|fade| {
+                humantime::parse_duration(&fade).map_err(|e| format!("invalid fade '{fade}': {e}"))
+            })
+            .transpose()?;
+        let curve = self.curve.map(|curve| curve.parse()).transpose()?;
+        if let Some((name, brightness)) = self
+            .presets
+            .iter()
+            .find(|(_, brightness)| **brightness > MAX_BRIGHTNESS)
+        {
+            return Err(format!(
+                "preset '{name}' has brightness {brightness}, expected a value between 0 and {MAX_BRIGHTNESS}"
+            ));
+        }
+        let devices = self
+            .devices
+            .into_iter()
+            .map(|(name, device)| {
+                let selector = match (device.serial, device.path) {
+                    (Some(serial), None) => Selector::Serial(serial),
+                    (None, Some(path)) => Selector::Path(path),
+                    _ => return Err(format!("device '{name}' needs either a serial or a path")),
+                };
+                Ok((name, selector))
+            })
+            .collect::<Result<_, String>>()?;
+        let idle_after = self
+            .idle
+            .after
+            .map(|after| {
+                humantime::parse_duration(&after)
+                    .map_err(|e| format!("invalid idle time '{after}': {e}"))
+            })
+            .transpose()?;
+        if let Some(level) = self.idle.level.filter(|level| *level > MAX_BRIGHTNESS) {
+            return Err(format!(
+                "idle level {level} is invalid, expected a value between 0 and {MAX_BRIGHTNESS}"
+            ));
+        }
+        if let Some(transform) = self.touch.transform {
+            if transform.iter().any(|value| !value.is_finite()) {
+                return Err("touch transform must only contain finite numbers".to_string());
+            }
+        }
+        Ok(Config {
+            fade,
+            curve,
+            verbose: self.verbose,
+            presets: self.presets,
+            devices,
+            touch_transform: self.touch.transform.map(Transform),
+            idle: IdleConfig {
+                after: idle_after,
+                level: self.idle.level,
+                inputs: self.idle.inputs,
+            },
+            auto: self.auto.into_config()?,
+            mapping: self.mapping.into_mapping()?,
+        })
+    }
+}
+
+impl Config {
+    /// Returns the brightness of the preset called `name`.
+    pub fn preset(&self, name: &str) -> Result<u8, CliError> {
+        self.presets
+            .get(name)
+            .copied()
+            .ok_or_else(|| unknown("preset", name, self.presets.keys()))
+    }
+
+    /// Returns the selector of the display called `name`.
+    pub fn device(&self, name: &str) -> Result<Selector, CliError> {
+        self.devices
+            .get(name)
+            .cloned()
+            .ok_or_else(|| unknown("device", name, self.devices.keys()))
+    }
+}
+
+fn unknown<'a>(what: &str, name: &str, known: impl Iterator<Item = &'a String>) -> CliError {
+    let known: Vec<&str> = known.map(String::as_str).collect();
+    let hint = if known.is_empty() {
+        format!("no {what}s are configured")
+    } else {
+        format!("configured {what}s: {}", known.join(", "))
+    };
+    CliError::InvalidArgument(format!("Unknown {what} '{name}', {hint}."))
+}
+
+/// Returns `$XDG_CONFIG_HOME/ws170120-ctl/config.toml`, falling back to
+/// `~/.config` when `XDG_CONFIG_HOME` is not set.
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(
+        config_home
+            .join(env!("CARGO_PKG_NAME"))
+            .join(CONFIG_FILE_NAME),
+    )
+}
+
+/// Loads the configuration from `path`, or from [`default_path`] if none is
+/// given. A missing default file is the same as an empty one.
+pub fn load(path: Option<&Path>) -> Result<Config, CliError> {
+    let (path, required) = match path {
+        Some(path) => (path.to_path_buf(), true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config::default()),
+        },
+    };
+    let invalid = |message: String| CliError::InvalidFile {
+        path: path.clone(),
+        message,
+    };
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => return Ok(Config::default()),
+        Err(e) => return Err(invalid(e.to_string())),
+    };
+    let file: ConfigFile = toml::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+    file.into_config().map_err(invalid)
+}
+
+/// Stores `transform` as the touch transform in the configuration file at
+/// `path`, or at [`default_path`] if none is given, keeping everything else
+/// in it as it is. Returns the path written.
+pub fn save_touch_transform(
+    path: Option<&Path>,
+    transform: &Transform,
+) -> Result<PathBuf, CliError> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => default_path().ok_or_else(|| {
+            CliError::InvalidArgument(
+                "No configuration file location is known, use --config.".to_string(),
+            )
+        })?,
+    };
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(CliError::InvalidFile {
+                path,
+                message: e.to_string(),
+            })
+        }
+    };
+    let mut document: toml_edit::DocumentMut =
+        contents
+            .parse()
+            .map_err(|e: toml_edit::TomlError| CliError::InvalidFile {
+                path: path.clone(),
+                message: e.to_string(),
+            })?;
+    let touch = document
+        .entry("touch")
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+        .ok_or_else(|| CliError::InvalidFile {
+            path: path.clone(),
+            message: "touch is not a table".to_string(),
+        })?;
+    let transform =
+        toml_edit::Value::from(transform.0.iter().copied().collect::<toml_edit::Array>());
+    match touch
+        .get_mut("transform")
+        .and_then(toml_edit::Item::as_value_mut)
+    {
+        // Replacing only the value keeps the comments around it
+        Some(value) => {
+            let decor = value.decor().clone();
+            *value = transform;
+            *value.decor_mut() = decor;
+        }
+        None => {
+            touch.insert("transform", toml_edit::Item::Value(transform));
+        }
+    }
+
+    let write = || -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, document.to_string())
+    };
+    write().map_err(|source| CliError::Install {
+        path: path.clone(),
+        source,
+    })?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn parse(contents: &str) -> Result<Config, String> {
+        toml::from_str::<ConfigFile>(contents)
+            .map_err(|e| e.to_string())?
+            .into_config()
+    }
+
+    fn assert_invalid(contents: &str, error: &str) {
+        match parse(contents) {
+            Ok(_) => panic!("{contents:?} was accepted"),
+            Err(message) => assert!(message.contains(error), "{contents:?} gave {message:?}"),
+        }
+    }
+
+    #[test]
+    fn reads_every_setting() {
+        let config = parse(
+            r#"
+            fade = "500ms"
+            curve = "perceptual"
+            verbose = 1
+
+            [presets]
+            day = 90
+            night = 20
+
+            [devices.lobby]
+            serial = "WS0001"
+
+            [devices.desk]
+            path = "/dev/hidraw3"
+
+            [touch]
+            transform = [0.0, 1.0, 0.0, -1.0, 0.0, 4095.0]
+
+            [idle]
+            after = "10m"
+            level = 5
+            inputs = ["/dev/input/event3"]
+
+            [auto]
+            command = "read-lux"
+            points = [[0, 10], [1000, 80]]
+            interval = "2s"
+            hysteresis = 5
+            rate = 2.5
+
+            [mapping]
+            curve = "gamma"
+            gamma = 2.0
+            max = 80
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.fade, Some(Duration::from_millis(500)));
+        assert_eq!(config.curve, Some(Curve::Perceptual));
+        assert_eq!(config.verbose, 1);
+        assert_eq!(config.preset("night").unwrap(), 20);
+        assert_eq!(
+            config.device("lobby").unwrap(),
+            Selector::Serial("WS0001".to_string())
+        );
+        assert_eq!(
+            config.device("desk").unwrap(),
+            Selector::Path("/dev/hidraw3".to_string())
+        );
+        assert_eq!(
+            config.touch_transform,
+            Some(Transform([0.0, 1.0, 0.0, -1.0, 0.0, 4095.0]))
+        );
+        assert_eq!(config.idle.after, Some(Duration::from_secs(600)));
+        assert_eq!(config.idle.level, Some(5));
+        assert_eq!(config.idle.inputs, [PathBuf::from("/dev/input/event3")]);
+        assert_eq!(
+            config.auto.source,
+            Some(Source::Command("read-lux".to_string()))
+        );
+        assert_eq!(config.auto.curve.unwrap().brightness(500.0), 45);
+        assert_eq!(config.auto.interval, Some(Duration::from_secs(2)));
+        assert_eq!(config.auto.hysteresis, Some(5));
+        assert_eq!(config.auto.rate, Some(2.5));
+        assert_eq!(config.mapping, Mapping::gamma(2.0, 80).unwrap());
+    }
+
+    #[test]
+    fn defaults_to_nothing_set() {
+        let config = parse("").unwrap();
+        assert_eq!(config.fade, None);
+        assert_eq!(config.curve, None);
+        assert_eq!(config.verbose, 0);
+        assert!(config.presets.is_empty() && config.devices.is_empty());
+        assert_eq!(config.touch_transform, None);
+        assert_eq!(config.auto.source, None);
+        assert_eq!(config.mapping, Mapping::linear());
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        for (contents, key) in [
+            ("brightness = 50", "brightness"),
+            ("[devices.lobby]\nserial = \"1\"\nname = \"x\"", "name"),
+            ("[touch]\nmatrix = [1]", "matrix"),
+            ("[idle]\ndelay = \"1m\"", "delay"),
+            ("[auto]\nlux = 10", "lux"),
+            ("[mapping]\nexponent = 2.0", "exponent"),
+        ] {
+            assert_invalid(contents, &format!("unknown field `{key}`"));
+        }
+    }
+
+    #[test]
+    fn checks_presets_and_devices() {
+        assert_invalid("[presets]\nday = 101", "preset 'day' has brightness 101");
+        assert_invalid("[presets]\nday = -1", "expected u8");
+        assert_invalid(
+            "[devices.lobby]",
+            "device 'lobby' needs either a serial or a path",
+        );
+        assert_invalid(
+            "[devices.lobby]\nserial = \"1\"\npath = \"/dev/hidraw3\"",
+            "device 'lobby' needs either",
+        );
+    }
+
+    #[test]
+    fn checks_the_other_settings() {
+        for (contents, error) in [
+            ("fade = \"slowly\"", "invalid fade 'slowly'"),
+            ("curve = \"wobbly\"", "unknown curve 'wobbly'"),
+            ("[idle]\nafter = \"later\"", "invalid idle time 'later'"),
+            ("[idle]\nlevel = 101", "idle level 101 is invalid"),
+            ("[touch]\ntransform = [1.0, 0.0, 0.0]", "transform"),
+            (
+                "[touch]\ntransform = [1.0, 0.0, nan, 0.0, 1.0, 0.0]",
+                "finite",
+            ),
+            (
+                "[touch]\ntransform = [1.0, 0.0, inf, 0.0, 1.0, 0.0]",
+                "finite",
+            ),
+        ] {
+            assert_invalid(contents, error);
+        }
+    }
+
+    #[test]
+    fn checks_the_mapping() {
+        assert_eq!(
+            parse("[mapping]\ncurve = \"perceptual\"").unwrap().mapping,
+            Mapping::perceptual(MAX_BRIGHTNESS)
+        );
+        assert_eq!(
+            parse("[mapping]\ntable = [[0, 0], [100, 80]]")
+                .unwrap()
+                .mapping,
+            Mapping::from_table(&[(0, 0), (100, 80)]).unwrap()
+        );
+        for (contents, error) in [
+            (
+                "[mapping]\ncurve = \"cubic\"",
+                "unknown mapping curve 'cubic'",
+            ),
+            ("[mapping]\ngamma = 2.0", "gamma needs curve = \"gamma\""),
+            ("[mapping]\ncurve = \"linear\"\nmax = 80", "max needs curve"),
+            (
+                "[mapping]\ncurve = \"gamma\"\ntable = [[0, 0], [100, 80]]",
+                "only one of curve, table and nits",
+            ),
+            (
+                "[mapping]\ntable = [[0, 0]]\nnits = [[0, 1.0], [100, 300.0]]",
+                "only one of curve, table and nits",
+            ),
+            (
+                "[mapping]\ncurve = \"gamma\"\ngamma = -1.0",
+                "invalid mapping",
+            ),
+            ("[mapping]\ntable = [[50, 10], [20, 30]]", "invalid mapping"),
+            ("[mapping]\ntable = []", "invalid mapping"),
+        ] {
+            assert_invalid(contents, error);
+        }
+    }
+
+    #[test]
+    fn checks_auto() {
+        assert_eq!(
+            parse("[auto]\nsensor = \"/sys/bus/iio/devices/iio:device0\"")
+                .unwrap()
+                .auto
+                .source,
+            Some(Source::Sensor(PathBuf::from(
+                "/sys/bus/iio/devices/iio:device0"
+            )))
+        );
+        assert_eq!(
+            parse("[auto]\nfile = \"/run/lux\"").unwrap().auto.source,
+            Some(Source::File(PathBuf::from("/run/lux")))
+        );
+        for (contents, error) in [
+            (
+                "[auto]\nsensor = \"/sys\"\ncommand = \"lux\"",
+                "only one of sensor, command and file",
+            ),
+            (
+                "[auto]\ncommand = \"lux\"\nfile = \"/run/lux\"",
+                "only one of sensor, command and file",
+            ),
+            ("[auto]\npoints = []", "invalid auto points"),
+            (
+                "[auto]\npoints = [[10, 20], [5, 30]]",
+                "invalid auto points",
+            ),
+            ("[auto]\npoints = [[10, 101]]", "invalid auto points"),
+            (
+                "[auto]\ninterval = \"often\"",
+                "invalid auto interval 'often'",
+            ),
+            ("[auto]\nhysteresis = 101", "auto hysteresis 101 is invalid"),
+            ("[auto]\nrate = -1.0", "auto rate -1 is invalid"),
+            ("[auto]\nrate = inf", "auto rate inf is invalid"),
+        ] {
+            assert_invalid(contents, error);
+        }
+    }
+
+    #[test]
+    fn names_unknown_presets_and_devices() {
+        let config = parse("[presets]\nday = 90\nnight = 20").unwrap();
+        let message = |error: CliError| match error {
+            CliError::InvalidArgument(message) => message,
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(
+            message(config.preset("dusk").unwrap_err()),
+            "Unknown preset 'dusk', configured presets: day, night."
+        );
+        assert_eq!(
+            message(config.device("lobby").unwrap_err()),
+            "Unknown device 'lobby', no devices are configured."
+        );
+    }
+
+    #[test]
+    fn loads_files() {
+        let dir = TestDir::new("config");
+        let path = dir.write("config.toml", "[presets]\nday = 90\n");
+        assert_eq!(load(Some(&path)).unwrap().preset("day").unwrap(), 90);
+
+        let invalid = dir.write("invalid.toml", "[presets]\nday = 101\n");
+        let missing = dir.join("missing.toml");
+        for path in [invalid, missing] {
+            match load(Some(&path)) {
+                Err(CliError::InvalidFile { path: reported, .. }) => assert_eq!(reported, path),
+                other => panic!("{path:?} gave {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn saves_the_touch_transform_keeping_the_rest() {
+        let dir = TestDir::new("config-save");
+        let original = "\
+# Settings for the lobby display
+fade = \"500ms\" # quick
+
+[presets]
+day = 90 # bright
+night = 20
+
+[touch]
+# Calibrated before the panel was turned
+transform = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0] # identity
+
+[idle]
+after = \"10m\"
+";
+        let path = dir.write("config.toml", original);
+        let transform = Transform([0.0, 1.0, 0.0, -1.0, 0.0, 4095.0]);
+        assert_eq!(save_touch_transform(Some(&path), &transform).unwrap(), path);
+
+        let saved = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            saved,
+            original.replace(
+                "transform = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0]",
+                "transform = [0.0, 1.0, 0.0, -1.0, 0.0, 4095.0]"
+            )
+        );
+        let config = load(Some(&path)).unwrap();
+        assert_eq!(config.touch_transform, Some(transform));
+        assert_eq!(config.preset("day").unwrap(), 90);
+        assert_eq!(config.idle.after, Some(Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn saves_the_touch_transform_to_new_files() {
+        let dir = TestDir::new("config-new");
+        let transform = Transform([-1.0, 0.0, 4095.0, 0.0, 1.0, 0.0]);
+
+        let path = dir.join("ws170120-ctl/config.toml");
+        save_touch_transform(Some(&path), &transform).unwrap();
+        assert_eq!(load(Some(&path)).unwrap().touch_transform, Some(transform));
+
+        let path = dir.write("presets.toml", "[presets]\nday = 90\n");
+        save_touch_transform(Some(&path), &transform).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[presets]\nday = 90\n\n[touch]\ntransform = [-1.0, 0.0, 4095.0, 0.0, 1.0, 0.0]\n"
+        );
+
+        let path = dir.write("broken.toml", "[touch\n");
+        assert!(matches!(
+            save_touch_transform(Some(&path), &transform),
+            Err(CliError::InvalidFile { .. })
+        ));
+        let path = dir.write("flat.toml", "touch = 1\n");
+        assert!(matches!(
+            save_touch_transform(Some(&path), &transform),
+            Err(CliError::InvalidFile { .. })
+        ));
+    }
+}
@@ -0,0 +1,181 @@
+//! Keeps the displays open and serves requests on a Unix domain socket.
+
+use crate::cli_error::{CliError, Kind};
+use crate::config::Config;
+use crate::panels::Panels;
+use crate::protocol::{self, Response};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{fs, thread};
+
+/// How often the bus is scanned for displays that were plugged or unplugged.
+const HOTPLUG_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(clap::Args)]
+pub struct DaemonArgs {
+    /// Also offer the brightness as a D-Bus service on this bus
+    #[cfg(feature = "dbus")]
+    #[arg(long, value_enum)]
+    dbus: Option<crate::dbus::Bus>,
+
+    #[cfg(feature = "mqtt")]
+    #[command(flatten)]
+    mqtt: crate::mqtt::MqttArgs,
+
+    #[cfg(feature = "http")]
+    #[command(flatten)]
+    http: crate::http::HttpArgs,
+
+    #[cfg(feature = "metrics")]
+    #[command(flatten)]
+    metrics: crate::metrics::MetricsArgs,
+}
+
+pub fn run(socket: &Path, args: &DaemonArgs, config: &Config, verbose: u8) -> Result<(), CliError> {
+    let mut panels = Panels::new()?;
+    panels.set_mapping(config.mapping.clone());
+    let panels = Arc::new(Mutex::new(panels));
+    connect(&panels, verbose);
+
+    #[cfg(feature = "dbus")]
+    let _dbus = match args.dbus {
+        Some(bus) => {
+            let connection = crate::dbus::serve(Arc::clone(&panels), bus)?;
+            if verbose > 0 {
+                println!("Registered {} on the {:?} bus.", crate::dbus::BUS_NAME, bus);
+            }
+            Some(connection)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "dbus"))]
+    let _ = args;
+
+    #[cfg(feature = "mqtt")]
+    crate::mqtt::serve(Arc::clone(&panels), &args.mqtt, verbose);
+    #[cfg(feature = "http")]
+    crate::http::start(Arc::clone(&panels), &args.http, verbose)?;
+    #[cfg(feature = "metrics")]
+    crate::metrics::start(panels.lock().unwrap().metrics(), &args.metrics, verbose)?;
+
+    let listener = bind(socket)?;
+    if verbose > 0 {
+        println!("Listening on {}.", socket.display());
+    }
+
+    if verbose > 0 {
+        let changes = panels.lock().unwrap().subscribe();
+        thread::spawn(move || {
+            for change in changes {
+                println!(
+                    "Brightness of {} set to {}%.",
+                    change.display.label(),
+                    change.brightness
+                );
+            }
+        });
+    }
+
+    let hotplug = Arc::clone(&panels);
+    thread::spawn(move || loop {
+        thread::sleep(HOTPLUG_INTERVAL);
+        connect(&hotplug, verbose);
+    });
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Accepting connection failed: {}", e);
+                continue;
+            }
+        };
+        let panels = Arc::clone(&panels);
+        thread::spawn(move || {
+            if let Err(e) = serve(stream, &panels, verbose) {
+                if verbose > 0 {
+                    eprintln!("Connection failed: {}", e);
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Binds the control socket, replacing a stale one left by a previous run.
+fn bind(socket: &Path) -> io::Result<UnixListener> {
+    if socket.exists() {
+        if protocol::connect(socket).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("a daemon is already listening on {}", socket.display()),
+            ));
+        }
+        fs::remove_file(socket)?;
+    }
+    UnixListener::bind(socket)
+}
+
+/// Closes displays that were unplugged and opens newly attached ones.
+fn connect(panels: &Mutex<Panels>, verbose: u8) {
+    let mut panels = panels.lock().unwrap();
+    match panels.refresh() {
+        Ok(gone) => {
+            if verbose > 0 {
+                for info in gone {
+                    println!("Display {} disconnected.", info.label());
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    }
+    let (opened, failed) = panels.connect_all();
+    if verbose > 0 {
+        for info in opened {
+            println!("Display {} connected.", info.label());
+        }
+        for (info, e) in failed {
+            eprintln!("Display {}: {}", info.label(), e);
+        }
+    }
+}
+
+fn serve(stream: UnixStream, panels: &Mutex<Panels>, verbose: u8) -> io::Result<()> {
+    let mut writer = &stream;
+    for line in BufReader::new(&stream).lines() {
+        let line = line?;
+        if verbose > 1 {
+            println!("Request: {}", line);
+        }
+        let (selector, request) = match protocol::parse_request(&line) {
+            Ok(parsed) => parsed,
+            Err(message) => {
+                let e = CliError::Daemon {
+                    kind: Kind::InvalidRequest,
+                    message,
+                };
+                writeln!(writer, "{}", Response::Error(&e))?;
+                continue;
+            }
+        };
+
+        // Requests are carried out one at a time, including fades
+        let outcomes = panels.lock().unwrap().execute(&selector, &request);
+        match outcomes {
+            Ok(outcomes) => {
+                for outcome in &outcomes {
+                    writeln!(writer, "{}", Response::Outcome(outcome))?;
+                }
+                writeln!(writer, "{}", Response::Ok)?;
+            }
+            Err(e) => writeln!(writer, "{}", Response::Error(&e.into()))?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,301 @@
+//! Exposes the display brightness as a D-Bus object.
+//!
+//! The object controls the first attached display, like the command line
+//! does without a selector. Requests are carried out on a blocking thread,
+//! so that a fade waiting for the panels does not hold up the connection.
+
+use crate::panels::{Action, Panels, Request};
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use ws170120::{Curve, Fade, Selector};
+use zbus::blocking::connection::{Builder, Connection};
+use zbus::fdo;
+use zbus::zvariant::Value;
+
+pub const BUS_NAME: &str = "io.github.upachler.Ws170120";
+pub const OBJECT_PATH: &str = "/io/github/upachler/Ws170120";
+pub const INTERFACE_NAME: &str = "io.github.upachler.Ws170120.Display";
+
+/// The message bus to register the service on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Bus {
+    Session,
+    System,
+}
+
+struct DisplayObject {
+    panels: Arc<Mutex<Panels>>,
+    /// The brightness last announced in a `PropertiesChanged` signal.
+    announced: Arc<Mutex<Option<u8>>>,
+}
+
+impl DisplayObject {
+    async fn execute(&self, request: Request) -> fdo::Result<u8> {
+        let panels = Arc::clone(&self.panels);
+        blocking::unblock(move || execute(&panels, &request)).await
+    }
+}
+
+/// Carries out `request` on the first display and returns its brightness.
+fn execute(panels: &Mutex<Panels>, request: &Request) -> fdo::Result<u8> {
+    let outcomes = panels
+        .lock()
+        .unwrap()
+        .execute(&Selector::First, request)
+        .map_err(|e| fdo::Error::Failed(e.to_string()))?;
+    let outcome = outcomes
+        .into_iter()
+        .next()
+        .ok_or_else(|| fdo::Error::Failed("no display selected".to_string()))?;
+    outcome
+        .result
+        .map(|applied| applied.brightness)
+        .map_err(|e| fdo::Error::Failed(e.to_string()))
+}
+
+#[zbus::interface(name = "io.github.upachler.Ws170120.Display")]
+impl DisplayObject {
+    /// Brightness percentage (0-100)
+    #[zbus(property)]
+    async fn brightness(&self) -> fdo::Result<u8> {
+        self.execute(Request {
+            action: Action::Get,
+            fade: None,
+        })
+        .await
+    }
+
+    #[zbus(property)]
+    async fn set_brightness(&mut self, brightness: u8) -> fdo::Result<()> {
+        // zbus announces the new value itself once the setter returns
+        let previous = self.announced.lock().unwrap().replace(brightness);
+        let result = self
+            .execute(Request {
+                action: Action::Set(brightness),
+                fade: None,
+            })
+            .await;
+        if result.is_err() {
+            *self.announced.lock().unwrap() = previous;
+        }
+        result.map(|_| ())
+    }
+
+    /// Fade to a brightness over a duration in milliseconds, using the curve
+    /// "linear", "ease-in-out" or "perceptual". Returns the new brightness.
+    #[zbus(out_args("brightness"))]
+    async fn fade(&self, brightness: u8, duration_ms: u32, curve: &str) -> fdo::Result<u8> {
+        let curve: Curve = curve.parse().map_err(fdo::Error::InvalidArgs)?;
+        self.execute(Request {
+            action: Action::Set(brightness),
+            fade: Some(Fade::new(
+                Duration::from_millis(u64::from(duration_ms)),
+                curve,
+            )),
+        })
+        .await
+    }
+
+    /// Change the brightness by a number of percentage points. Returns the
+    /// new brightness.
+    #[zbus(out_args("brightness"))]
+    async fn step(&self, delta: i16) -> fdo::Result<u8> {
+        self.execute(Request {
+            action: Action::Step(delta),
+            fade: None,
+        })
+        .await
+    }
+}
+
+/// Registers the service on `bus` and keeps serving it in the background.
+pub fn serve(panels: Arc<Mutex<Panels>>, bus: Bus) -> zbus::Result<Connection> {
+    let builder = match bus {
+        Bus::Session => Builder::session()?,
+        Bus::System => Builder::system()?,
+    };
+    serve_on(panels, builder)
+}
+
+/// Registers the service on the bus `builder` connects to.
+fn serve_on(panels: Arc<Mutex<Panels>>, builder: Builder) -> zbus::Result<Connection> {
+    let changes = panels.lock().unwrap().subscribe();
+    let announced = Arc::new(Mutex::new(None));
+    let connection = builder
+        .name(BUS_NAME)?
+        .serve_at(
+            OBJECT_PATH,
+            DisplayObject {
+                panels: Arc::clone(&panels),
+                announced: Arc::clone(&announced),
+            },
+        )?
+        .build()?;
+
+    let signals = connection.clone();
+    thread::spawn(move || {
+        for change in changes {
+            // Only the first display is exposed, so skip changes of the others
+            let first = panels.lock().unwrap().displays().into_iter().next();
+            if first.as_ref() != Some(&change.display) {
+                continue;
+            }
+            if announced.lock().unwrap().replace(change.brightness) == Some(change.brightness) {
+                continue;
+            }
+            if let Err(e) = signal_brightness(&signals, change.brightness) {
+                eprintln!("Emitting D-Bus signal failed: {}", e);
+            }
+        }
+    });
+    Ok(connection)
+}
+
+/// Emits `PropertiesChanged` for the `Brightness` property.
+fn signal_brightness(connection: &Connection, brightness: u8) -> zbus::Result<()> {
+    let changed = HashMap::from([("Brightness", Value::from(brightness))]);
+    connection.emit_signal(
+        None::<&str>,
+        OBJECT_PATH,
+        "org.freedesktop.DBus.Properties",
+        "PropertiesChanged",
+        &(INTERFACE_NAME, changed, Vec::<&str>::new()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::sync::mpsc;
+    use std::{env, fs};
+    use zbus::blocking::fdo::PeerProxy;
+    use zbus::blocking::Proxy;
+
+    /// A message bus of its own, stopped when dropped.
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl PrivateBus {
+        fn start() -> Self {
+            let config = env::temp_dir().join(format!("ws170120-dbus-{}.conf", std::process::id()));
+            fs::write(
+                &config,
+                r#"<busconfig>
+  <type>session</type>
+  <listen>unix:tmpdir=/tmp</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#,
+            )
+            .unwrap();
+            let mut daemon = Command::new("dbus-daemon")
+                .arg(format!("--config-file={}", config.display()))
+                .args(["--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("the tests on a private bus need dbus-daemon");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            let _ = fs::remove_file(&config);
+            PrivateBus {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+
+        fn connect(&self) -> Connection {
+            Builder::address(self.address.as_str())
+                .unwrap()
+                .build()
+                .unwrap()
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    /// Panels that don't see any display, so that no request reaches
+    /// attached hardware.
+    fn panels() -> Arc<Mutex<Panels>> {
+        Arc::new(Mutex::new(Panels::without_displays().unwrap()))
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon, run with --ignored"]
+    fn answers_while_a_request_waits_for_the_panels() {
+        let bus = PrivateBus::start();
+        let panels = panels();
+        let _service = serve_on(
+            Arc::clone(&panels),
+            Builder::address(bus.address.as_str()).unwrap(),
+        )
+        .unwrap();
+        let client = bus.connect();
+
+        // Hold the panels like a long fade from another client would
+        let held = panels.lock().unwrap();
+        let (sent, step) = mpsc::channel();
+        let step_client = client.clone();
+        thread::spawn(move || {
+            let proxy = Proxy::new(&step_client, BUS_NAME, OBJECT_PATH, INTERFACE_NAME).unwrap();
+            let _ = sent.send(proxy.call::<_, _, u8>("Step", &(5i16,)));
+        });
+        thread::sleep(Duration::from_millis(200));
+        assert!(
+            step.try_recv().is_err(),
+            "Step returned while the panels were held"
+        );
+
+        let (sent, ping) = mpsc::channel();
+        let ping_client = client.clone();
+        thread::spawn(move || {
+            let peer = PeerProxy::new(&ping_client, BUS_NAME, OBJECT_PATH).unwrap();
+            let _ = sent.send(peer.ping());
+        });
+        ping.recv_timeout(Duration::from_secs(5))
+            .expect("the service did not answer while a request waited")
+            .unwrap();
+
+        // Without a display, the step fails once the panels are free
+        drop(held);
+        let result = step.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(
+            matches!(result, Err(zbus::Error::MethodError(..))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon, run with --ignored"]
+    fn rejects_unknown_curves() {
+        let bus = PrivateBus::start();
+        let _service = serve_on(panels(), Builder::address(bus.address.as_str()).unwrap()).unwrap();
+        let client = bus.connect();
+        let proxy = Proxy::new(&client, BUS_NAME, OBJECT_PATH, INTERFACE_NAME).unwrap();
+        let result = proxy.call::<_, _, u8>("Fade", &(50u8, 100u32, "wobbly"));
+        match result {
+            Err(zbus::Error::MethodError(name, _, _)) => {
+                assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.InvalidArgs")
+            }
+            other => panic!("expected InvalidArgs, got {other:?}"),
+        }
+    }
+}
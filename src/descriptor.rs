@@ -0,0 +1,685 @@
+//! Decoding of HID report descriptors.
+//!
+//! A report descriptor declares the reports a device sends and accepts: their
+//! IDs, the usages of their fields and the size of each field. It is a
+//! sequence of short items that update a parser state, and main items that
+//! declare fields or open and close collections.
+
+use crate::{Error, Result};
+use std::fmt;
+
+/// The direction of a report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ReportKind {
+    /// Sent by the device, e.g. touch events.
+    Input,
+    /// Sent to the device, e.g. the brightness report.
+    Output,
+    /// Read or written on request.
+    Feature,
+}
+
+impl fmt::Display for ReportKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReportKind::Input => "input",
+            ReportKind::Output => "output",
+            ReportKind::Feature => "feature",
+        })
+    }
+}
+
+/// The type of a collection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectionKind {
+    Physical,
+    Application,
+    Logical,
+    Report,
+    NamedArray,
+    UsageSwitch,
+    UsageModifier,
+    /// A reserved or vendor defined type.
+    Other(u8),
+}
+
+impl From<u8> for CollectionKind {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => CollectionKind::Physical,
+            0x01 => CollectionKind::Application,
+            0x02 => CollectionKind::Logical,
+            0x03 => CollectionKind::Report,
+            0x04 => CollectionKind::NamedArray,
+            0x05 => CollectionKind::UsageSwitch,
+            0x06 => CollectionKind::UsageModifier,
+            other => CollectionKind::Other(other),
+        }
+    }
+}
+
+impl fmt::Display for CollectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CollectionKind::Physical => f.write_str("physical"),
+            CollectionKind::Application => f.write_str("application"),
+            CollectionKind::Logical => f.write_str("logical"),
+            CollectionKind::Report => f.write_str("report"),
+            CollectionKind::NamedArray => f.write_str("named array"),
+            CollectionKind::UsageSwitch => f.write_str("usage switch"),
+            CollectionKind::UsageModifier => f.write_str("usage modifier"),
+            CollectionKind::Other(value) => write!(f, "0x{value:02x}"),
+        }
+    }
+}
+
+/// A usage, qualified with its usage page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Usage {
+    pub page: u16,
+    pub id: u16,
+}
+
+impl Usage {
+    fn new(page: u16, value: u32, size: usize) -> Self {
+        // Four byte usages carry their own page in the upper half
+        if size == 4 {
+            Usage {
+                page: (value >> 16) as u16,
+                id: value as u16,
+            }
+        } else {
+            Usage {
+                page,
+                id: value as u16,
+            }
+        }
+    }
+
+    /// The name of the usage, if it is one of the commonly used ones.
+    pub fn name(&self) -> Option<&'static str> {
+        usage_name(self.page, self.id)
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match usage_page_name(self.page) {
+            Some(page) => write!(f, "{page}")?,
+            None => write!(f, "0x{:04x}", self.page)?,
+        }
+        match self.name() {
+            Some(name) => write!(f, " / {name}"),
+            None => write!(f, " / 0x{:04x}", self.id),
+        }
+    }
+}
+
+/// A field of a report, declared by an Input, Output or Feature item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub kind: ReportKind,
+    /// `None` if the device does not use report IDs.
+    pub report_id: Option<u8>,
+    /// The usages assigned to the field's values, in order.
+    pub usages: Vec<Usage>,
+    /// A usage range, assigned after any individual usages.
+    pub usage_range: Option<(Usage, Usage)>,
+    /// Size of each value in bits.
+    pub report_size: u32,
+    /// Number of values.
+    pub report_count: u32,
+    pub logical_minimum: i32,
+    pub logical_maximum: i32,
+    /// The raw flags of the main item, see [`Field::is_constant`] and friends.
+    pub flags: u32,
+}
+
+impl Field {
+    /// Padding or a value that cannot be changed.
+    pub fn is_constant(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+
+    /// One value per usage, as opposed to an array of usage indices.
+    pub fn is_variable(&self) -> bool {
+        self.flags & 0x02 != 0
+    }
+
+    /// Values are changes relative to the previous report.
+    pub fn is_relative(&self) -> bool {
+        self.flags & 0x04 != 0
+    }
+
+    /// Total size of the field in bits.
+    pub fn bits(&self) -> u32 {
+        self.report_size.saturating_mul(self.report_count)
+    }
+}
+
+/// A collection grouping fields and other collections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collection {
+    pub kind: CollectionKind,
+    pub usage: Option<Usage>,
+    pub children: Vec<Node>,
+}
+
+/// An entry of the descriptor tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Collection(Collection),
+    Field(Field),
+}
+
+/// The size of one report, summed over its fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportInfo {
+    pub kind: ReportKind,
+    pub report_id: Option<u8>,
+    /// Size in bits, not counting the report ID.
+    pub bits: u32,
+}
+
+impl ReportInfo {
+    /// Size in bytes, not counting the report ID.
+    pub fn bytes(&self) -> u32 {
+        self.bits.div_ceil(8)
+    }
+}
+
+/// A decoded report descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportDescriptor {
+    /// Top level collections and fields, in declaration order.
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Globals {
+    usage_page: u16,
+    logical_minimum: i32,
+    logical_maximum: i32,
+    report_size: u32,
+    report_count: u32,
+    report_id: Option<u8>,
+}
+
+#[derive(Debug, Default)]
+struct Locals {
+    usages: Vec<Usage>,
+    minimum: Option<Usage>,
+    maximum: Option<Usage>,
+}
+
+impl ReportDescriptor {
+    /// Decodes the descriptor in `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let invalid = |offset: usize, message: &str| Error::InvalidDescriptor {
+            offset,
+            message: message.to_string(),
+        };
+
+        let mut globals = Globals::default();
+        let mut stack = Vec::new();
+        let mut locals = Locals::default();
+        // The innermost collection is last; the root holds top level nodes
+        let mut open: Vec<Collection> = Vec::new();
+        let mut root = Vec::new();
+
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let prefix = bytes[offset];
+            if prefix == 0xfe {
+                // Long items are reserved and carry their own size
+                let size = *bytes
+                    .get(offset + 1)
+                    .ok_or_else(|| invalid(offset, "truncated long item"))?;
+                offset += 3 + usize::from(size);
+                continue;
+            }
+            let size = match prefix & 0x03 {
+                3 => 4,
+                size => usize::from(size),
+            };
+            let data = bytes
+                .get(offset + 1..offset + 1 + size)
+                .ok_or_else(|| invalid(offset, "truncated item"))?;
+            let unsigned = data
+                .iter()
+                .rev()
+                .fold(0u32, |value, &byte| value << 8 | u32::from(byte));
+            let signed = match size {
+                1 => i32::from(unsigned as u8 as i8),
+                2 => i32::from(unsigned as u16 as i16),
+                _ => unsigned as i32,
+            };
+
+            match (prefix >> 2) & 0x03 {
+                // Main items
+                0 => {
+                    let tag = prefix >> 4;
+                    let kind = match tag {
+                        0x8 => Some(ReportKind::Input),
+                        0x9 => Some(ReportKind::Output),
+                        0xb => Some(ReportKind::Feature),
+                        _ => None,
+                    };
+                    if let Some(kind) = kind {
+                        let field = Field {
+                            kind,
+                            report_id: globals.report_id,
+                            usages: std::mem::take(&mut locals.usages),
+                            usage_range: locals.minimum.zip(locals.maximum),
+                            report_size: globals.report_size,
+                            report_count: globals.report_count,
+                            logical_minimum: globals.logical_minimum,
+                            logical_maximum: globals.logical_maximum,
+                            flags: unsigned,
+                        };
+                        match open.last_mut() {
+                            Some(collection) => collection.children.push(Node::Field(field)),
+                            None => root.push(Node::Field(field)),
+                        }
+                    } else if tag == 0xa {
+                        open.push(Collection {
+                            kind: CollectionKind::from(unsigned as u8),
+                            usage: locals.usages.first().copied().or(locals.minimum),
+                            children: Vec::new(),
+                        });
+                    } else if tag == 0xc {
+                        let collection = open
+                            .pop()
+                            .ok_or_else(|| invalid(offset, "end collection without collection"))?;
+                        match open.last_mut() {
+                            Some(parent) => parent.children.push(Node::Collection(collection)),
+                            None => root.push(Node::Collection(collection)),
+                        }
+                    } else {
+                        return Err(invalid(offset, "unknown main item"));
+                    }
+                    locals = Locals::default();
+                }
+                // Global items
+                1 => match prefix >> 4 {
+                    0x0 => globals.usage_page = unsigned as u16,
+                    0x1 => globals.logical_minimum = signed,
+                    0x2 => {
+                        // Many devices declare e.g. 0..255 with a one byte maximum
+                        globals.logical_maximum = if signed < globals.logical_minimum {
+                            unsigned as i32
+                        } else {
+                            signed
+                        }
+                    }
+                    0x7 => globals.report_size = unsigned,
+                    0x8 => {
+                        globals.report_id = Some(
+                            u8::try_from(unsigned)
+                                .map_err(|_| invalid(offset, "report ID out of range"))?,
+                        )
+                    }
+                    0x9 => globals.report_count = unsigned,
+                    0xa => stack.push(globals),
+                    0xb => {
+                        globals = stack
+                            .pop()
+                            .ok_or_else(|| invalid(offset, "pop without push"))?
+                    }
+                    // Physical extents and units don't affect the layout
+                    _ => {}
+                },
+                // Local items
+                2 => match prefix >> 4 {
+                    0x0 => locals
+                        .usages
+                        .push(Usage::new(globals.usage_page, unsigned, size)),
+                    0x1 => locals.minimum = Some(Usage::new(globals.usage_page, unsigned, size)),
+                    0x2 => locals.maximum = Some(Usage::new(globals.usage_page, unsigned, size)),
+                    // Designators, strings and delimiters are rarely used
+                    _ => {}
+                },
+                _ => return Err(invalid(offset, "reserved item type")),
+            }
+            offset += 1 + size;
+        }
+
+        if !open.is_empty() {
+            return Err(invalid(bytes.len(), "collection is not closed"));
+        }
+        Ok(ReportDescriptor { nodes: root })
+    }
+
+    /// Returns every field, in declaration order.
+    pub fn fields(&self) -> Vec<&Field> {
+        fn collect<'a>(nodes: &'a [Node], fields: &mut Vec<&'a Field>) {
+            for node in nodes {
+                match node {
+                    Node::Field(field) => fields.push(field),
+                    Node::Collection(collection) => collect(&collection.children, fields),
+                }
+            }
+        }
+        let mut fields = Vec::new();
+        collect(&self.nodes, &mut fields);
+        fields
+    }
+
+    /// Returns the declared reports with their sizes, ordered by ID and kind.
+    pub fn reports(&self) -> Vec<ReportInfo> {
+        let mut reports: Vec<ReportInfo> = Vec::new();
+        for field in self.fields() {
+            match reports
+                .iter_mut()
+                .find(|report| report.kind == field.kind && report.report_id == field.report_id)
+            {
+                Some(report) => report.bits += field.bits(),
+                None => reports.push(ReportInfo {
+                    kind: field.kind,
+                    report_id: field.report_id,
+                    bits: field.bits(),
+                }),
+            }
+        }
+        reports.sort_by_key(|report| (report.report_id, report.kind));
+        reports
+    }
+}
+
+/// The name of a usage page, if it is one of the commonly used ones.
+pub fn usage_page_name(page: u16) -> Option<&'static str> {
+    Some(match page {
+        0x01 => "Generic Desktop",
+        0x07 => "Keyboard",
+        0x08 => "LED",
+        0x09 => "Button",
+        0x0c => "Consumer",
+        0x0d => "Digitizers",
+        0x80 => "Monitor",
+        0x82 => "VESA Virtual Controls",
+        0xff00..=0xffff => "Vendor Defined",
+        _ => return None,
+    })
+}
+
+/// The name of a usage, if it is one of the commonly used ones.
+pub fn usage_name(page: u16, id: u16) -> Option<&'static str> {
+    Some(match (page, id) {
+        (0x01, 0x01) => "Pointer",
+        (0x01, 0x02) => "Mouse",
+        (0x01, 0x04) => "Joystick",
+        (0x01, 0x06) => "Keyboard",
+        (0x01, 0x30) => "X",
+        (0x01, 0x31) => "Y",
+        (0x01, 0x38) => "Wheel",
+        (0x09, id) if id > 0 => "Button",
+        (0x0d, 0x01) => "Digitizer",
+        (0x0d, 0x02) => "Pen",
+        (0x0d, 0x04) => "Touch Screen",
+        (0x0d, 0x05) => "Touch Pad",
+        (0x0d, 0x0e) => "Device Configuration",
+        (0x0d, 0x20) => "Stylus",
+        (0x0d, 0x22) => "Finger",
+        (0x0d, 0x23) => "Device Settings",
+        (0x0d, 0x30) => "Tip Pressure",
+        (0x0d, 0x32) => "In Range",
+        (0x0d, 0x42) => "Tip Switch",
+        (0x0d, 0x47) => "Confidence",
+        (0x0d, 0x48) => "Width",
+        (0x0d, 0x49) => "Height",
+        (0x0d, 0x51) => "Contact Identifier",
+        (0x0d, 0x52) => "Device Mode",
+        (0x0d, 0x53) => "Device Identifier",
+        (0x0d, 0x54) => "Contact Count",
+        (0x0d, 0x55) => "Contact Count Maximum",
+        (0x0d, 0x56) => "Scan Time",
+        (0x82, 0x10) => "Brightness",
+        (0x82, 0x12) => "Contrast",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{CONTROL_DESCRIPTOR, TOUCH_DESCRIPTOR};
+    use crate::{CONTROL_MAGIC, DATA_LENGTH};
+
+    fn usage(page: u16, id: u16) -> Usage {
+        Usage { page, id }
+    }
+
+    fn collections(nodes: &[Node]) -> Vec<&Collection> {
+        nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::Collection(collection) => Some(collection),
+                Node::Field(_) => None,
+            })
+            .collect()
+    }
+
+    /// Returns the offset and message of the error parsing `bytes` gives.
+    fn parse_error(bytes: &[u8]) -> (usize, String) {
+        match ReportDescriptor::parse(bytes) {
+            Err(Error::InvalidDescriptor { offset, message }) => (offset, message),
+            other => panic!("expected an invalid descriptor, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_the_control_interface() {
+        let descriptor = ReportDescriptor::parse(CONTROL_DESCRIPTOR).unwrap();
+        let top = collections(&descriptor.nodes);
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].kind, CollectionKind::Application);
+        assert_eq!(top[0].usage, Some(usage(0xff00, 0x01)));
+        assert_eq!(top[0].usage.unwrap().to_string(), "Vendor Defined / 0x0001");
+
+        let reports = descriptor.reports();
+        let kinds: Vec<_> = reports.iter().map(|report| report.kind).collect();
+        assert_eq!(
+            kinds,
+            [ReportKind::Input, ReportKind::Output, ReportKind::Feature]
+        );
+        for report in reports {
+            assert_eq!(report.report_id, Some(CONTROL_MAGIC[0]));
+            // The brightness report is the ID followed by the report itself
+            assert_eq!(report.bytes() as usize, DATA_LENGTH - 1);
+        }
+
+        let output = descriptor.fields()[1];
+        assert_eq!(output.kind, ReportKind::Output);
+        assert_eq!(output.usages, [usage(0xff00, 0x03)]);
+        assert_eq!((output.report_size, output.report_count), (8, 37));
+        // A two byte maximum of 255 is positive as it is
+        assert_eq!((output.logical_minimum, output.logical_maximum), (0, 255));
+    }
+
+    #[test]
+    fn parses_the_touch_interface() {
+        let descriptor = ReportDescriptor::parse(TOUCH_DESCRIPTOR).unwrap();
+        let top = collections(&descriptor.nodes);
+        let usages: Vec<_> = top.iter().map(|collection| collection.usage).collect();
+        assert_eq!(usages, [Some(usage(0x01, 0x02)), Some(usage(0x0d, 0x04))]);
+        assert_eq!(
+            top[1].usage.unwrap().to_string(),
+            "Digitizers / Touch Screen"
+        );
+
+        let fingers = collections(&top[1].children);
+        assert_eq!(fingers.len(), 2);
+        for finger in fingers {
+            assert_eq!(finger.kind, CollectionKind::Logical);
+            assert_eq!(finger.usage, Some(usage(0x0d, 0x22)));
+        }
+
+        assert_eq!(
+            descriptor.reports(),
+            [
+                // Two buttons, padding, X and Y
+                ReportInfo {
+                    kind: ReportKind::Input,
+                    report_id: Some(1),
+                    bits: 40,
+                },
+                // Two contacts of six bytes and the contact count
+                ReportInfo {
+                    kind: ReportKind::Input,
+                    report_id: Some(2),
+                    bits: 104,
+                },
+                ReportInfo {
+                    kind: ReportKind::Feature,
+                    report_id: Some(2),
+                    bits: 8,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn extracts_field_attributes() {
+        let descriptor = ReportDescriptor::parse(TOUCH_DESCRIPTOR).unwrap();
+        let fields = descriptor.fields();
+
+        let buttons = fields[0];
+        assert!(buttons.usages.is_empty());
+        assert_eq!(
+            buttons.usage_range,
+            Some((usage(0x09, 0x01), usage(0x09, 0x02)))
+        );
+        assert_eq!((buttons.report_size, buttons.report_count), (1, 2));
+
+        let padding = fields[1];
+        assert!(padding.is_constant());
+        assert!(padding.is_variable());
+        assert_eq!(padding.bits(), 6);
+
+        let pointer = fields[2];
+        assert_eq!(pointer.usages, [usage(0x01, 0x30), usage(0x01, 0x31)]);
+        assert_eq!(
+            (pointer.logical_minimum, pointer.logical_maximum),
+            (0, 4095)
+        );
+        assert_eq!(pointer.bits(), 32);
+        assert!(!pointer.is_relative());
+
+        // Tip switch, in range, confidence, padding, contact ID, X and Y
+        let finger: Vec<_> = fields[3..10].to_vec();
+        assert!(finger.iter().all(|field| field.report_id == Some(2)));
+        assert_eq!(finger[0].usages, [usage(0x0d, 0x42)]);
+        assert_eq!(
+            (finger[0].logical_minimum, finger[0].logical_maximum),
+            (0, 1)
+        );
+        assert!(finger[3].is_constant());
+        assert_eq!(finger[4].usages, [usage(0x0d, 0x51)]);
+        assert_eq!(finger[4].logical_maximum, 127);
+        assert_eq!(finger[5].usages, [usage(0x01, 0x30)]);
+        assert_eq!(finger[6].usages, [usage(0x01, 0x31)]);
+        assert_eq!(
+            (finger[6].report_size, finger[6].logical_maximum),
+            (16, 4095)
+        );
+
+        let count = fields[fields.len() - 2];
+        assert_eq!(count.usages, [usage(0x0d, 0x54)]);
+        assert_eq!((count.logical_minimum, count.logical_maximum), (0, 5));
+        let maximum = fields[fields.len() - 1];
+        assert_eq!(maximum.kind, ReportKind::Feature);
+        assert_eq!(maximum.usages, [usage(0x0d, 0x55)]);
+    }
+
+    #[test]
+    fn reads_one_byte_maximums_as_unsigned() {
+        let descriptor = ReportDescriptor::parse(&[
+            0x15, 0x00, // Logical Minimum (0)
+            0x25, 0xff, // Logical Maximum (255, or -1 if signed)
+            0x75, 0x08, // Report Size (8)
+            0x95, 0x01, // Report Count (1)
+            0x81, 0x02, // Input (Data, Variable, Absolute)
+            0x15, 0x81, // Logical Minimum (-127)
+            0x25, 0x7f, // Logical Maximum (127)
+            0x81, 0x06, // Input (Data, Variable, Relative)
+        ])
+        .unwrap();
+        let fields = descriptor.fields();
+        assert_eq!(
+            (fields[0].logical_minimum, fields[0].logical_maximum),
+            (0, 255)
+        );
+        assert_eq!(
+            (fields[1].logical_minimum, fields[1].logical_maximum),
+            (-127, 127)
+        );
+        assert!(fields[1].is_relative());
+        // Without a Report ID item, fields belong to the only report
+        assert_eq!(fields[0].report_id, None);
+        assert_eq!(descriptor.reports()[0].bits, 16);
+    }
+
+    #[test]
+    fn handles_extended_usages_and_the_global_stack() {
+        let descriptor = ReportDescriptor::parse(&[
+            0x05, 0x0d, // Usage Page (Digitizers)
+            0x75, 0x08, // Report Size (8)
+            0x95, 0x01, // Report Count (1)
+            0xa4, // Push
+            0x0b, 0x30, 0x00, 0x01, 0x00, // Usage (Generic Desktop / X)
+            0x75, 0x10, // Report Size (16)
+            0x81, 0x02, // Input (Data, Variable, Absolute)
+            0xb4, // Pop
+            0x09, 0x54, // Usage (Contact Count)
+            0x81, 0x02, // Input (Data, Variable, Absolute)
+            0xfe, 0x02, 0x10, 0xaa, 0xbb, // Long item, skipped
+        ])
+        .unwrap();
+        let fields = descriptor.fields();
+        assert_eq!(fields[0].usages, [usage(0x01, 0x30)]);
+        assert_eq!(fields[0].report_size, 16);
+        assert_eq!(fields[1].usages, [usage(0x0d, 0x54)]);
+        assert_eq!(fields[1].report_size, 8);
+        assert_eq!(fields.len(), 2);
+    }
+
+    #[test]
+    fn rejects_invalid_descriptors() {
+        let cases: [(&[u8], usize, &str); 7] = [
+            (&[0x05], 0, "truncated item"),
+            (&[0x05, 0x01, 0x26, 0xff], 2, "truncated item"),
+            (&[0xfe], 0, "truncated long item"),
+            (&[0xc0], 0, "end collection without collection"),
+            (&[0x09, 0x01, 0xa1, 0x01], 4, "collection is not closed"),
+            (&[0xb4], 0, "pop without push"),
+            (&[0x86, 0x00, 0x01], 0, "report ID out of range"),
+        ];
+        for (bytes, offset, message) in cases {
+            assert_eq!(
+                parse_error(bytes),
+                (offset, message.to_string()),
+                "{bytes:02x?}"
+            );
+        }
+        assert_eq!(parse_error(&[0x0c]).1, "reserved item type");
+        assert_eq!(
+            parse_error(&[0x75, 0x08, 0xd0]),
+            (2, "unknown main item".to_string())
+        );
+    }
+
+    #[test]
+    fn names_usages() {
+        assert_eq!(
+            usage(0x0d, 0x51).to_string(),
+            "Digitizers / Contact Identifier"
+        );
+        assert_eq!(usage(0x09, 0x03).to_string(), "Button / Button");
+        assert_eq!(
+            usage(0x82, 0x10).to_string(),
+            "VESA Virtual Controls / Brightness"
+        );
+        assert_eq!(usage(0x1234, 0x05).to_string(), "0x1234 / 0x0005");
+        assert_eq!(usage(0x09, 0x00).name(), None);
+    }
+}
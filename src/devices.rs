@@ -0,0 +1,228 @@
+//! Enumerating and selecting attached WS170120 displays.
+
+use crate::{Display, Error, Result, WS170120_PRODUCT_ID, WS170120_VENDOR_ID};
+use hidapi::{DeviceInfo, HidApi, HidDevice};
+use std::ffi::CString;
+
+/// An attached WS170120 display that has not been opened yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayInfo {
+    /// Platform specific HID path, e.g. `/dev/hidraw3` on Linux.
+    pub path: String,
+    pub serial: Option<String>,
+    pub interface_number: i32,
+    /// Usage page of the interface's top-level collection, 0 if the HID
+    /// backend does not report it.
+    pub usage_page: u16,
+}
+
+impl DisplayInfo {
+    /// Opens this display.
+    pub fn open(&self, api: &HidApi) -> Result<Display> {
+        open_path(api, &self.path).map(Display::new)
+    }
+
+    /// A short name for messages: the serial number if known, else the path.
+    pub fn label(&self) -> &str {
+        self.serial.as_deref().unwrap_or(&self.path)
+    }
+}
+
+/// Opens the HID interface at `path` as is, whichever interface of which
+/// device it is.
+pub fn open_path(api: &HidApi, path: &str) -> Result<HidDevice> {
+    let path = CString::new(path).map_err(|_| Error::NotConnected)?;
+    api.open_path(&path)
+        .map_err(|e| Error::from_device("Failed to open device", e))
+}
+
+impl From<&DeviceInfo> for DisplayInfo {
+    fn from(info: &DeviceInfo) -> Self {
+        DisplayInfo {
+            path: info.path().to_string_lossy().into_owned(),
+            serial: info
+                .serial_number()
+                .filter(|serial| !serial.is_empty())
+                .map(str::to_string),
+            interface_number: info.interface_number(),
+            usage_page: info.usage_page(),
+        }
+    }
+}
+
+/// Usage pages of the input interfaces next to the control interface.
+const GENERIC_DESKTOP: u16 = 0x01;
+const DIGITIZERS: u16 = 0x0d;
+
+/// Lists all WS170120 displays known to `api`, in enumeration order.
+///
+/// Only the interfaces taking brightness reports are listed, see
+/// [`control_interfaces`].
+pub fn find_displays(api: &HidApi) -> Vec<DisplayInfo> {
+    control_interfaces(
+        api.device_list()
+            .filter(|info| {
+                info.vendor_id() == WS170120_VENDOR_ID && info.product_id() == WS170120_PRODUCT_ID
+            })
+            .map(DisplayInfo::from)
+            .collect(),
+    )
+}
+
+/// Drops the touch screen and mouse interfaces a display also exposes, so
+/// that brightness reports only go to its control interface.
+///
+/// If no other interface is left, e.g. because a firmware exposes a single
+/// interface for everything, all of them are kept.
+fn control_interfaces(interfaces: Vec<DisplayInfo>) -> Vec<DisplayInfo> {
+    let is_input = |info: &DisplayInfo| matches!(info.usage_page, GENERIC_DESKTOP | DIGITIZERS);
+    if interfaces.iter().all(is_input) {
+        return interfaces;
+    }
+    interfaces
+        .into_iter()
+        .filter(|info| !is_input(info))
+        .collect()
+}
+
+/// Chooses which of the attached displays a command applies to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Selector {
+    /// The first display found.
+    #[default]
+    First,
+    /// Every display found.
+    All,
+    /// The display with this USB serial number.
+    Serial(String),
+    /// The display at this HID path.
+    Path(String),
+}
+
+impl Selector {
+    /// Returns the displays from `displays` this selector applies to.
+    ///
+    /// Fails with [`Error::NotConnected`] if none match.
+    pub fn select(&self, displays: Vec<DisplayInfo>) -> Result<Vec<DisplayInfo>> {
+        let selected: Vec<DisplayInfo> = match self {
+            Selector::First => displays.into_iter().take(1).collect(),
+            Selector::All => displays,
+            Selector::Serial(serial) => displays
+                .into_iter()
+                .filter(|info| info.serial.as_ref() == Some(serial))
+                .collect(),
+            Selector::Path(path) => displays
+                .into_iter()
+                .filter(|info| &info.path == path)
+                .collect(),
+        };
+        if selected.is_empty() {
+            return Err(Error::NotConnected);
+        }
+        Ok(selected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interface(path: &str, serial: Option<&str>, usage_page: u16) -> DisplayInfo {
+        DisplayInfo {
+            path: path.to_string(),
+            serial: serial.map(str::to_string),
+            interface_number: 0,
+            usage_page,
+        }
+    }
+
+    fn paths(interfaces: &[DisplayInfo]) -> Vec<&str> {
+        interfaces.iter().map(|info| info.path.as_str()).collect()
+    }
+
+    /// Two displays, each with a touch and a vendor defined control
+    /// interface.
+    fn two_displays() -> Vec<DisplayInfo> {
+        vec![
+            interface("/dev/hidraw0", Some("A"), DIGITIZERS),
+            interface("/dev/hidraw1", Some("A"), 0xff00),
+            interface("/dev/hidraw2", Some("B"), DIGITIZERS),
+            interface("/dev/hidraw3", Some("B"), 0xff00),
+        ]
+    }
+
+    #[test]
+    fn drops_touch_interfaces() {
+        let interfaces = control_interfaces(two_displays());
+        assert_eq!(paths(&interfaces), ["/dev/hidraw1", "/dev/hidraw3"]);
+    }
+
+    #[test]
+    fn drops_mouse_interfaces() {
+        let interfaces = control_interfaces(vec![
+            interface("/dev/hidraw0", None, GENERIC_DESKTOP),
+            interface("/dev/hidraw1", None, 0xffa0),
+        ]);
+        assert_eq!(paths(&interfaces), ["/dev/hidraw1"]);
+    }
+
+    #[test]
+    fn keeps_interfaces_of_unknown_usage() {
+        let interfaces = control_interfaces(vec![
+            interface("/dev/hidraw0", None, 0),
+            interface("/dev/hidraw1", None, DIGITIZERS),
+        ]);
+        assert_eq!(paths(&interfaces), ["/dev/hidraw0"]);
+    }
+
+    #[test]
+    fn keeps_a_lone_input_interface() {
+        let interfaces = control_interfaces(vec![interface("/dev/hidraw0", None, DIGITIZERS)]);
+        assert_eq!(paths(&interfaces), ["/dev/hidraw0"]);
+    }
+
+    #[test]
+    fn selects_first_control_interface() {
+        let selected = Selector::First
+            .select(control_interfaces(two_displays()))
+            .unwrap();
+        assert_eq!(paths(&selected), ["/dev/hidraw1"]);
+    }
+
+    #[test]
+    fn selects_all_control_interfaces() {
+        let selected = Selector::All
+            .select(control_interfaces(two_displays()))
+            .unwrap();
+        assert_eq!(paths(&selected), ["/dev/hidraw1", "/dev/hidraw3"]);
+    }
+
+    #[test]
+    fn selects_by_serial() {
+        let selected = Selector::Serial("B".to_string())
+            .select(control_interfaces(two_displays()))
+            .unwrap();
+        assert_eq!(paths(&selected), ["/dev/hidraw3"]);
+    }
+
+    #[test]
+    fn selects_by_path() {
+        let selected = Selector::Path("/dev/hidraw1".to_string())
+            .select(two_displays())
+            .unwrap();
+        assert_eq!(paths(&selected), ["/dev/hidraw1"]);
+    }
+
+    #[test]
+    fn fails_when_nothing_matches() {
+        let unknown_serial = Selector::Serial("C".to_string()).select(two_displays());
+        assert!(matches!(unknown_serial, Err(Error::NotConnected)));
+        let touch_path =
+            Selector::Path("/dev/hidraw0".to_string()).select(control_interfaces(two_displays()));
+        assert!(matches!(touch_path, Err(Error::NotConnected)));
+        assert!(matches!(
+            Selector::First.select(Vec::new()),
+            Err(Error::NotConnected)
+        ));
+    }
+}
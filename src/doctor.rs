@@ -0,0 +1,525 @@
+//! Explains why a display cannot be found or controlled.
+
+use crate::cli_error::CliError;
+use crate::config::Config;
+use crate::state::State;
+use hidapi::{DeviceInfo, HidApi};
+use ws170120::touch::Touchscreen;
+use ws170120::{
+    brightness_report, find_displays, Display, DisplayInfo, Error, Transport, WS170120_PRODUCT_ID,
+    WS170120_VENDOR_ID,
+};
+
+/// Usage page of the interfaces announcing a touch screen.
+const DIGITIZERS: u16 = 0x0d;
+
+#[derive(clap::Args)]
+pub struct DoctorArgs {
+    /// Brightness to write when testing each report path [default: the current brightness]
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub brightness: Option<u8>,
+}
+
+pub fn run(args: &DoctorArgs, config: &Config) -> Result<(), CliError> {
+    let api = HidApi::new().map_err(ws170120::Error::HidInit)?;
+    let devices: Vec<&DeviceInfo> = api.device_list().collect();
+    let mut problems = Vec::new();
+
+    println!("hidapi backend: {}", backend(&devices));
+    println!();
+    println!("HID devices ({}):", devices.len());
+    for device in &devices {
+        println!("  {}", describe(device));
+    }
+    println!();
+
+    let others: Vec<&&DeviceInfo> = devices
+        .iter()
+        .filter(|device| {
+            device.vendor_id() == WS170120_VENDOR_ID && device.product_id() != WS170120_PRODUCT_ID
+        })
+        .collect();
+    for other in &others {
+        println!(
+            "Found another device of the same vendor: {}",
+            describe(other)
+        );
+    }
+
+    let displays = find_displays(&api);
+    // The touch screen may be on any interface of the vendor, like `touch` looks for it
+    let inputs: Vec<DisplayInfo> = devices
+        .iter()
+        .filter(|device| device.vendor_id() == WS170120_VENDOR_ID)
+        .map(|device| DisplayInfo::from(*device))
+        .filter(|info| !displays.contains(info))
+        .collect();
+    if displays.is_empty() {
+        println!(
+            "No WS170120 display ({:04x}:{:04x}) found.",
+            WS170120_VENDOR_ID, WS170120_PRODUCT_ID
+        );
+        problems.push(if others.is_empty() {
+            format!(
+                "No device with vendor ID {:04x} is attached. Check the USB cable; the touch/control port of the display must be connected, not just HDMI.",
+                WS170120_VENDOR_ID
+            )
+        } else {
+            format!(
+                "A device with vendor ID {:04x} is attached, but with another product ID. It may be a different display model or firmware that this tool does not support.",
+                WS170120_VENDOR_ID
+            )
+        });
+    } else {
+        println!("WS170120 display interfaces ({}):", displays.len());
+    }
+    for info in &displays {
+        check_display(&api, info, args, config, &mut problems);
+    }
+    if !displays.is_empty() {
+        println!();
+        println!("Other interfaces ({}):", inputs.len());
+        let touch: Vec<(&DisplayInfo, Touch)> = inputs
+            .iter()
+            .map(|info| (info, check_touch(&api, info, &mut problems)))
+            .collect();
+        touch_problems(&touch, &mut problems);
+    }
+
+    println!();
+    verdict(&problems)
+}
+
+/// Prints the problems found, which make the command fail.
+fn verdict(problems: &[String]) -> Result<(), CliError> {
+    if problems.is_empty() {
+        println!("No problems found.");
+        Ok(())
+    } else {
+        println!("Problems:");
+        for problem in problems {
+            println!("- {}", problem);
+        }
+        Err(CliError::ProblemsFound(problems.len()))
+    }
+}
+
+/// Guesses the hidapi backend from the shape of the device paths.
+fn backend(devices: &[&DeviceInfo]) -> &'static str {
+    if cfg!(target_os = "linux") {
+        match devices.first() {
+            Some(device) if device.path().to_bytes().starts_with(b"/dev/hidraw") => "hidraw",
+            Some(_) => "libusb",
+            None => "hidraw or libusb (no devices to tell from)",
+        }
+    } else if cfg!(target_os = "macos") {
+        "IOHIDManager"
+    } else if cfg!(windows) {
+        "Windows HID"
+    } else {
+        "libusb"
+    }
+}
+
+fn describe(device: &DeviceInfo) -> String {
+    format!(
+        "{:04x}:{:04x}  {}  interface={} usage={:04x}:{:04x}  {} {}",
+        device.vendor_id(),
+        device.product_id(),
+        device.path().to_string_lossy(),
+        device.interface_number(),
+        device.usage_page(),
+        device.usage(),
+        device.manufacturer_string().unwrap_or("-"),
+        device.product_string().unwrap_or("-"),
+    )
+}
+
+fn check_display(
+    api: &HidApi,
+    info: &DisplayInfo,
+    args: &DoctorArgs,
+    config: &Config,
+    problems: &mut Vec<String>,
+) {
+    println!(
+        "  {} (interface {}, serial {})",
+        info.path,
+        info.interface_number,
+        info.serial.as_deref().unwrap_or("-")
+    );
+    #[cfg(target_os = "linux")]
+    let denied = linux::check_node(&info.path, problems);
+    #[cfg(not(target_os = "linux"))]
+    let denied = false;
+
+    let mut display = match info.open(api) {
+        Ok(display) => display,
+        Err(e) => {
+            println!("    open: FAILED ({})", e);
+            if !denied {
+                problems.push(format!("{}: {}", info.path, e));
+            }
+            return;
+        }
+    };
+    println!("    open: ok");
+    // Percentages are read and written through the configured mapping
+    display.set_mapping(config.mapping.clone());
+
+    let current = match display.brightness() {
+        Ok(brightness) => {
+            println!("    readback: {}%", brightness);
+            Some(brightness)
+        }
+        Err(e) => {
+            // Not a problem as such, some firmware doesn't support it
+            println!("    readback: not supported ({})", e);
+            State::from_env().load_brightness(info.serial.as_deref())
+        }
+    };
+    let Some(brightness) = args.brightness.or(current) else {
+        println!("    test writes: skipped, the current brightness is unknown (pass --brightness)");
+        return;
+    };
+
+    check_writes(&display, brightness, &info.path, problems);
+}
+
+/// Writes `brightness` once as output report and once as feature report.
+fn check_writes<T: Transport>(
+    display: &Display<T>,
+    brightness: u8,
+    path: &str,
+    problems: &mut Vec<String>,
+) {
+    let raw = display.mapping().raw(brightness);
+    let value = if raw == brightness {
+        format!("{brightness}%")
+    } else {
+        format!("{brightness}%, raw {raw}")
+    };
+    let report = brightness_report(raw);
+    let transport = display.transport();
+    let output = match transport.write_output_report(&report) {
+        Ok(written) if written == report.len() => Ok(()),
+        Ok(written) => Err(format!(
+            "short write, {} of {} bytes",
+            written,
+            report.len()
+        )),
+        Err(e) => Err(e.to_string()),
+    };
+    let feature = transport
+        .send_feature_report(&report)
+        .map_err(|e| e.to_string());
+    for (name, result) in [("output report", &output), ("feature report", &feature)] {
+        match result {
+            Ok(()) => println!("    {} ({}): ok", name, value),
+            Err(e) => println!("    {} ({}): FAILED ({})", name, value, e),
+        }
+    }
+    if output.is_err() && feature.is_err() {
+        problems.push(format!(
+            "{path}: neither the output report nor the feature report could be written."
+        ));
+    }
+}
+
+/// What an interface other than the control interface turned out to be.
+#[derive(Debug)]
+enum Touch {
+    /// A touch screen the tool can decode.
+    Found,
+    /// Something else, e.g. the mouse interface.
+    Missing,
+    /// The current user may not open the interface, a problem reported
+    /// with the node's permissions.
+    Denied,
+    /// The interface could not be opened or its descriptor not read.
+    Failed(Error),
+}
+
+/// Opens `info` and looks for a touch report in its report descriptor.
+fn check_touch(api: &HidApi, info: &DisplayInfo, problems: &mut Vec<String>) -> Touch {
+    println!(
+        "  {} (interface {}, usage page {:04x})",
+        info.path, info.interface_number, info.usage_page
+    );
+    #[cfg(target_os = "linux")]
+    let denied = linux::check_node(&info.path, problems);
+    #[cfg(not(target_os = "linux"))]
+    let denied = false;
+
+    match Touchscreen::open_interface(api, info.clone()) {
+        Ok(Some(touchscreen)) => {
+            let layout = touchscreen.layout();
+            println!(
+                "    touch screen: {} contacts, x {}..{}, y {}..{}",
+                layout.max_contacts(),
+                layout.x_range.0,
+                layout.x_range.1,
+                layout.y_range.0,
+                layout.y_range.1
+            );
+            Touch::Found
+        }
+        Ok(None) => {
+            println!("    touch screen: no");
+            Touch::Missing
+        }
+        Err(e) => {
+            println!("    touch screen: FAILED ({})", e);
+            if denied {
+                Touch::Denied
+            } else {
+                Touch::Failed(e)
+            }
+        }
+    }
+}
+
+/// Adds the problems with the touch screen, given what each interface other
+/// than the control interfaces turned out to be.
+fn touch_problems(interfaces: &[(&DisplayInfo, Touch)], problems: &mut Vec<String>) {
+    for (info, touch) in interfaces {
+        match touch {
+            Touch::Missing if info.usage_page == DIGITIZERS => problems.push(format!(
+                "{}: the interface announces a touch screen, but its report descriptor has no touch report this tool can decode.",
+                info.path
+            )),
+            Touch::Failed(e) => problems.push(format!("{}: {}", info.path, e)),
+            _ => {}
+        }
+    }
+    if !interfaces
+        .iter()
+        .any(|(_, touch)| matches!(touch, Touch::Found))
+    {
+        problems.push(
+            "No touch screen interface was found, so `touch` can't be used. Displays without touch screen don't have one; otherwise check that the USB cable is connected to the touch port.".to_string(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli_error::EXIT_FAILURE;
+    use ws170120::mock::{MockTransport, Report};
+    use ws170120::Mapping;
+
+    fn interface(path: &str, usage_page: u16) -> DisplayInfo {
+        DisplayInfo {
+            path: path.to_string(),
+            serial: None,
+            interface_number: 1,
+            usage_page,
+        }
+    }
+
+    fn writes(display: &Display<MockTransport>) -> Vec<String> {
+        let mut problems = Vec::new();
+        check_writes(display, 40, "/dev/hidraw3", &mut problems);
+        problems
+    }
+
+    #[test]
+    fn accepts_either_report_path() {
+        let display = Display::new(MockTransport::new());
+        assert!(writes(&display).is_empty());
+        assert_eq!(display.transport().reports().len(), 2);
+
+        display.transport().fail_output(true);
+        assert!(writes(&display).is_empty());
+        display.transport().fail_output(false);
+        display.transport().fail_feature(true);
+        assert!(writes(&display).is_empty());
+    }
+
+    #[test]
+    fn fails_if_no_report_path_works() {
+        let display = Display::new(MockTransport::new());
+        display.transport().fail_output(true);
+        display.transport().fail_feature(true);
+        let problems = writes(&display);
+        assert_eq!(
+            problems,
+            ["/dev/hidraw3: neither the output report nor the feature report could be written."]
+        );
+
+        // A short write counts as failed
+        display.transport().fail_output(false);
+        display.transport().short_write(Some(10));
+        assert_eq!(writes(&display).len(), 1);
+
+        let error = verdict(&problems).unwrap_err();
+        assert!(matches!(error, CliError::ProblemsFound(1)));
+        assert_eq!(error.exit_code(), EXIT_FAILURE);
+        assert!(verdict(&[]).is_ok());
+    }
+
+    #[test]
+    fn writes_through_the_mapping() {
+        let mut display = Display::new(MockTransport::new());
+        display.set_mapping(Mapping::from_table(&[(0, 0), (100, 50)]).unwrap());
+        assert!(writes(&display).is_empty());
+        let report = brightness_report(20).to_vec();
+        assert_eq!(
+            display.transport().reports(),
+            [Report::Output(report.clone()), Report::Feature(report)]
+        );
+    }
+
+    #[test]
+    fn reports_touch_screen_problems() {
+        let mouse = interface("/dev/hidraw4", 0x01);
+        let touch = interface("/dev/hidraw5", DIGITIZERS);
+        let problems = |interfaces: &[(&DisplayInfo, Touch)]| {
+            let mut problems = Vec::new();
+            touch_problems(interfaces, &mut problems);
+            problems
+        };
+
+        assert!(problems(&[(&mouse, Touch::Missing), (&touch, Touch::Found)]).is_empty());
+        assert!(problems(&[(&mouse, Touch::Denied), (&touch, Touch::Found)]).is_empty());
+
+        let missing = problems(&[(&mouse, Touch::Missing)]);
+        assert_eq!(missing.len(), 1);
+        assert!(missing[0].starts_with("No touch screen interface"));
+        assert_eq!(problems(&[]), missing);
+
+        let wrong = problems(&[(&mouse, Touch::Missing), (&touch, Touch::Missing)]);
+        assert_eq!(wrong.len(), 2);
+        assert!(wrong[0].starts_with("/dev/hidraw5: the interface announces a touch screen"));
+
+        let failed = problems(&[
+            (&mouse, Touch::Failed(Error::NotConnected)),
+            (&touch, Touch::Found),
+        ]);
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].starts_with("/dev/hidraw4: "));
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::fs::{self, OpenOptions};
+    use std::io;
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+    use std::path::Path;
+    use std::process;
+
+    /// Prints the permissions of a hidraw node and which processes hold it.
+    /// Returns whether the current user was denied access.
+    pub fn check_node(path: &str, problems: &mut Vec<String>) -> bool {
+        if !path.starts_with("/dev/") {
+            return false;
+        }
+        match fs::metadata(path) {
+            Ok(metadata) => println!(
+                "    node: {} {}:{}",
+                mode_string(&metadata),
+                name_of("/etc/passwd", metadata.uid()),
+                name_of("/etc/group", metadata.gid())
+            ),
+            Err(e) => println!("    node: {}", e),
+        }
+        let denied = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(_) => {
+                println!("    access: read/write ok");
+                false
+            }
+            Err(e) => {
+                println!("    access: {}", e);
+                let denied = e.kind() == io::ErrorKind::PermissionDenied;
+                if denied {
+                    problems.push(format!(
+                        "{path}: the current user may not open the display. Run `sudo {} install-udev-rules`, then replug it.",
+                        env!("CARGO_PKG_NAME")
+                    ));
+                }
+                denied
+            }
+        };
+
+        let (holders, complete) = holders(Path::new(path));
+        for (pid, name) in &holders {
+            println!("    open by: {} ({})", pid, name);
+        }
+        if holders.is_empty() {
+            println!("    open by: no other process");
+        }
+        if !complete {
+            println!("    (processes of other users are only visible to root)");
+        }
+        denied
+    }
+
+    fn mode_string(metadata: &fs::Metadata) -> String {
+        let kind = if metadata.file_type().is_char_device() {
+            'c'
+        } else {
+            '-'
+        };
+        let mode = metadata.mode();
+        let bits: String = (0..9)
+            .map(|i| {
+                if mode & (0o400 >> i) == 0 {
+                    '-'
+                } else {
+                    ['r', 'w', 'x'][i % 3]
+                }
+            })
+            .collect();
+        format!("{kind}{bits}")
+    }
+
+    /// Looks up a user or group name in `/etc/passwd` or `/etc/group`.
+    fn name_of(database: &str, id: u32) -> String {
+        fs::read_to_string(database)
+            .ok()
+            .and_then(|contents| {
+                contents.lines().find_map(|line| {
+                    let mut fields = line.split(':');
+                    let name = fields.next()?;
+                    let entry_id = fields.nth(1)?;
+                    (entry_id.parse() == Ok(id)).then(|| name.to_string())
+                })
+            })
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    /// Returns the processes other than this one with `node` open, and
+    /// whether all processes could be inspected.
+    fn holders(node: &Path) -> (Vec<(u32, String)>, bool) {
+        let mut holders = Vec::new();
+        let mut complete = true;
+        let Ok(entries) = fs::read_dir("/proc") else {
+            return (holders, false);
+        };
+        for entry in entries.flatten() {
+            let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse().ok()) else {
+                continue;
+            };
+            if pid == process::id() {
+                continue;
+            }
+            let fds = match fs::read_dir(entry.path().join("fd")) {
+                Ok(fds) => fds,
+                Err(_) => {
+                    complete = false;
+                    continue;
+                }
+            };
+            let open = fds
+                .flatten()
+                .any(|fd| fs::read_link(fd.path()).is_ok_and(|target| target == node));
+            if open {
+                let name = fs::read_to_string(entry.path().join("comm")).unwrap_or_default();
+                holders.push((pid, name.trim().to_string()));
+            }
+        }
+        (holders, complete)
+    }
+}
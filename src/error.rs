@@ -0,0 +1,198 @@
+use hidapi::HidError;
+use std::fmt;
+
+/// Errors reported while talking to a WS170120 display.
+#[derive(Debug)]
+pub enum Error {
+    /// The HID API could not be initialized.
+    HidInit(HidError),
+    /// No device with the WS170120 vendor and product ID was found.
+    NotConnected,
+    /// The device exists but the current user may not access it.
+    AccessDenied { context: String, source: HidError },
+    /// Any other failure reported by the HID layer.
+    Device { context: String, source: HidError },
+    /// The brightness value is outside of the supported range.
+    InvalidBrightness(u8),
+    /// The output report was only partially written.
+    ShortWrite { written: usize, expected: usize },
+    /// Neither the output report nor the feature report could be sent.
+    WriteFailed { output: HidError, feature: HidError },
+    /// The brightness could not be read back from the device.
+    ReadFailed(HidError),
+    /// The device answered a query with a report we do not understand.
+    InvalidResponse(Vec<u8>),
+    /// None of the display's interfaces declares a touch screen.
+    NoTouchInterface,
+    /// A report descriptor could not be decoded.
+    InvalidDescriptor { offset: usize, message: String },
+}
+
+/// What kind of [`Error`] occurred, without its details.
+///
+/// Rendered as a short, stable identifier, e.g. `not-connected`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    HidInit,
+    NotConnected,
+    AccessDenied,
+    Device,
+    InvalidBrightness,
+    ShortWrite,
+    WriteFailed,
+    ReadFailed,
+    InvalidResponse,
+    NoTouchInterface,
+    InvalidDescriptor,
+}
+
+impl ErrorKind {
+    /// Every kind, in the order of the [`Error`] variants.
+    pub const ALL: [ErrorKind; 11] = [
+        ErrorKind::HidInit,
+        ErrorKind::NotConnected,
+        ErrorKind::AccessDenied,
+        ErrorKind::Device,
+        ErrorKind::InvalidBrightness,
+        ErrorKind::ShortWrite,
+        ErrorKind::WriteFailed,
+        ErrorKind::ReadFailed,
+        ErrorKind::InvalidResponse,
+        ErrorKind::NoTouchInterface,
+        ErrorKind::InvalidDescriptor,
+    ];
+
+    /// Returns the identifier of this kind.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::HidInit => "hid-init",
+            ErrorKind::NotConnected => "not-connected",
+            ErrorKind::AccessDenied => "access-denied",
+            ErrorKind::Device => "device",
+            ErrorKind::InvalidBrightness => "invalid-brightness",
+            ErrorKind::ShortWrite => "short-write",
+            ErrorKind::WriteFailed => "write-failed",
+            ErrorKind::ReadFailed => "read-failed",
+            ErrorKind::InvalidResponse => "invalid-response",
+            ErrorKind::NoTouchInterface => "no-touch-interface",
+            ErrorKind::InvalidDescriptor => "invalid-descriptor",
+        }
+    }
+
+    /// Reads an identifier back, `None` if no kind has this name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Error {
+    /// The kind of error, e.g. [`ErrorKind::NotConnected`].
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::HidInit(_) => ErrorKind::HidInit,
+            Error::NotConnected => ErrorKind::NotConnected,
+            Error::AccessDenied { .. } => ErrorKind::AccessDenied,
+            Error::Device { .. } => ErrorKind::Device,
+            Error::InvalidBrightness(_) => ErrorKind::InvalidBrightness,
+            Error::ShortWrite { .. } => ErrorKind::ShortWrite,
+            Error::WriteFailed { .. } => ErrorKind::WriteFailed,
+            Error::ReadFailed(_) => ErrorKind::ReadFailed,
+            Error::InvalidResponse(_) => ErrorKind::InvalidResponse,
+            Error::NoTouchInterface => ErrorKind::NoTouchInterface,
+            Error::InvalidDescriptor { .. } => ErrorKind::InvalidDescriptor,
+        }
+    }
+
+    /// Classifies a HID error that occurred while doing `context`.
+    pub(crate) fn from_device(context: &str, source: HidError) -> Self {
+        let err_str = source.to_string().to_lowercase();
+        let context = context.to_string();
+        if err_str.contains("access denied")
+            || err_str.contains("exclusive access")
+            || err_str.contains("permission denied")
+        {
+            Error::AccessDenied { context, source }
+        } else {
+            Error::Device { context, source }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::HidInit(e) => write!(f, "Failed to initialize HID API: {e}"),
+            Error::NotConnected => write!(f, "Waveshare monitor WS170120 is not connected."),
+            Error::AccessDenied { context, source } => {
+                write!(f, "{context}: Device access denied. ")?;
+                if cfg!(target_os = "linux") {
+                    write!(
+                        f,
+                        "Allow access with `sudo ws170120-ctl install-udev-rules`, or try running with elevated privileges (sudo). "
+                    )?;
+                } else {
+                    write!(f, "Try running with elevated privileges (sudo). ")?;
+                }
+                write!(f, "Error message was {source:?}")
+            }
+            Error::Device { context, source } => write!(f, "{context}: {source}"),
+            Error::InvalidBrightness(value) => write!(
+                f,
+                "Brightness {value} is out of range, expected a value between 0 and 100."
+            ),
+            Error::ShortWrite { written, expected } => write!(
+                f,
+                "Unexpected result {written} from writing brightness data, expected {expected}."
+            ),
+            Error::WriteFailed { output, feature } => write!(
+                f,
+                "Failed to write brightness data via both regular write and feature report. Write error: {output}, Feature report error: {feature}"
+            ),
+            Error::ReadFailed(e) => write!(f, "Failed to read brightness from device: {e}"),
+            Error::InvalidResponse(data) => {
+                write!(f, "Unexpected brightness report from device: {data:02x?}")
+            }
+            Error::NoTouchInterface => write!(
+                f,
+                "No touch screen interface found on the Waveshare monitor WS170120."
+            ),
+            Error::InvalidDescriptor { offset, message } => {
+                write!(f, "Invalid report descriptor at byte {offset}: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::HidInit(e)
+            | Error::AccessDenied { source: e, .. }
+            | Error::Device { source: e, .. }
+            | Error::ReadFailed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_names_back() {
+        for kind in ErrorKind::ALL {
+            assert_eq!(ErrorKind::from_name(kind.name()), Some(kind));
+        }
+        assert_eq!(ErrorKind::from_name("io"), None);
+        assert_eq!(Error::NotConnected.kind().to_string(), "not-connected");
+    }
+}
@@ -0,0 +1,249 @@
+//! Gradual brightness transitions.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Number of reports sent per second while fading, unless configured otherwise.
+pub const DEFAULT_STEPS_PER_SECOND: u32 = 25;
+
+/// How intermediate brightness values are spread over a fade.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Curve {
+    /// Constant rate of change in brightness percentage.
+    #[default]
+    Linear,
+    /// Starts and ends slowly, fastest in the middle.
+    EaseInOut,
+    /// Constant rate of change in perceived lightness (CIE L*), which avoids
+    /// the apparent jump at the dark end of a linear fade.
+    Perceptual,
+}
+
+impl Curve {
+    /// Returns the brightness at progress `t` (0.0 to 1.0) of a fade from
+    /// `from` to `to`.
+    pub fn interpolate(self, from: f64, to: f64, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Curve::Linear => lerp(from, to, t),
+            Curve::EaseInOut => lerp(from, to, t * t * (3.0 - 2.0 * t)),
+            Curve::Perceptual => luminance(lerp(lightness(from), lightness(to), t)),
+        }
+    }
+}
+
+fn lerp(from: f64, to: f64, t: f64) -> f64 {
+    from + (to - from) * t
+}
+
+/// CIE 1976 lightness (0-100) of a relative luminance given in percent.
+fn lightness(luminance: f64) -> f64 {
+    let y = luminance / 100.0;
+    if y > 216.0 / 24389.0 {
+        116.0 * y.cbrt() - 16.0
+    } else {
+        y * 24389.0 / 27.0
+    }
+}
+
+/// Inverse of [`lightness`].
+pub(crate) fn luminance(lightness: f64) -> f64 {
+    let y = if lightness > 8.0 {
+        ((lightness + 16.0) / 116.0).powi(3)
+    } else {
+        lightness * 27.0 / 24389.0
+    };
+    y * 100.0
+}
+
+impl FromStr for Curve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Curve::Linear),
+            "ease-in-out" => Ok(Curve::EaseInOut),
+            "perceptual" => Ok(Curve::Perceptual),
+            _ => Err(format!(
+                "unknown curve '{s}', expected linear, ease-in-out or perceptual"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Curve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Curve::Linear => "linear",
+            Curve::EaseInOut => "ease-in-out",
+            Curve::Perceptual => "perceptual",
+        })
+    }
+}
+
+/// Parameters of a brightness fade.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fade {
+    pub duration: Duration,
+    pub curve: Curve,
+    pub steps_per_second: u32,
+}
+
+impl Fade {
+    pub fn new(duration: Duration, curve: Curve) -> Self {
+        Self {
+            duration,
+            curve,
+            steps_per_second: DEFAULT_STEPS_PER_SECOND,
+        }
+    }
+
+    /// Returns the brightness values of a fade from `from` to `to`, each with
+    /// its offset from the start of the fade.
+    ///
+    /// Consecutive duplicates are dropped, so a short range yields fewer
+    /// steps than the step rate would allow. The last step is always `to`.
+    pub fn steps(&self, from: u8, to: u8) -> Vec<(Duration, u8)> {
+        let count = (self.duration.as_secs_f64() * f64::from(self.steps_per_second)).ceil() as u32;
+        let mut steps: Vec<(Duration, u8)> = Vec::new();
+        for i in 1..=count.max(1) {
+            let t = f64::from(i) / f64::from(count.max(1));
+            let value = self
+                .curve
+                .interpolate(f64::from(from), f64::from(to), t)
+                .round() as u8;
+            if steps.last().map(|&(_, last)| last) != Some(value) {
+                steps.push((self.duration.mul_f64(t), value));
+            }
+        }
+        if steps.last().map(|&(_, last)| last) != Some(to) {
+            steps.push((self.duration, to));
+        }
+        steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fade(millis: u64, curve: Curve, steps_per_second: u32) -> Fade {
+        Fade {
+            duration: Duration::from_millis(millis),
+            curve,
+            steps_per_second,
+        }
+    }
+
+    fn values(steps: &[(Duration, u8)]) -> Vec<u8> {
+        steps.iter().map(|&(_, value)| value).collect()
+    }
+
+    #[test]
+    fn interpolates_linearly() {
+        assert_eq!(Curve::Linear.interpolate(20.0, 80.0, 0.0), 20.0);
+        assert_eq!(Curve::Linear.interpolate(20.0, 80.0, 0.25), 35.0);
+        assert_eq!(Curve::Linear.interpolate(80.0, 20.0, 0.5), 50.0);
+        assert_eq!(Curve::Linear.interpolate(20.0, 80.0, 1.0), 80.0);
+        // Progress is clamped
+        assert_eq!(Curve::Linear.interpolate(20.0, 80.0, -1.0), 20.0);
+        assert_eq!(Curve::Linear.interpolate(20.0, 80.0, 2.0), 80.0);
+    }
+
+    #[test]
+    fn eases_in_and_out() {
+        let ease = |t| Curve::EaseInOut.interpolate(0.0, 100.0, t);
+        assert_eq!(ease(0.0), 0.0);
+        assert_eq!(ease(0.5), 50.0);
+        assert_eq!(ease(1.0), 100.0);
+        // Slower than linear at the ends, faster in the middle
+        assert!((ease(0.25) - 15.625).abs() < 1e-9);
+        assert!((ease(0.75) - 84.375).abs() < 1e-9);
+        assert!(ease(0.55) - ease(0.45) > 10.0);
+        assert_eq!(
+            Curve::EaseInOut.interpolate(100.0, 0.0, 0.25),
+            100.0 - ease(0.25)
+        );
+    }
+
+    #[test]
+    fn moves_evenly_in_lightness() {
+        let perceptual = |t| Curve::Perceptual.interpolate(0.0, 100.0, t);
+        assert!(perceptual(0.0).abs() < 1e-9);
+        assert!((perceptual(1.0) - 100.0).abs() < 1e-9);
+        // Half the lightness is about 18% luminance
+        assert!((perceptual(0.5) - 18.42).abs() < 0.01);
+        for luminance in [0.0, 0.5, 1.0, 18.42, 50.0, 100.0] {
+            assert!((super::luminance(lightness(luminance)) - luminance).abs() < 1e-9);
+        }
+        // Evenly spaced in lightness between any two values
+        let (from, to) = (10.0, 60.0);
+        let middle = Curve::Perceptual.interpolate(from, to, 0.5);
+        assert!((lightness(middle) - (lightness(from) + lightness(to)) / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parses_curves() {
+        for curve in [Curve::Linear, Curve::EaseInOut, Curve::Perceptual] {
+            assert_eq!(curve.to_string().parse::<Curve>(), Ok(curve));
+        }
+        assert!("ease".parse::<Curve>().is_err());
+    }
+
+    #[test]
+    fn limits_the_step_rate() {
+        let steps = fade(1000, Curve::Linear, 10).steps(0, 100);
+        assert_eq!(steps.len(), 10);
+        assert_eq!(values(&steps), [10, 20, 30, 40, 50, 60, 70, 80, 90, 100]);
+        assert_eq!(steps[0].0, Duration::from_millis(100));
+        assert_eq!(steps[9].0, Duration::from_secs(1));
+
+        // A partial step interval still counts as a step
+        assert_eq!(fade(250, Curve::Linear, 10).steps(0, 90).len(), 3);
+    }
+
+    #[test]
+    fn drops_repeated_values() {
+        // 50 steps for a change of 5 leave one step per value, starting
+        // with the first one rounding to the start value
+        let steps = fade(1000, Curve::Linear, 50).steps(40, 45);
+        assert_eq!(values(&steps), [40, 41, 42, 43, 44, 45]);
+        assert!(steps.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        let steps = fade(1000, Curve::Linear, 50).steps(45, 40);
+        assert_eq!(values(&steps), [45, 44, 43, 42, 41, 40]);
+
+        let steps = fade(1000, Curve::Linear, 50).steps(30, 30);
+        assert_eq!(values(&steps), [30]);
+    }
+
+    #[test]
+    fn ends_at_the_target() {
+        for curve in [Curve::Linear, Curve::EaseInOut, Curve::Perceptual] {
+            for (from, to) in [(0, 100), (100, 0), (3, 97), (50, 51)] {
+                let steps = fade(700, curve, 30).steps(from, to);
+                let &(offset, last) = steps.last().unwrap();
+                assert_eq!(last, to, "{curve} {from}-{to}");
+                assert!(offset <= Duration::from_millis(700));
+                assert!(steps.len() <= 21, "{curve} {from}-{to}");
+            }
+        }
+    }
+
+    #[test]
+    fn jumps_without_a_duration() {
+        assert_eq!(
+            fade(0, Curve::Linear, 30).steps(10, 90),
+            [(Duration::ZERO, 90)]
+        );
+        assert_eq!(
+            Fade::new(Duration::ZERO, Curve::Perceptual).steps(90, 10),
+            [(Duration::ZERO, 10)]
+        );
+        assert_eq!(
+            fade(500, Curve::Linear, 0).steps(10, 90),
+            [(Duration::from_millis(500), 90)]
+        );
+    }
+}
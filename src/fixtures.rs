@@ -0,0 +1,273 @@
+//! Report descriptors and reports shared by the tests.
+//!
+//! The constants below are synthetic: they were assembled by hand after the
+//! layout of the display's interfaces, not read from a device. The display
+//! has a vendor defined interface taking the brightness report, and a touch
+//! controller that also exposes a mouse. The touch screen sends two contacts
+//! per report, followed by the number of contacts of the frame, and splits
+//! frames of up to five contacts over several reports.
+//!
+//! Captures of real devices are kept in `testdata/` as recordings of
+//! `hid-recorder` from [hid-tools](https://gitlab.freedesktop.org/libevdev/hid-tools),
+//! and read with [`Recording`].
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The control interface: a 37 byte input, output and feature report with
+/// the ID 4, the first byte of [`CONTROL_MAGIC`](crate::CONTROL_MAGIC).
+pub const CONTROL_DESCRIPTOR: &[u8] = &[
+    0x06, 0x00, 0xff, // Usage Page (Vendor Defined 0xff00)
+    0x09, 0x01, // Usage (0x01)
+    0xa1, 0x01, // Collection (Application)
+    0x85, 0x04, //   Report ID (4)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xff, 0x00, //   Logical Maximum (255)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x25, //   Report Count (37)
+    0x09, 0x02, //   Usage (0x02)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x09, 0x03, //   Usage (0x03)
+    0x91, 0x02, //   Output (Data, Variable, Absolute)
+    0x09, 0x04, //   Usage (0x04)
+    0xb1, 0x02, //   Feature (Data, Variable, Absolute)
+    0xc0, // End Collection
+];
+
+/// The touch controller: a mouse with the report ID 1, and a touch screen
+/// with the report ID 2.
+pub const TOUCH_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x02, // Usage (Mouse)
+    0xa1, 0x01, // Collection (Application)
+    0x85, 0x01, //   Report ID (1)
+    0x09, 0x01, //   Usage (Pointer)
+    0xa1, 0x00, //   Collection (Physical)
+    0x05, 0x09, //     Usage Page (Button)
+    0x19, 0x01, //     Usage Minimum (1)
+    0x29, 0x02, //     Usage Maximum (2)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x95, 0x02, //     Report Count (2)
+    0x75, 0x01, //     Report Size (1)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x95, 0x01, //     Report Count (1)
+    0x75, 0x06, //     Report Size (6)
+    0x81, 0x03, //     Input (Constant, Variable, Absolute)
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x09, 0x30, //     Usage (X)
+    0x09, 0x31, //     Usage (Y)
+    0x16, 0x00, 0x00, //     Logical Minimum (0)
+    0x26, 0xff, 0x0f, //     Logical Maximum (4095)
+    0x75, 0x10, //     Report Size (16)
+    0x95, 0x02, //     Report Count (2)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0xc0, //   End Collection
+    0xc0, // End Collection
+    0x05, 0x0d, // Usage Page (Digitizers)
+    0x09, 0x04, // Usage (Touch Screen)
+    0xa1, 0x01, // Collection (Application)
+    0x85, 0x02, //   Report ID (2)
+    0x09, 0x22, //   Usage (Finger)
+    0xa1, 0x02, //   Collection (Logical)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x75, 0x01, //     Report Size (1)
+    0x95, 0x01, //     Report Count (1)
+    0x09, 0x42, //     Usage (Tip Switch)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x09, 0x32, //     Usage (In Range)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x09, 0x47, //     Usage (Confidence)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x95, 0x05, //     Report Count (5)
+    0x81, 0x03, //     Input (Constant, Variable, Absolute)
+    0x75, 0x08, //     Report Size (8)
+    0x95, 0x01, //     Report Count (1)
+    0x25, 0x7f, //     Logical Maximum (127)
+    0x09, 0x51, //     Usage (Contact Identifier)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x26, 0xff, 0x0f, //     Logical Maximum (4095)
+    0x75, 0x10, //     Report Size (16)
+    0x55, 0x0e, //     Unit Exponent (-2)
+    0x65, 0x11, //     Unit (Centimeter)
+    0x35, 0x00, //     Physical Minimum (0)
+    0x46, 0xb5, 0x04, //     Physical Maximum (1205)
+    0x09, 0x30, //     Usage (X)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x46, 0x8a, 0x03, //     Physical Maximum (906)
+    0x09, 0x31, //     Usage (Y)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0xc0, //   End Collection
+    0x05, 0x0d, //   Usage Page (Digitizers)
+    0x09, 0x22, //   Usage (Finger)
+    0xa1, 0x02, //   Collection (Logical)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x75, 0x01, //     Report Size (1)
+    0x95, 0x01, //     Report Count (1)
+    0x09, 0x42, //     Usage (Tip Switch)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x09, 0x32, //     Usage (In Range)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x09, 0x47, //     Usage (Confidence)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x95, 0x05, //     Report Count (5)
+    0x81, 0x03, //     Input (Constant, Variable, Absolute)
+    0x75, 0x08, //     Report Size (8)
+    0x95, 0x01, //     Report Count (1)
+    0x25, 0x7f, //     Logical Maximum (127)
+    0x09, 0x51, //     Usage (Contact Identifier)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x26, 0xff, 0x0f, //     Logical Maximum (4095)
+    0x75, 0x10, //     Report Size (16)
+    0x55, 0x0e, //     Unit Exponent (-2)
+    0x65, 0x11, //     Unit (Centimeter)
+    0x35, 0x00, //     Physical Minimum (0)
+    0x46, 0xb5, 0x04, //     Physical Maximum (1205)
+    0x09, 0x30, //     Usage (X)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0x46, 0x8a, 0x03, //     Physical Maximum (906)
+    0x09, 0x31, //     Usage (Y)
+    0x81, 0x02, //     Input (Data, Variable, Absolute)
+    0xc0, //   End Collection
+    0x05, 0x0d, //   Usage Page (Digitizers)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x05, //   Logical Maximum (5)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x01, //   Report Count (1)
+    0x09, 0x54, //   Usage (Contact Count)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x09, 0x55, //   Usage (Contact Count Maximum)
+    0xb1, 0x02, //   Feature (Data, Variable, Absolute)
+    0xc0, // End Collection
+];
+
+/// A touch report with contact 3 touching at 564, 1400 and contact 4 at
+/// 4095, 0, as read from the device including the report ID.
+pub const TWO_CONTACTS: [u8; 14] = [
+    0x02, // Report ID
+    0x07, 0x03, 0x34, 0x02, 0x78, 0x05, // Tip, in range, confident; ID 3; X; Y
+    0x07, 0x04, 0xff, 0x0f, 0x00, 0x00, // Tip, in range, confident; ID 4; X; Y
+    0x02, // Contact count
+];
+
+/// A mouse report, which is not a touch report.
+pub const MOUSE: [u8; 6] = [0x01, 0x01, 0x00, 0x08, 0x00, 0x08];
+
+/// State of a contact in [`touch_report`].
+#[derive(Debug, Clone, Copy)]
+pub struct Finger {
+    pub id: u8,
+    pub x: u16,
+    pub y: u16,
+    pub tip: bool,
+    pub confident: bool,
+}
+
+/// A contact touching the screen at `x`, `y`.
+pub fn finger(id: u8, x: u16, y: u16) -> Finger {
+    Finger {
+        id,
+        x,
+        y,
+        tip: true,
+        confident: true,
+    }
+}
+
+/// Builds a touch report carrying up to two `fingers` and the contact count
+/// `count`, leaving unused slots zeroed.
+pub fn touch_report(fingers: &[Finger], count: u8) -> Vec<u8> {
+    assert!(fingers.len() <= 2, "the touch screen has two slots");
+    let mut report = vec![0x02];
+    for index in 0..2 {
+        match fingers.get(index) {
+            Some(finger) => {
+                let flags = u8::from(finger.tip) | 0x02 | u8::from(finger.confident) << 2;
+                report.extend([flags, finger.id]);
+                report.extend(finger.x.to_le_bytes());
+                report.extend(finger.y.to_le_bytes());
+            }
+            None => report.extend([0; 6]),
+        }
+    }
+    report.push(count);
+    report
+}
+
+/// A device captured with `hid-recorder`: its report descriptor and the
+/// input reports it sent, including the report IDs.
+#[derive(Debug, Default, PartialEq)]
+pub struct Recording {
+    pub descriptor: Vec<u8>,
+    pub reports: Vec<Vec<u8>>,
+}
+
+impl Recording {
+    /// Reads the `R:` (descriptor) and `E:` (event) lines of a recording,
+    /// skipping the other lines. Each holds the byte count followed by the
+    /// bytes in hex; events start with a timestamp.
+    pub fn parse(text: &str) -> Recording {
+        let bytes = |fields: &str| -> Vec<u8> {
+            let mut fields = fields.split_whitespace();
+            let count: usize = fields.next().unwrap().parse().unwrap();
+            let bytes: Vec<u8> = fields
+                .map(|byte| u8::from_str_radix(byte, 16).unwrap())
+                .collect();
+            assert_eq!(bytes.len(), count, "line with {count} bytes");
+            bytes
+        };
+        let mut recording = Recording::default();
+        for line in text.lines() {
+            if let Some(descriptor) = line.strip_prefix("R: ") {
+                recording.descriptor = bytes(descriptor);
+            } else if let Some(event) = line.strip_prefix("E: ") {
+                let (_timestamp, report) = event.trim_start().split_once(' ').unwrap();
+                recording.reports.push(bytes(report));
+            }
+        }
+        recording
+    }
+
+    /// Returns the recordings (`*.hid`) in `testdata/`.
+    pub fn all() -> Vec<(PathBuf, Recording)> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let mut recordings: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "hid"))
+            .map(|path| {
+                let recording = Recording::parse(&fs::read_to_string(&path).unwrap());
+                (path, recording)
+            })
+            .collect();
+        recordings.sort_by(|(a, _), (b, _)| a.cmp(b));
+        recordings
+    }
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_recordings() {
+        let recording = Recording::parse(
+            "# WS170120 touch\n\
+             N: Test Device\n\
+             I: 3 0eef 0005\n\
+             R: 4 05 0d 09 04\n\
+             E: 000000.000000 6 01 01 00 08 00 08\n\
+             E: 000000.008012 2 02 00\n",
+        );
+        assert_eq!(
+            recording,
+            Recording {
+                descriptor: vec![0x05, 0x0d, 0x09, 0x04],
+                reports: vec![MOUSE.to_vec(), vec![0x02, 0x00]],
+            }
+        );
+    }
+}
@@ -0,0 +1,693 @@
+//! Serves the displays over HTTP, for web frontends.
+//!
+//! The API mirrors the command line and answers with the same JSON documents
+//! as `--output json`:
+//!
+//! - `GET /api/displays` lists the attached displays.
+//! - `GET /api/brightness` reads the brightness.
+//! - `PUT /api/brightness` sets it, with a body like `{"brightness": 40}` or
+//!   `{"step": -10}`.
+//! - `POST /api/fade` fades to a brightness, with a body like
+//!   `{"brightness": 40, "duration": "2s", "curve": "perceptual"}`.
+//! - `GET /api/events` is a WebSocket pushing every brightness change.
+//!
+//! The displays are chosen with the query parameters `serial`, `path` or
+//! `all`, like the command line options of the same name.
+//!
+//! Request bodies must be sent as `application/json`, and requests carrying
+//! an `Origin` other than the API's own are refused. Web pages of other
+//! origins can thus neither post a form to the API nor open the WebSocket,
+//! even when no token is required.
+
+use crate::cli_error::{CliError, Kind};
+use crate::output;
+use crate::panels::{Action, Panels, Request};
+use serde::{Deserialize, Serialize};
+use std::io::{Cursor, Read};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::{fs, thread};
+use tiny_http::{Header, Method, Response, Server, StatusCode};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+use ws170120::{Curve, ErrorKind, Fade, Selector};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8170";
+
+type JsonResponse = Response<Cursor<Vec<u8>>>;
+
+/// Largest request body accepted.
+const MAX_BODY: u64 = 4096;
+
+#[derive(clap::Args)]
+pub struct HttpArgs {
+    /// Also serve the HTTP API on this address [default: 127.0.0.1:8170]
+    #[arg(long, value_name = "ADDRESS", num_args = 0..=1, default_missing_value = DEFAULT_ADDRESS)]
+    pub http: Option<SocketAddr>,
+
+    /// Require the token in this file as `Authorization: Bearer <token>`
+    /// header or `token` query parameter
+    #[arg(long, value_name = "FILE", requires = "http")]
+    pub http_token_file: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SetBody {
+    brightness: Option<u8>,
+    step: Option<i16>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FadeBody {
+    brightness: u8,
+    duration: String,
+    curve: Option<String>,
+}
+
+/// A request that failed before reaching a display.
+struct Rejection {
+    status: u16,
+    error: CliError,
+}
+
+impl Rejection {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Rejection {
+            status,
+            error: CliError::InvalidArgument(message.into()),
+        }
+    }
+}
+
+impl From<CliError> for Rejection {
+    fn from(error: CliError) -> Self {
+        Rejection {
+            status: status(&error),
+            error,
+        }
+    }
+}
+
+/// The HTTP status for failing with `e`.
+fn status(e: &CliError) -> u16 {
+    match e.kind() {
+        Kind::InvalidArgument
+        | Kind::InvalidRequest
+        | Kind::Display(ErrorKind::InvalidBrightness) => 400,
+        Kind::Display(ErrorKind::AccessDenied) => 403,
+        Kind::Display(ErrorKind::NotConnected) => 404,
+        _ => 500,
+    }
+}
+
+/// Decodes `%XX` escapes and `+` in a query string component.
+fn decode(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Splits a request URL into its path and decoded query parameters.
+fn split_url(url: &str) -> (&str, Vec<(String, String)>) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let parameters = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(name), decode(value))
+        })
+        .collect();
+    (path, parameters)
+}
+
+fn selector(parameters: &[(String, String)]) -> Result<Selector, Rejection> {
+    let mut selectors = parameters
+        .iter()
+        .filter_map(|(name, value)| match name.as_str() {
+            "serial" => Some(Selector::Serial(value.clone())),
+            "path" => Some(Selector::Path(value.clone())),
+            "all" => Some(Selector::All),
+            _ => None,
+        });
+    let selector = selectors.next().unwrap_or_default();
+    if selectors.next().is_some() {
+        return Err(Rejection::new(
+            400,
+            "Only one of serial, path and all may be given.",
+        ));
+    }
+    Ok(selector)
+}
+
+fn header<'a>(request: &'a tiny_http::Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+/// Compares without giving away how much of the token matched.
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Returns the host and port of an `Origin` header value, `None` for an
+/// opaque origin such as `null`.
+fn origin_host(origin: &str) -> Option<&str> {
+    let (scheme, host) = origin.split_once("://")?;
+    matches!(scheme, "http" | "https").then_some(host.trim_end_matches('/'))
+}
+
+/// Refuses requests a web page of another origin made. Browsers send the
+/// `Origin` with such requests, including WebSocket handshakes; clients
+/// outside a browser usually send none.
+fn same_origin(request: &tiny_http::Request) -> Result<(), Rejection> {
+    let Some(origin) = header(request, "Origin") else {
+        return Ok(());
+    };
+    let host = header(request, "Host");
+    match origin_host(origin) {
+        Some(origin) if Some(origin) == host => Ok(()),
+        _ => Err(Rejection::new(
+            403,
+            format!("Requests from origin '{origin}' are not allowed."),
+        )),
+    }
+}
+
+fn authorized(
+    request: &tiny_http::Request,
+    parameters: &[(String, String)],
+    token: Option<&str>,
+) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    let bearer = header(request, "Authorization").and_then(|value| value.strip_prefix("Bearer "));
+    // Browsers cannot set headers on WebSocket connections
+    let parameter = parameters
+        .iter()
+        .find(|(name, _)| name == "token")
+        .map(|(_, value)| value.as_str());
+    [bearer, parameter]
+        .into_iter()
+        .flatten()
+        .any(|given| same_token(given.trim(), token))
+}
+
+fn body<T: for<'de> Deserialize<'de>>(request: &mut tiny_http::Request) -> Result<T, Rejection> {
+    // Forms can only send other types without a CORS preflight
+    let media_type = header(request, "Content-Type")
+        .and_then(|value| value.split(';').next())
+        .map(str::trim);
+    if !media_type.is_some_and(|media_type| media_type.eq_ignore_ascii_case("application/json")) {
+        return Err(Rejection::new(
+            415,
+            "Expected a request body of type application/json.",
+        ));
+    }
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_BODY)
+        .read_to_end(&mut body)
+        .map_err(|e| Rejection::new(400, format!("Reading the request failed: {e}")))?;
+    serde_json::from_slice(&body)
+        .map_err(|e| Rejection::new(400, format!("Invalid request body: {e}")))
+}
+
+fn json_response(status: u16, document: &impl Serialize) -> JsonResponse {
+    let json = serde_json::to_string(document).unwrap_or_default();
+    Response::from_string(json)
+        .with_status_code(StatusCode(status))
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+/// Carries out `request` on the displays chosen by `parameters`.
+fn execute(
+    panels: &Mutex<Panels>,
+    parameters: &[(String, String)],
+    request: Request,
+) -> Result<JsonResponse, Rejection> {
+    let selector = selector(parameters)?;
+    let outcomes = panels
+        .lock()
+        .unwrap()
+        .execute(&selector, &request)
+        .map_err(CliError::from)?;
+    // Like the exit status, the first failing display decides the status
+    let status = outcomes
+        .iter()
+        .find_map(|outcome| outcome.result.as_ref().err())
+        .map_or(200, self::status);
+    let response = json_response(status, &output::outcomes(&request, &outcomes));
+    Ok(response)
+}
+
+fn route(
+    request: &mut tiny_http::Request,
+    path: &str,
+    parameters: &[(String, String)],
+    panels: &Mutex<Panels>,
+) -> Result<JsonResponse, Rejection> {
+    match (request.method(), path) {
+        (Method::Get, "/api/displays") => {
+            let displays = panels.lock().unwrap().displays();
+            let response = json_response(200, &output::list(&displays));
+            Ok(response)
+        }
+        (Method::Get, "/api/brightness") => execute(
+            panels,
+            parameters,
+            Request {
+                action: Action::Get,
+                fade: None,
+            },
+        ),
+        (Method::Put, "/api/brightness") => {
+            let action = match body::<SetBody>(request)? {
+                SetBody {
+                    brightness: Some(brightness),
+                    step: None,
+                } => Action::Set(brightness),
+                SetBody {
+                    brightness: None,
+                    step: Some(step),
+                } => Action::Step(step),
+                _ => return Err(Rejection::new(400, "Expected either brightness or step.")),
+            };
+            execute(panels, parameters, Request { action, fade: None })
+        }
+        (Method::Post, "/api/fade") => {
+            let fade = body::<FadeBody>(request)?;
+            let duration = humantime::parse_duration(&fade.duration).map_err(|e| {
+                Rejection::new(400, format!("Invalid duration '{}': {e}", fade.duration))
+            })?;
+            let curve: Curve = match &fade.curve {
+                Some(curve) => curve.parse().map_err(|e: String| Rejection::new(400, e))?,
+                None => Curve::default(),
+            };
+            execute(
+                panels,
+                parameters,
+                Request {
+                    action: Action::Set(fade.brightness),
+                    fade: Some(Fade::new(duration, curve)),
+                },
+            )
+        }
+        (_, "/api/displays" | "/api/brightness" | "/api/fade" | "/api/events") => {
+            Err(Rejection::new(405, "Method not allowed."))
+        }
+        _ => Err(Rejection::new(404, format!("Unknown endpoint {path}."))),
+    }
+}
+
+/// Upgrades `request` to a WebSocket and sends every brightness change on
+/// it until the client goes away.
+fn events(request: tiny_http::Request, panels: &Mutex<Panels>) {
+    let Some(key) = header(&request, "Sec-WebSocket-Key") else {
+        let response = json_response(
+            400,
+            &output::failure(&CliError::InvalidArgument(
+                "Expected a WebSocket connection.".to_string(),
+            )),
+        );
+        let _ = request.respond(response);
+        return;
+    };
+    let response = Response::empty(101)
+        .with_header(Header::from_bytes("Upgrade", "websocket").unwrap())
+        .with_header(Header::from_bytes("Connection", "Upgrade").unwrap())
+        .with_header(
+            Header::from_bytes("Sec-WebSocket-Accept", derive_accept_key(key.as_bytes())).unwrap(),
+        );
+    let changes = panels.lock().unwrap().subscribe();
+    let stream = request.upgrade("websocket", response);
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+    for change in changes {
+        let json = serde_json::to_string(&output::change(&change)).unwrap_or_default();
+        if socket.send(Message::text(json)).is_err() {
+            return;
+        }
+    }
+}
+
+fn serve(
+    mut request: tiny_http::Request,
+    panels: &Mutex<Panels>,
+    token: Option<&str>,
+    verbose: u8,
+) {
+    let url = request.url().to_string();
+    let (path, parameters) = split_url(&url);
+    if verbose > 1 {
+        println!("HTTP {} {}", request.method(), path);
+    }
+    let result = if !authorized(&request, &parameters, token) {
+        Err(Rejection::new(401, "Missing or wrong token."))
+    } else if let Err(rejection) = same_origin(&request) {
+        Err(rejection)
+    } else if path == "/api/events" && *request.method() == Method::Get {
+        return events(request, panels);
+    } else {
+        route(&mut request, path, &parameters, panels)
+    };
+    let response = result.unwrap_or_else(|rejection| {
+        json_response(rejection.status, &output::failure(&rejection.error))
+    });
+    if let Err(e) = request.respond(response) {
+        if verbose > 0 {
+            eprintln!("Sending HTTP response failed: {}", e);
+        }
+    }
+}
+
+/// Starts serving the HTTP API on the address given in `args`, if any, in
+/// the background.
+pub fn start(panels: Arc<Mutex<Panels>>, args: &HttpArgs, verbose: u8) -> Result<(), CliError> {
+    let Some(address) = args.http else {
+        return Ok(());
+    };
+    let token = match &args.http_token_file {
+        Some(path) => {
+            let token = fs::read_to_string(path).map_err(|e| CliError::InvalidFile {
+                path: path.clone(),
+                message: e.to_string(),
+            })?;
+            let token = token.trim().to_string();
+            if token.is_empty() {
+                return Err(CliError::InvalidFile {
+                    path: path.clone(),
+                    message: "the token is empty".to_string(),
+                });
+            }
+            Some(token)
+        }
+        None => None,
+    };
+    let server = Server::http(address).map_err(CliError::Http)?;
+    if verbose > 0 {
+        println!("Serving the HTTP API on http://{}/api/.", address);
+    }
+
+    let token: Option<Arc<str>> = token.map(Arc::from);
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let panels = Arc::clone(&panels);
+            let token = token.clone();
+            // Fades and WebSockets keep their thread busy for a while
+            thread::spawn(move || serve(request, &panels, token.as_deref(), verbose));
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use tiny_http::TestRequest;
+    use ws170120::Error;
+
+    fn parameters(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn request(method: Method, url: &str, headers: &[(&str, &str)]) -> TestRequest {
+        headers.iter().fold(
+            TestRequest::new().with_method(method).with_path(url),
+            |request, (name, value)| {
+                request.with_header(Header::from_bytes(*name, *value).unwrap())
+            },
+        )
+    }
+
+    /// Routes `request`, returning the status and JSON document of the
+    /// response.
+    fn respond(panels: &Mutex<Panels>, request: TestRequest) -> (u16, Value) {
+        let mut request: tiny_http::Request = request.into();
+        let url = request.url().to_string();
+        let (path, parameters) = split_url(&url);
+        match route(&mut request, path, &parameters, panels) {
+            Ok(response) => (
+                response.status_code().0,
+                serde_json::from_slice(&response.into_reader().into_inner()).unwrap(),
+            ),
+            Err(rejection) => (
+                rejection.status,
+                json!({ "kind": rejection.error.kind().name() }),
+            ),
+        }
+    }
+
+    #[test]
+    fn splits_urls() {
+        assert_eq!(split_url("/api/brightness"), ("/api/brightness", vec![]));
+        assert_eq!(
+            split_url("/api/brightness?serial=A%20B+C&all&token=a%3Db&"),
+            (
+                "/api/brightness",
+                parameters(&[("serial", "A B C"), ("all", ""), ("token", "a=b")])
+            )
+        );
+        // Broken escapes are taken as they are
+        assert_eq!(
+            split_url("/?path=%2Fdev%2Fhidraw3%zz%4"),
+            ("/", parameters(&[("path", "/dev/hidraw3%zz%4")]))
+        );
+    }
+
+    #[test]
+    fn picks_the_selector() {
+        let select = |pairs: &[(&str, &str)]| selector(&parameters(pairs)).ok();
+        assert_eq!(select(&[]), Some(Selector::First));
+        assert_eq!(select(&[("token", "x")]), Some(Selector::First));
+        assert_eq!(
+            select(&[("serial", "0123")]),
+            Some(Selector::Serial("0123".to_string()))
+        );
+        assert_eq!(
+            select(&[("path", "/dev/hidraw3")]),
+            Some(Selector::Path("/dev/hidraw3".to_string()))
+        );
+        assert_eq!(select(&[("all", "")]), Some(Selector::All));
+        assert!(select(&[("all", ""), ("serial", "0123")]).is_none());
+        assert!(select(&[("path", "a"), ("path", "b")]).is_none());
+    }
+
+    #[test]
+    fn checks_the_token() {
+        assert!(same_token("secret", "secret"));
+        assert!(!same_token("secreT", "secret"));
+        assert!(!same_token("secret2", "secret"));
+        assert!(!same_token("", "secret"));
+
+        let check = |headers: &[(&str, &str)], pairs: &[(&str, &str)], token| {
+            let request: tiny_http::Request = request(Method::Get, "/", headers).into();
+            authorized(&request, &parameters(pairs), token)
+        };
+        assert!(check(&[], &[], None));
+        assert!(!check(&[], &[], Some("secret")));
+        assert!(check(
+            &[("Authorization", "Bearer secret")],
+            &[],
+            Some("secret")
+        ));
+        assert!(!check(
+            &[("Authorization", "Basic secret")],
+            &[],
+            Some("secret")
+        ));
+        assert!(!check(
+            &[("Authorization", "Bearer wrong")],
+            &[],
+            Some("secret")
+        ));
+        assert!(check(&[], &[("token", "secret")], Some("secret")));
+        assert!(!check(&[], &[("token", "wrong")], Some("secret")));
+    }
+
+    #[test]
+    fn refuses_other_origins() {
+        let check = |headers: &[(&str, &str)]| {
+            let request: tiny_http::Request = request(Method::Post, "/api/fade", headers).into();
+            same_origin(&request).map_err(|rejection| rejection.status)
+        };
+        assert_eq!(check(&[("Host", "127.0.0.1:8170")]), Ok(()));
+        assert_eq!(
+            check(&[
+                ("Host", "127.0.0.1:8170"),
+                ("Origin", "http://127.0.0.1:8170")
+            ]),
+            Ok(())
+        );
+        for origin in [
+            "http://evil.example",
+            "http://127.0.0.1:8000",
+            "null",
+            "file://",
+        ] {
+            assert_eq!(
+                check(&[("Host", "127.0.0.1:8170"), ("Origin", origin)]),
+                Err(403),
+                "{origin}"
+            );
+        }
+        assert_eq!(check(&[("Origin", "http://127.0.0.1:8170")]), Err(403));
+    }
+
+    #[test]
+    fn maps_error_kinds_to_statuses() {
+        let cases = [
+            (CliError::InvalidArgument(String::new()), 400),
+            (CliError::from(Error::InvalidBrightness(101)), 400),
+            (
+                CliError::Daemon {
+                    kind: Kind::InvalidRequest,
+                    message: String::new(),
+                },
+                400,
+            ),
+            (
+                CliError::Daemon {
+                    kind: Kind::Display(ErrorKind::AccessDenied),
+                    message: String::new(),
+                },
+                403,
+            ),
+            (CliError::from(Error::NotConnected), 404),
+            (
+                CliError::from(Error::ReadFailed(hidapi::HidError::HidApiError {
+                    message: String::new(),
+                })),
+                500,
+            ),
+        ];
+        for (error, expected) in cases {
+            assert_eq!(status(&error), expected, "{error:?}");
+        }
+    }
+
+    #[test]
+    fn routes_requests() {
+        let panels = Mutex::new(Panels::without_displays().unwrap());
+        let json = [("Content-Type", "application/json; charset=utf-8")];
+
+        assert_eq!(
+            respond(&panels, request(Method::Get, "/api/displays", &[])),
+            (200, json!({ "displays": [] }))
+        );
+        assert_eq!(
+            respond(&panels, request(Method::Get, "/api/brightness", &[])),
+            (404, json!({ "kind": "not-connected" }))
+        );
+        assert_eq!(
+            respond(
+                &panels,
+                request(Method::Put, "/api/brightness", &json).with_body(r#"{"step": -10}"#)
+            ),
+            (404, json!({ "kind": "not-connected" }))
+        );
+        assert_eq!(
+            respond(
+                &panels,
+                request(Method::Get, "/api/brightness?all&serial=1", &[])
+            ),
+            (400, json!({ "kind": "invalid-argument" }))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_bodies() {
+        let panels = Mutex::new(Panels::without_displays().unwrap());
+        let json = [("Content-Type", "application/json")];
+        let cases = [
+            (
+                Method::Put,
+                "/api/brightness",
+                &[][..],
+                r#"{"brightness": 40}"#,
+                415,
+            ),
+            (
+                Method::Post,
+                "/api/fade",
+                &[("Content-Type", "text/plain")],
+                r#"{"brightness": 40, "duration": "1s"}"#,
+                415,
+            ),
+            (Method::Put, "/api/brightness", &json, "40", 400),
+            (Method::Put, "/api/brightness", &json, "{}", 400),
+            (
+                Method::Put,
+                "/api/brightness",
+                &json,
+                r#"{"brightness": 40, "step": 5}"#,
+                400,
+            ),
+            (
+                Method::Put,
+                "/api/brightness",
+                &json,
+                r#"{"level": 40}"#,
+                400,
+            ),
+            (
+                Method::Post,
+                "/api/fade",
+                &json,
+                r#"{"brightness": 40, "duration": "soon"}"#,
+                400,
+            ),
+            (
+                Method::Post,
+                "/api/fade",
+                &json,
+                r#"{"brightness": 40, "duration": "1s", "curve": "wobbly"}"#,
+                400,
+            ),
+            (Method::Delete, "/api/brightness", &[], "", 405),
+            (Method::Get, "/api/fade", &[], "", 405),
+            (Method::Get, "/api/unknown", &[], "", 404),
+        ];
+        for (method, url, headers, body, expected) in cases {
+            let (status, _) = respond(
+                &panels,
+                request(method.clone(), url, headers).with_body(body),
+            );
+            assert_eq!(status, expected, "{method} {url} {body}");
+        }
+    }
+}
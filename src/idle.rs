@@ -0,0 +1,358 @@
+//! Dims the displays while nobody uses them.
+//!
+//! Activity is any touch on the display's touch screen, or any event of the
+//! input devices given with `--input`. Each source is read by a thread of its
+//! own that reports to the main loop over a channel.
+
+use crate::cli_error::CliError;
+use crate::config::Config;
+use crate::panels::{Action, Outcome, Request};
+use hidapi::HidApi;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use ws170120::touch::Touchscreen;
+use ws170120::{DisplayInfo, Error, Fade, Selector, MAX_BRIGHTNESS};
+
+const DEFAULT_AFTER: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_LEVEL: u8 = 10;
+
+#[derive(clap::Args)]
+pub struct IdleArgs {
+    /// Dim after this long without activity, e.g. 10m [default: 5m]
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub after: Option<Duration>,
+
+    /// Brightness while idle (0-100) [default: 10]
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=MAX_BRIGHTNESS as i64))]
+    pub level: Option<u8>,
+
+    /// Also count events of this input device as activity, e.g.
+    /// /dev/input/event3; may be given several times
+    #[arg(long = "input", value_name = "PATH")]
+    pub inputs: Vec<PathBuf>,
+
+    /// Don't watch the touch screen, only the input devices
+    #[arg(long)]
+    pub no_touch: bool,
+}
+
+/// The options of [`IdleArgs`] with the defaults from the configuration
+/// filled in.
+pub struct Settings {
+    pub after: Duration,
+    pub level: u8,
+    pub inputs: Vec<PathBuf>,
+    pub touch: bool,
+}
+
+impl Settings {
+    pub fn new(args: &IdleArgs, config: &Config) -> Result<Self, CliError> {
+        let inputs = if args.inputs.is_empty() {
+            config.idle.inputs.clone()
+        } else {
+            args.inputs.clone()
+        };
+        if args.no_touch && inputs.is_empty() {
+            return Err(CliError::InvalidArgument(
+                "Nothing to watch: --no-touch needs at least one --input.".to_string(),
+            ));
+        }
+        Ok(Settings {
+            after: args.after.or(config.idle.after).unwrap_or(DEFAULT_AFTER),
+            level: args.level.or(config.idle.level).unwrap_or(DEFAULT_LEVEL),
+            inputs,
+            touch: !args.no_touch,
+        })
+    }
+}
+
+/// Something a watching thread noticed.
+type Activity = Result<(), CliError>;
+
+fn watch_touch(touchscreen: Touchscreen, activity: Sender<Activity>) {
+    loop {
+        let touched = match touchscreen.read_report(None) {
+            Ok(Some(report)) => touchscreen
+                .layout()
+                .decode(&report)
+                .is_some_and(|report| report.contacts.iter().any(|contact| contact.tip)),
+            Ok(None) => false,
+            Err(e) => {
+                let _ = activity.send(Err(e.into()));
+                return;
+            }
+        };
+        if touched && activity.send(Ok(())).is_err() {
+            return;
+        }
+    }
+}
+
+fn watch_input(path: PathBuf, mut file: File, activity: Sender<Activity>) {
+    // Room for a few input events of 24 bytes each
+    let mut buf = [0; 24 * 16];
+    let message = loop {
+        match file.read(&mut buf) {
+            Ok(0) => break "unexpected end of file".to_string(),
+            Ok(_) => {
+                if activity.send(Ok(())).is_err() {
+                    return;
+                }
+            }
+            Err(e) => break e.to_string(),
+        }
+    };
+    let _ = activity.send(Err(CliError::InvalidFile { path, message }));
+}
+
+/// Starts the threads watching for activity.
+fn watch(
+    settings: &Settings,
+    selector: &Selector,
+    verbose: u8,
+) -> Result<Receiver<Activity>, CliError> {
+    let (sender, receiver) = mpsc::channel();
+    if settings.touch {
+        let api = HidApi::new().map_err(Error::HidInit)?;
+        let touchscreen = Touchscreen::open(&api, selector)?;
+        if verbose > 0 {
+            println!("Watching touch screen {}.", touchscreen.info().path);
+        }
+        let sender = sender.clone();
+        thread::spawn(move || watch_touch(touchscreen, sender));
+    }
+    for path in &settings.inputs {
+        let file = File::open(path).map_err(|e| CliError::InvalidFile {
+            path: path.clone(),
+            message: e.to_string(),
+        })?;
+        if verbose > 0 {
+            println!("Watching input device {}.", path.display());
+        }
+        let (path, sender) = (path.clone(), sender.clone());
+        thread::spawn(move || watch_input(path, file, sender));
+    }
+    Ok(receiver)
+}
+
+/// Returns the brightness of each display in `outcomes`, reporting failures.
+fn brightness(outcomes: Vec<Outcome>) -> Vec<(DisplayInfo, u8)> {
+    outcomes
+        .into_iter()
+        .filter_map(|outcome| match outcome.result {
+            Ok(applied) => Some((outcome.display, applied.brightness)),
+            Err(e) => {
+                eprintln!("{}: {}", outcome.display.label(), e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Dims the displays chosen by `selector` to the idle level after a while
+/// without activity, fading with `fade`, and restores their brightness on
+/// the next activity. Returns only if watching for activity fails.
+pub fn run(
+    settings: &Settings,
+    selector: &Selector,
+    fade: Option<Fade>,
+    verbose: u8,
+    execute: impl FnMut(&Selector, &Request) -> Result<Vec<Outcome>, CliError>,
+) -> Result<(), CliError> {
+    let activity = watch(settings, selector, verbose)?;
+    if verbose > 0 {
+        println!(
+            "Dimming to {}% after {} without activity.",
+            settings.level,
+            humantime::format_duration(settings.after)
+        );
+    }
+    follow(settings, selector, fade, verbose, &activity, execute)
+}
+
+/// The main loop of [`run`], dimming and restoring as `activity` reports.
+/// Returns once every watching thread is gone.
+fn follow(
+    settings: &Settings,
+    selector: &Selector,
+    fade: Option<Fade>,
+    verbose: u8,
+    activity: &Receiver<Activity>,
+    mut execute: impl FnMut(&Selector, &Request) -> Result<Vec<Outcome>, CliError>,
+) -> Result<(), CliError> {
+    let mut run =
+        |selector: &Selector, action, fade| match execute(selector, &Request { action, fade }) {
+            Ok(outcomes) => brightness(outcomes),
+            Err(e) => {
+                eprintln!("{}", e);
+                Vec::new()
+            }
+        };
+
+    let mut last_activity = Instant::now();
+    // The brightness of the displays that were dimmed, to restore later
+    let mut dimmed: Option<Vec<(DisplayInfo, u8)>> = None;
+    loop {
+        let received = match dimmed {
+            Some(_) => activity.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            None => activity.recv_timeout(settings.after.saturating_sub(last_activity.elapsed())),
+        };
+        match received {
+            Ok(Ok(())) => {
+                last_activity = Instant::now();
+                let Some(displays) = dimmed.take() else {
+                    continue;
+                };
+                if verbose > 0 && !displays.is_empty() {
+                    println!("Activity, restoring the brightness.");
+                }
+                // Without fading, the display is bright again right away
+                for (display, brightness) in displays {
+                    run(&Selector::Path(display.path), Action::Set(brightness), None);
+                }
+            }
+            Ok(Err(e)) => return Err(e),
+            Err(RecvTimeoutError::Timeout) => {
+                // Never brighten a display that is already darker
+                let displays: Vec<(DisplayInfo, u8)> = run(selector, Action::Recall, None)
+                    .into_iter()
+                    .filter(|(_, brightness)| *brightness > settings.level)
+                    .collect();
+                if verbose > 0 && !displays.is_empty() {
+                    println!("Idle, dimming to {}%.", settings.level);
+                }
+                for (display, _) in &displays {
+                    let selector = Selector::Path(display.path.clone());
+                    run(&selector, Action::Set(settings.level), fade);
+                }
+                dimmed = Some(displays);
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::panels;
+    use crate::state::State;
+    use crate::test_dir::TestDir;
+    use std::sync::{Arc, Mutex};
+    use ws170120::mock::{MockTransport, Report};
+    use ws170120::Display;
+
+    const SERIAL: &str = "idle-test";
+
+    type MockDisplay = Arc<Mutex<Display<MockTransport>>>;
+
+    fn settings(after: Duration) -> Settings {
+        Settings {
+            after,
+            level: 10,
+            inputs: Vec::new(),
+            touch: false,
+        }
+    }
+
+    /// Runs [`follow`] on a mock display that cannot be read back, keeping
+    /// its state in `state`, in the background. Returns the display and the
+    /// fake input source.
+    fn start(
+        after: Duration,
+        state: &State,
+    ) -> (
+        MockDisplay,
+        Sender<Activity>,
+        thread::JoinHandle<Result<(), CliError>>,
+    ) {
+        let display = Arc::new(Mutex::new(Display::new(MockTransport::new())));
+        let (input, activity) = mpsc::channel();
+        let info = DisplayInfo {
+            path: "mock".to_string(),
+            serial: Some(SERIAL.to_string()),
+            interface_number: 0,
+            usage_page: 0,
+        };
+        let shared = Arc::clone(&display);
+        let state = state.clone();
+        let follower = thread::spawn(move || {
+            follow(
+                &settings(after),
+                &Selector::First,
+                None,
+                0,
+                &activity,
+                |_, request| {
+                    let display = shared.lock().unwrap();
+                    Ok(vec![Outcome {
+                        display: info.clone(),
+                        result: panels::apply(
+                            &display,
+                            &state,
+                            Some(SERIAL),
+                            request,
+                            &mut Vec::new(),
+                        )
+                        .map_err(CliError::from),
+                    }])
+                },
+            )
+        });
+        (display, input, follower)
+    }
+
+    /// The brightness of each report sent so far.
+    fn written(display: &Mutex<Display<MockTransport>>) -> Vec<u8> {
+        let display = display.lock().unwrap();
+        display
+            .transport()
+            .reports()
+            .iter()
+            .map(|report| match report {
+                Report::Output(data) | Report::Feature(data) => data[6],
+            })
+            .collect()
+    }
+
+    /// Waits until the reports sent add up to `expected`.
+    fn wait_for(display: &Mutex<Display<MockTransport>>, expected: &[u8]) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while written(display) != expected {
+            assert!(
+                Instant::now() < deadline,
+                "sent {:?}, expected {expected:?}",
+                written(display)
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn dims_and_restores_from_the_state_file() {
+        let dir = TestDir::new("idle");
+        let state = State::in_dir(dir.path());
+        state.store_brightness(Some(SERIAL), 80).unwrap();
+
+        let (display, input, follower) = start(Duration::from_millis(100), &state);
+        wait_for(&display, &[10]);
+        input.send(Ok(())).unwrap();
+        wait_for(&display, &[10, 80]);
+        drop(input);
+        follower.join().unwrap().unwrap();
+        assert_eq!(written(&display), [10, 80]);
+        assert_eq!(state.load_brightness(Some(SERIAL)), Some(80));
+
+        // Already darker than the idle level, so nothing is written
+        state.store_brightness(Some(SERIAL), 5).unwrap();
+        let (display, input, follower) = start(Duration::from_millis(20), &state);
+        thread::sleep(Duration::from_millis(200));
+        drop(input);
+        follower.join().unwrap().unwrap();
+        assert!(written(&display).is_empty());
+    }
+}
@@ -0,0 +1,438 @@
+//! Brightness control for the Waveshare WS170120 17 inch LCD (C) display.
+//!
+//! The display exposes a HID interface over USB. Brightness is changed by
+//! sending a 38-byte report that starts with [`CONTROL_MAGIC`] and carries the
+//! brightness percentage at [`BRIGHTNESS_ADDRESS`]. Reading the same report
+//! back as a feature report returns the current brightness at that offset.
+
+use hidapi::{HidApi, HidDevice};
+use std::cell::Cell;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub mod descriptor;
+mod devices;
+mod error;
+mod fade;
+#[cfg(test)]
+mod fixtures;
+mod light;
+mod mapping;
+pub mod mock;
+mod schedule;
+mod solar;
+pub mod touch;
+mod transport;
+
+pub use devices::{find_displays, open_path, DisplayInfo, Selector};
+pub use error::{Error, ErrorKind, Result};
+pub use fade::{Curve, Fade, DEFAULT_STEPS_PER_SECOND};
+pub use light::{LuxCurve, LuxPoint, Smoother};
+pub use mapping::Mapping;
+pub use schedule::{Schedule, SchedulePoint, TimeOfDay};
+pub use solar::{Location, SunTimes};
+pub use transport::Transport;
+
+pub const WS170120_VENDOR_ID: u16 = 0x0eef;
+pub const WS170120_PRODUCT_ID: u16 = 0x0005;
+pub const DATA_LENGTH: usize = 38;
+pub const BRIGHTNESS_ADDRESS: usize = 6;
+pub const CONTROL_MAGIC: [u8; 4] = [0x04, 0xaa, 0x01, 0x00];
+pub const MAX_BRIGHTNESS: u8 = 100;
+
+/// Builds the report that sets the display to `brightness` percent.
+pub fn brightness_report(brightness: u8) -> [u8; DATA_LENGTH] {
+    let mut data_buffer = [0u8; DATA_LENGTH];
+    data_buffer[..CONTROL_MAGIC.len()].copy_from_slice(&CONTROL_MAGIC);
+    data_buffer[BRIGHTNESS_ADDRESS] = brightness;
+    data_buffer
+}
+
+/// Applies a relative change to `current`, clamping to 0..=[`MAX_BRIGHTNESS`].
+pub fn step_brightness(current: u8, delta: i16) -> u8 {
+    i16::from(current)
+        .saturating_add(delta)
+        .clamp(0, i16::from(MAX_BRIGHTNESS)) as u8
+}
+
+/// Extracts the brightness from a report read back from the device.
+pub fn parse_brightness_report(report: &[u8]) -> Result<u8> {
+    match parse_raw_brightness_report(report)? {
+        brightness @ 0..=MAX_BRIGHTNESS => Ok(brightness),
+        _ => Err(Error::InvalidResponse(report.to_vec())),
+    }
+}
+
+/// Extracts the value at [`BRIGHTNESS_ADDRESS`] from a report read back from
+/// the device, without checking that it is a percentage.
+pub fn parse_raw_brightness_report(report: &[u8]) -> Result<u8> {
+    if report.len() <= BRIGHTNESS_ADDRESS || !report.starts_with(&CONTROL_MAGIC) {
+        return Err(Error::InvalidResponse(report.to_vec()));
+    }
+    Ok(report[BRIGHTNESS_ADDRESS])
+}
+
+/// The way a report reached the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportPath {
+    /// Sent as a regular output report.
+    Output,
+    /// The output report failed and the feature report fallback was used.
+    Feature,
+}
+
+/// An opened WS170120 display, talking to the device through `T`.
+pub struct Display<T: Transport = HidDevice> {
+    transport: T,
+    mapping: Mapping,
+    last_brightness: Cell<Option<u8>>,
+}
+
+impl Display<HidDevice> {
+    /// Opens the first WS170120 display found by `api`.
+    pub fn open(api: &HidApi) -> Result<Self> {
+        find_displays(api)
+            .first()
+            .ok_or(Error::NotConnected)?
+            .open(api)
+    }
+}
+
+impl<T: Transport> Display<T> {
+    /// Wraps an already opened transport, such as a [`HidDevice`].
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            mapping: Mapping::default(),
+            last_brightness: Cell::new(None),
+        }
+    }
+
+    /// Translates percentages through `mapping` from now on, instead of
+    /// writing them as is.
+    pub fn set_mapping(&mut self, mapping: Mapping) {
+        self.mapping = mapping;
+        self.last_brightness.set(None);
+    }
+
+    pub fn mapping(&self) -> &Mapping {
+        &self.mapping
+    }
+
+    /// Returns the underlying transport.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Unwraps the underlying transport, e.g. to send other reports.
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Sets the brightness to a percentage between 0 and [`MAX_BRIGHTNESS`].
+    ///
+    /// The percentage is translated through the [`Mapping`]. The report is
+    /// sent as an output report first. If that fails, it is sent again as a
+    /// feature report.
+    pub fn set_brightness(&self, brightness: u8) -> Result<ReportPath> {
+        if brightness > MAX_BRIGHTNESS {
+            return Err(Error::InvalidBrightness(brightness));
+        }
+        let path = self.write_brightness(self.mapping.raw(brightness))?;
+        self.last_brightness.set(Some(brightness));
+        Ok(path)
+    }
+
+    /// Writes `raw` to the device as is, bypassing the [`Mapping`], e.g. to
+    /// find out how the panel responds to values above [`MAX_BRIGHTNESS`].
+    pub fn set_raw_brightness(&self, raw: u8) -> Result<ReportPath> {
+        let path = self.write_brightness(raw)?;
+        self.last_brightness.set(self.mapping.percent(raw));
+        Ok(path)
+    }
+
+    fn write_brightness(&self, raw: u8) -> Result<ReportPath> {
+        let data_buffer = brightness_report(raw);
+
+        // For HID devices, we use write to send the report
+        let path = match self.transport.write_output_report(&data_buffer) {
+            Ok(bytes_written) if bytes_written != DATA_LENGTH => {
+                return Err(Error::ShortWrite {
+                    written: bytes_written,
+                    expected: DATA_LENGTH,
+                })
+            }
+            Ok(_) => ReportPath::Output,
+            // If regular write fails, try send_feature_report as fallback
+            Err(output) => match self.transport.send_feature_report(&data_buffer) {
+                Ok(()) => ReportPath::Feature,
+                Err(feature) => return Err(Error::WriteFailed { output, feature }),
+            },
+        };
+        Ok(path)
+    }
+
+    /// Returns the brightness most recently written or remembered.
+    pub fn last_brightness(&self) -> Option<u8> {
+        self.last_brightness.get()
+    }
+
+    /// Records `brightness` as the current value without writing it, e.g.
+    /// when it is known from an earlier session. Fades start from this value.
+    pub fn remember_brightness(&self, brightness: u8) {
+        self.last_brightness.set(Some(brightness));
+    }
+
+    /// Fades from the current brightness to `target` over `duration`.
+    ///
+    /// See [`Display::fade`] for how the starting value is determined.
+    pub fn fade_to(&self, target: u8, duration: Duration, curve: Curve) -> Result<ReportPath> {
+        self.fade(target, &Fade::new(duration, curve))
+    }
+
+    /// Sends the intermediate brightness reports of `fade`, ending at `target`.
+    ///
+    /// The fade starts from [`Display::last_brightness`], or from the value
+    /// read back from the device if nothing was written yet. Returns the path
+    /// used for the final report.
+    pub fn fade(&self, target: u8, fade: &Fade) -> Result<ReportPath> {
+        self.fade_with(target, fade, |_, _| {})
+    }
+
+    /// Like [`Display::fade`], calling `sent` with the brightness and path
+    /// of each report as it is sent.
+    pub fn fade_with(
+        &self,
+        target: u8,
+        fade: &Fade,
+        mut sent: impl FnMut(u8, ReportPath),
+    ) -> Result<ReportPath> {
+        if target > MAX_BRIGHTNESS {
+            return Err(Error::InvalidBrightness(target));
+        }
+        let from = match self.last_brightness() {
+            Some(brightness) => brightness,
+            None => self.brightness()?,
+        };
+
+        let start = Instant::now();
+        let mut path = ReportPath::Output;
+        for (offset, brightness) in fade.steps(from, target) {
+            if let Some(delay) = offset.checked_sub(start.elapsed()) {
+                thread::sleep(delay);
+            }
+            path = self.set_brightness(brightness)?;
+            sent(brightness, path);
+        }
+        Ok(path)
+    }
+
+    /// Reads the current brightness percentage from the display, translating
+    /// the raw value back through the [`Mapping`].
+    ///
+    /// As several percentages may share a raw value, the last percentage
+    /// written is returned if it matches.
+    pub fn brightness(&self) -> Result<u8> {
+        let report = self.read_brightness_report()?;
+        let raw = parse_raw_brightness_report(&report)?;
+        let brightness = match self.last_brightness() {
+            Some(last) if self.mapping.raw(last) == raw => last,
+            _ => self
+                .mapping
+                .percent(raw)
+                .ok_or(Error::InvalidResponse(report))?,
+        };
+        self.last_brightness.set(Some(brightness));
+        Ok(brightness)
+    }
+
+    /// Reads the value at [`BRIGHTNESS_ADDRESS`] as is, bypassing the
+    /// [`Mapping`].
+    pub fn raw_brightness(&self) -> Result<u8> {
+        parse_raw_brightness_report(&self.read_brightness_report()?)
+    }
+
+    /// Requests the brightness report as a feature report using the report
+    /// ID at the start of [`CONTROL_MAGIC`].
+    fn read_brightness_report(&self) -> Result<Vec<u8>> {
+        let mut data_buffer = [0u8; DATA_LENGTH];
+        data_buffer[0] = CONTROL_MAGIC[0];
+        let len = self
+            .transport
+            .get_feature_report(&mut data_buffer)
+            .map_err(Error::ReadFailed)?;
+        Ok(data_buffer[..len].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockTransport, Report};
+
+    fn response(brightness: u8) -> Vec<u8> {
+        brightness_report(brightness).to_vec()
+    }
+
+    /// The brightness byte of every report sent, in order.
+    fn written(transport: &MockTransport) -> Vec<u8> {
+        transport
+            .reports()
+            .iter()
+            .map(|report| match report {
+                Report::Output(data) | Report::Feature(data) => data[BRIGHTNESS_ADDRESS],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn set_sends_output_report() {
+        let display = Display::new(MockTransport::new());
+        assert_eq!(display.set_brightness(42).unwrap(), ReportPath::Output);
+        assert_eq!(
+            display.transport().reports(),
+            vec![Report::Output(brightness_report(42).to_vec())]
+        );
+        assert_eq!(display.last_brightness(), Some(42));
+    }
+
+    #[test]
+    fn set_falls_back_to_feature_report() {
+        let display = Display::new(MockTransport::new());
+        display.transport().fail_output(true);
+        assert_eq!(display.set_brightness(42).unwrap(), ReportPath::Feature);
+        assert_eq!(
+            display.transport().last_report(),
+            Some(Report::Feature(brightness_report(42).to_vec()))
+        );
+    }
+
+    #[test]
+    fn set_fails_when_both_paths_fail() {
+        let display = Display::new(MockTransport::new());
+        display.transport().fail_output(true);
+        display.transport().fail_feature(true);
+        assert!(matches!(
+            display.set_brightness(42),
+            Err(Error::WriteFailed { .. })
+        ));
+        assert_eq!(display.last_brightness(), None);
+    }
+
+    #[test]
+    fn set_reports_short_write() {
+        let display = Display::new(MockTransport::new());
+        display.transport().short_write(Some(10));
+        assert!(matches!(
+            display.set_brightness(42),
+            Err(Error::ShortWrite {
+                written: 10,
+                expected: DATA_LENGTH
+            })
+        ));
+    }
+
+    #[test]
+    fn set_rejects_out_of_range() {
+        let display = Display::new(MockTransport::new());
+        assert!(matches!(
+            display.set_brightness(101),
+            Err(Error::InvalidBrightness(101))
+        ));
+        assert!(display.transport().reports().is_empty());
+    }
+
+    #[test]
+    fn step_clamps_to_range() {
+        assert_eq!(step_brightness(50, 10), 60);
+        assert_eq!(step_brightness(95, 10), MAX_BRIGHTNESS);
+        assert_eq!(step_brightness(5, -10), 0);
+        assert_eq!(step_brightness(0, i16::MIN), 0);
+        assert_eq!(step_brightness(100, i16::MAX), MAX_BRIGHTNESS);
+    }
+
+    #[test]
+    fn reads_brightness_back() {
+        let display = Display::new(MockTransport::new());
+        display.transport().push_feature_response(response(73));
+        assert_eq!(display.brightness().unwrap(), 73);
+        assert_eq!(display.last_brightness(), Some(73));
+    }
+
+    #[test]
+    fn rejects_invalid_readback() {
+        let display = Display::new(MockTransport::new());
+        display.transport().push_feature_response(response(150));
+        assert!(matches!(
+            display.brightness(),
+            Err(Error::InvalidResponse(_))
+        ));
+        display
+            .transport()
+            .push_feature_response(vec![0x05, 0, 0, 0, 0, 0, 50]);
+        assert!(matches!(
+            display.brightness(),
+            Err(Error::InvalidResponse(_))
+        ));
+        assert!(matches!(display.brightness(), Err(Error::ReadFailed(_))));
+    }
+
+    #[test]
+    fn fade_to_sends_each_step() {
+        let display = Display::new(MockTransport::new());
+        display.remember_brightness(0);
+        let path = display
+            .fade_to(100, Duration::from_millis(200), Curve::Linear)
+            .unwrap();
+        assert_eq!(path, ReportPath::Output);
+        assert_eq!(written(display.transport()), vec![20, 40, 60, 80, 100]);
+    }
+
+    #[test]
+    fn fade_to_starts_from_readback() {
+        let display = Display::new(MockTransport::new());
+        display.transport().push_feature_response(response(50));
+        display
+            .fade_to(48, Duration::from_millis(200), Curve::Linear)
+            .unwrap();
+        assert_eq!(written(display.transport()), vec![50, 49, 48]);
+    }
+
+    #[test]
+    fn fade_reports_fallback_of_last_step() {
+        let display = Display::new(MockTransport::new());
+        display.remember_brightness(10);
+        display.transport().fail_output(true);
+        let path = display
+            .fade_to(20, Duration::from_millis(80), Curve::Linear)
+            .unwrap();
+        assert_eq!(path, ReportPath::Feature);
+        assert_eq!(written(display.transport()), vec![15, 20]);
+    }
+
+    #[test]
+    fn fade_with_reports_each_step() {
+        let display = Display::new(MockTransport::new());
+        display.remember_brightness(10);
+        let mut sent = Vec::new();
+        display
+            .fade_with(
+                40,
+                &Fade::new(Duration::from_millis(120), Curve::Linear),
+                |brightness, path| {
+                    sent.push((brightness, path));
+                    // Later steps only get through as feature reports
+                    display.transport().fail_output(true);
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            sent,
+            [
+                (20, ReportPath::Output),
+                (30, ReportPath::Feature),
+                (40, ReportPath::Feature)
+            ]
+        );
+    }
+}
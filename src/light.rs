@@ -0,0 +1,286 @@
+//! Brightness that follows the ambient light.
+
+use crate::MAX_BRIGHTNESS;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A brightness that is reached at an illuminance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LuxPoint {
+    pub lux: f64,
+    pub brightness: u8,
+}
+
+impl FromStr for LuxPoint {
+    type Err = String;
+
+    /// Parses `LUX:PERCENT`, e.g. `500:80`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid point '{s}', expected LUX:PERCENT, e.g. 500:80");
+        let (lux, brightness) = s.split_once(':').ok_or_else(invalid)?;
+        Ok(LuxPoint {
+            lux: lux.trim().parse().map_err(|_| invalid())?,
+            brightness: brightness.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl fmt::Display for LuxPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.lux, self.brightness)
+    }
+}
+
+/// Maps an illuminance to a brightness, interpolating linearly between the
+/// points and holding the first and last brightness beyond them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuxCurve {
+    points: Vec<LuxPoint>,
+}
+
+impl LuxCurve {
+    /// Creates a curve, checking that there is at least one point, that
+    /// the illuminance grows from point to point and that brightness values
+    /// are in range.
+    pub fn new(points: Vec<LuxPoint>) -> Result<Self, String> {
+        if points.is_empty() {
+            return Err("the curve needs at least one point".to_string());
+        }
+        for point in &points {
+            if !point.lux.is_finite() || point.lux < 0.0 {
+                return Err(format!(
+                    "illuminance {} is invalid, expected a number of lux of at least 0",
+                    point.lux
+                ));
+            }
+            if point.brightness > MAX_BRIGHTNESS {
+                return Err(format!(
+                    "brightness {} at {} lux is out of range, expected a value between 0 and {}",
+                    point.brightness, point.lux, MAX_BRIGHTNESS
+                ));
+            }
+        }
+        if let Some(pair) = points.windows(2).find(|pair| pair[0].lux >= pair[1].lux) {
+            return Err(format!(
+                "point {} must come after {}, the illuminance must grow from point to point",
+                pair[0], pair[1]
+            ));
+        }
+        Ok(Self { points })
+    }
+
+    pub fn points(&self) -> &[LuxPoint] {
+        &self.points
+    }
+
+    /// Returns the brightness for an illuminance of `lux`.
+    pub fn brightness(&self, lux: f64) -> u8 {
+        let next_index = self.points.iter().position(|point| point.lux > lux);
+        let (previous, next) = match next_index {
+            Some(0) => return self.points[0].brightness,
+            Some(index) => (self.points[index - 1], self.points[index]),
+            None => return self.points[self.points.len() - 1].brightness,
+        };
+        let t = (lux - previous.lux) / (next.lux - previous.lux);
+        let value = f64::from(previous.brightness)
+            + (f64::from(next.brightness) - f64::from(previous.brightness)) * t;
+        value.round() as u8
+    }
+}
+
+impl Default for LuxCurve {
+    /// Dim in the dark, full brightness in daylight.
+    fn default() -> Self {
+        let points = [
+            (0.0, 10),
+            (10.0, 20),
+            (100.0, 45),
+            (1000.0, 80),
+            (10000.0, 100),
+        ];
+        Self {
+            points: points
+                .into_iter()
+                .map(|(lux, brightness)| LuxPoint { lux, brightness })
+                .collect(),
+        }
+    }
+}
+
+/// Keeps the brightness from following every flicker of the light.
+///
+/// A new brightness is only taken on once it differs from the current goal
+/// by at least the hysteresis, and the brightness moves towards the goal by
+/// at most the rate per second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Smoother {
+    hysteresis: u8,
+    /// Percentage points per second, `None` to jump.
+    rate: Option<f64>,
+    goal: Option<u8>,
+    current: Option<f64>,
+}
+
+impl Smoother {
+    pub fn new(hysteresis: u8, rate: Option<f64>) -> Self {
+        Self {
+            hysteresis,
+            rate,
+            goal: None,
+            current: None,
+        }
+    }
+
+    /// Feeds the brightness the curve asks for, `elapsed` after the previous
+    /// call, and returns the brightness to show now. The first call returns
+    /// `brightness` itself.
+    pub fn update(&mut self, brightness: u8, elapsed: Duration) -> u8 {
+        let goal = match self.goal {
+            Some(goal) if goal.abs_diff(brightness) < self.hysteresis => goal,
+            _ => brightness,
+        };
+        self.goal = Some(goal);
+        let goal = f64::from(goal);
+        let current = match (self.current, self.rate) {
+            (Some(current), Some(rate)) => {
+                let limit = rate * elapsed.as_secs_f64();
+                current + (goal - current).clamp(-limit, limit)
+            }
+            _ => goal,
+        };
+        self.current = Some(current);
+        current.round() as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(points: &[(f64, u8)]) -> LuxCurve {
+        LuxCurve::new(
+            points
+                .iter()
+                .map(|&(lux, brightness)| LuxPoint { lux, brightness })
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_points() {
+        assert_eq!(
+            " 500 : 80 ".parse::<LuxPoint>(),
+            Ok(LuxPoint {
+                lux: 500.0,
+                brightness: 80
+            })
+        );
+        assert_eq!("0.5:5".parse::<LuxPoint>().unwrap().to_string(), "0.5:5");
+        for invalid in ["500", "500:", ":80", "lots:80", "500:-1", "500:256"] {
+            assert!(invalid.parse::<LuxPoint>().is_err(), "point {invalid:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_curves() {
+        let point = |lux, brightness| LuxPoint { lux, brightness };
+        for points in [
+            vec![],
+            vec![point(-1.0, 10)],
+            vec![point(f64::NAN, 10)],
+            vec![point(0.0, 101)],
+            vec![point(10.0, 10), point(10.0, 20)],
+            vec![point(100.0, 10), point(10.0, 20)],
+        ] {
+            assert!(LuxCurve::new(points.clone()).is_err(), "points {points:?}");
+        }
+    }
+
+    #[test]
+    fn interpolates_between_points() {
+        let curve = curve(&[(0.0, 10), (100.0, 50), (1000.0, 100)]);
+        assert_eq!(curve.brightness(0.0), 10);
+        assert_eq!(curve.brightness(25.0), 20);
+        assert_eq!(curve.brightness(50.0), 30);
+        assert_eq!(curve.brightness(100.0), 50);
+        assert_eq!(curve.brightness(550.0), 75);
+        assert_eq!(curve.brightness(1000.0), 100);
+        // Rounds to the nearest percentage
+        assert_eq!(curve.brightness(1.0), 10);
+        assert_eq!(curve.brightness(2.0), 11);
+    }
+
+    #[test]
+    fn holds_beyond_the_end_points() {
+        let curve = curve(&[(10.0, 20), (500.0, 80)]);
+        assert_eq!(curve.brightness(0.0), 20);
+        assert_eq!(curve.brightness(-5.0), 20);
+        assert_eq!(curve.brightness(500.0), 80);
+        assert_eq!(curve.brightness(100_000.0), 80);
+
+        let flat = self::curve(&[(300.0, 60)]);
+        assert_eq!(flat.brightness(0.0), 60);
+        assert_eq!(flat.brightness(1e6), 60);
+    }
+
+    #[test]
+    fn default_curve_is_valid() {
+        let default = LuxCurve::default();
+        assert_eq!(
+            LuxCurve::new(default.points().to_vec()),
+            Ok(default.clone())
+        );
+        assert_eq!(default.brightness(0.0), 10);
+        assert_eq!(default.brightness(10_000.0), 100);
+    }
+
+    #[test]
+    fn first_update_is_taken_as_is() {
+        let mut smoother = Smoother::new(5, Some(1.0));
+        assert_eq!(smoother.update(70, Duration::ZERO), 70);
+    }
+
+    #[test]
+    fn hysteresis_ignores_small_changes() {
+        let mut smoother = Smoother::new(5, None);
+        assert_eq!(smoother.update(50, Duration::ZERO), 50);
+        assert_eq!(smoother.update(53, Duration::ZERO), 50);
+        assert_eq!(smoother.update(46, Duration::ZERO), 50);
+        assert_eq!(smoother.update(55, Duration::ZERO), 55);
+        // Measured against the goal, so a slow drift is not followed
+        assert_eq!(smoother.update(51, Duration::ZERO), 55);
+        assert_eq!(smoother.update(50, Duration::ZERO), 50);
+
+        let mut smoother = Smoother::new(0, None);
+        assert_eq!(smoother.update(50, Duration::ZERO), 50);
+        assert_eq!(smoother.update(51, Duration::ZERO), 51);
+    }
+
+    #[test]
+    fn rate_limits_the_change_per_second() {
+        let second = Duration::from_secs(1);
+        let mut smoother = Smoother::new(0, Some(2.0));
+        assert_eq!(smoother.update(50, Duration::ZERO), 50);
+        assert_eq!(smoother.update(60, second), 52);
+        assert_eq!(smoother.update(60, Duration::ZERO), 52);
+        assert_eq!(smoother.update(60, 2 * second), 56);
+        assert_eq!(smoother.update(60, 10 * second), 60);
+        assert_eq!(smoother.update(40, Duration::from_millis(500)), 59);
+        assert_eq!(smoother.update(40, Duration::from_millis(500)), 58);
+    }
+
+    #[test]
+    fn hysteresis_and_rate_combine() {
+        let second = Duration::from_secs(1);
+        let mut smoother = Smoother::new(5, Some(10.0));
+        assert_eq!(smoother.update(50, Duration::ZERO), 50);
+        assert_eq!(smoother.update(53, second), 50);
+        assert_eq!(smoother.update(80, second), 60);
+        // Still on the way to 80, which is within the hysteresis of 82
+        assert_eq!(smoother.update(82, second), 70);
+        assert_eq!(smoother.update(82, second), 80);
+        assert_eq!(smoother.update(82, second), 80);
+    }
+}
@@ -1,130 +1,522 @@
-use clap::Parser;
-use hidapi::{HidApi, HidDevice};
-use std::{error::Error, process};
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
+use cli_error::CliError;
+use config::Config;
+use output::Format;
+use panels::{Action, Outcome, Panels, Request};
+use std::path::PathBuf;
+use std::{process, str::FromStr, time::Duration};
+use ws170120::{
+    Curve, DisplayInfo, Fade, ReportPath, Selector, DEFAULT_STEPS_PER_SECOND, MAX_BRIGHTNESS,
+};
+
+mod auto;
+mod cli_error;
+mod config;
+#[cfg(unix)]
+mod daemon;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod doctor;
+#[cfg(all(unix, feature = "http"))]
+mod http;
+mod idle;
+#[cfg(all(unix, feature = "metrics"))]
+mod metrics;
+#[cfg(all(unix, feature = "mqtt"))]
+mod mqtt;
+mod output;
+mod panels;
+#[cfg(unix)]
+mod protocol;
+mod raw;
+mod report_descriptor;
+mod scheduler;
+mod state;
+#[cfg(test)]
+mod test_dir;
+mod touchscreen;
+#[cfg(target_os = "linux")]
+mod udev;
+#[cfg(all(target_os = "linux", feature = "uinput"))]
+mod uinput;
 
 /// Control the brightness of a Waveshare WS170120 display
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(disable_help_flag = true)]
+#[command(arg_required_else_help = true)]
+#[command(allow_negative_numbers = true)]
 struct Args {
-    /// Brightness percentage (0-100)
-    #[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
-    brightness: u8,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Brightness percentage (0-100), or a relative step like +10 or -10
+    brightness: Option<Level>,
+
+    /// Configuration file [default: $XDG_CONFIG_HOME/ws170120-ctl/config.toml]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Fade to the new brightness over this duration (e.g. 2s, 500ms)
+    #[arg(long, global = true, value_parser = humantime::parse_duration)]
+    fade: Option<Duration>,
+
+    /// Brightness reports sent per second while fading
+    #[arg(long, global = true, default_value_t = DEFAULT_STEPS_PER_SECOND, value_parser = clap::value_parser!(u32).range(1..))]
+    fade_rate: u32,
+
+    /// Easing curve used while fading: linear, ease-in-out or perceptual [default: linear]
+    #[arg(long, global = true)]
+    curve: Option<Curve>,
+
+    /// Control the display with this USB serial number
+    #[arg(long, global = true, group = "select")]
+    serial: Option<String>,
+
+    /// Control the display at this HID path (see `list`)
+    #[arg(long, global = true, group = "select")]
+    path: Option<String>,
+
+    /// Control all attached displays
+    #[arg(long, global = true, group = "select")]
+    all: bool,
+
+    /// Control the display with this name in the configuration file
+    #[arg(long, global = true, group = "select")]
+    device: Option<String>,
+
+    /// Control socket of the daemon [default: $XDG_RUNTIME_DIR/ws170120-ctl.sock]
+    #[cfg(unix)]
+    #[arg(long, global = true)]
+    socket: Option<PathBuf>,
+
+    /// Talk to the display directly even if a daemon is running
+    #[cfg(unix)]
+    #[arg(long, global = true)]
+    no_daemon: bool,
+
+    /// How results are printed
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    output: Format,
 
     /// Increase verbosity
-    #[arg(short, long, action = clap::ArgAction::Count)]
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
     verbose: u8,
 
     /// Print help
-    #[arg(short = '?', short_alias = 'h', long = "help", action = clap::ArgAction::Help)]
+    #[arg(short = '?', short_alias = 'h', long = "help", global = true, action = clap::ArgAction::Help)]
     help: Option<bool>,
 }
 
-const WS170120_VENDOR_ID: u16 = 0x0eef;
-const WS170120_PRODUCT_ID: u16 = 0x0005;
-const DATA_LENGTH: usize = 38;
-const BRIGHTNESS_ADDRESS: usize = 6;
-const CONTROL_MAGIC: [u8; 4] = [0x04, 0xaa, 0x01, 0x00];
-
-fn find_ws170120_device(api: &HidApi) -> Result<HidDevice, String> {
-    let device_info = api
-        .device_list()
-        .find(|info| {
-            info.vendor_id() == WS170120_VENDOR_ID && info.product_id() == WS170120_PRODUCT_ID
-        })
-        .ok_or_else(|| "Waveshare monitor WS170120 is not connected.".to_string())?;
-
-    device_info
-        .open_device(api)
-        .map_err(|e| translate_device_error("opening device failed", e))
-}
-
-fn translate_device_error(title: &str, e: impl Error) -> String {
-    let err_str = e.to_string().to_lowercase();
-    if err_str.contains("access denied")
-        || err_str.contains("exclusive access")
-        || err_str.contains("permission denied")
-    {
-        format!("{title}: Device access denied. Try running with elevated privileges (sudo). Error message was {e:?}")
-    } else {
-        format!("{title}: Failed to open device: {e}")
-    }
+#[derive(Subcommand)]
+enum Command {
+    /// List all attached displays
+    List,
+    /// Print the current brightness percentage
+    Get,
+    /// Increase the brightness by a number of percentage points
+    Up {
+        /// Percentage points to add
+        #[arg(default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=100))]
+        step: u8,
+    },
+    /// Decrease the brightness by a number of percentage points
+    Down {
+        /// Percentage points to subtract
+        #[arg(default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=100))]
+        step: u8,
+    },
+    /// Set the brightness of a preset from the configuration file
+    Preset {
+        /// Name of the preset, e.g. night
+        name: String,
+    },
+    /// Diagnose why a display cannot be found or controlled
+    Doctor(doctor::DoctorArgs),
+    /// Print the HID report descriptor of a display
+    Descriptor(report_descriptor::DescriptorArgs),
+    /// Send and receive raw HID reports, for exploring the protocol
+    Raw {
+        #[command(subcommand)]
+        command: raw::RawCommand,
+    },
+    /// Read the touch screen
+    Touch {
+        #[command(subcommand)]
+        command: touchscreen::TouchCommand,
+    },
+    /// Follow a daily brightness schedule
+    Schedule(scheduler::ScheduleArgs),
+    /// Dim the display while nobody touches it
+    Idle(idle::IdleArgs),
+    /// Follow the ambient light measured by a light sensor
+    Auto(auto::AutoArgs),
+    /// Keep the displays open and accept commands on the control socket
+    #[cfg(unix)]
+    Daemon(daemon::DaemonArgs),
+    /// Install udev rules that allow non-root access to the display
+    #[cfg(target_os = "linux")]
+    InstallUdevRules(udev::UdevArgs),
 }
 
-fn set_brightness(device: &HidDevice, brightness: u8, verbose: u8) -> Result<(), String> {
-    // Brightness validation is now handled by clap's value parser
-
-    // Prepare the data buffer
-    let mut data_buffer = [0u8; DATA_LENGTH];
-    data_buffer[..CONTROL_MAGIC.len()].copy_from_slice(&CONTROL_MAGIC);
-    data_buffer[BRIGHTNESS_ADDRESS] = brightness;
+/// A brightness given on the command line.
+#[derive(Clone, Copy)]
+enum Level {
+    Absolute(u8),
+    Relative(i16),
+}
 
-    // For HID devices, we use write to send the report
-    let result = device.write(&data_buffer);
+impl FromStr for Level {
+    type Err = String;
 
-    match result {
-        Ok(bytes_written) => {
-            if bytes_written != DATA_LENGTH {
-                return Err(format!(
-                    "Unexpected result {} from writing brightness data, expected {}.",
-                    bytes_written, DATA_LENGTH
-                ));
-            }
-            if verbose > 0 {
-                println!("Brightness has been set to {}%.", brightness);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with(['+', '-']) {
+            s.parse()
+                .map(Level::Relative)
+                .map_err(|_| format!("{s} is not a valid relative step"))
+        } else {
+            match s.parse() {
+                Ok(brightness @ 0..=MAX_BRIGHTNESS) => Ok(Level::Absolute(brightness)),
+                _ => Err(format!("{s} is not in 0..={MAX_BRIGHTNESS}")),
             }
-            Ok(())
         }
-        Err(e) => {
-            // If regular write fails, try send_feature_report as fallback
+    }
+}
+
+fn print_outcome(outcome: &Outcome, request: &Request, labelled: bool, verbose: u8) {
+    let Ok(applied) = &outcome.result else {
+        return;
+    };
+    let label = outcome.display.label();
+    match request.action {
+        Action::Get | Action::Recall if verbose > 0 => {
+            println!(
+                "Current brightness of {} is {}%.",
+                label, applied.brightness
+            )
+        }
+        Action::Get | Action::Recall if labelled => println!("{}: {}", label, applied.brightness),
+        Action::Get | Action::Recall => println!("{}", applied.brightness),
+        Action::Set(_) | Action::Step(_) => {
             if verbose > 0 {
-                println!("Regular write failed, trying feature report...");
+                if applied.path == Some(ReportPath::Feature) {
+                    println!("Regular write failed, used feature report instead.");
+                }
+                println!("Brightness has been set to {}%.", applied.brightness);
             }
+        }
+    }
+}
+
+fn list(displays: &[DisplayInfo]) {
+    for info in displays {
+        println!(
+            "{}\tserial={}\tinterface={}",
+            info.path,
+            info.serial.as_deref().unwrap_or("-"),
+            info.interface_number
+        );
+    }
+}
+
+fn selector(args: &Args, config: &Config) -> Result<Selector, CliError> {
+    Ok(if let Some(serial) = &args.serial {
+        Selector::Serial(serial.clone())
+    } else if let Some(path) = &args.path {
+        Selector::Path(path.clone())
+    } else if args.all {
+        Selector::All
+    } else if let Some(device) = &args.device {
+        config.device(device)?
+    } else {
+        Selector::First
+    })
+}
+
+fn request(args: &Args, config: &Config) -> Result<Request, CliError> {
+    let action = match (&args.command, args.brightness) {
+        (Some(Command::Up { step }), _) => Action::Step(i16::from(*step)),
+        (Some(Command::Down { step }), _) => Action::Step(-i16::from(*step)),
+        (Some(Command::Preset { name }), _) => Action::Set(config.preset(name)?),
+        (None, Some(Level::Absolute(brightness))) => Action::Set(brightness),
+        (None, Some(Level::Relative(delta))) => Action::Step(delta),
+        _ => Action::Get,
+    };
+    Ok(Request {
+        action,
+        fade: fade(args),
+    })
+}
+
+fn fade(args: &Args) -> Option<Fade> {
+    args.fade.map(|duration| Fade {
+        duration,
+        curve: args.curve.unwrap_or_default(),
+        steps_per_second: args.fade_rate,
+    })
+}
+
+/// Fills in the options not given on the command line from `config`.
+fn apply_defaults(args: &mut Args, config: &Config) {
+    args.fade = args.fade.or(config.fade);
+    args.curve = args.curve.or(config.curve);
+    if args.verbose == 0 {
+        args.verbose = config.verbose;
+    }
+    // Keep stdout to the JSON document alone
+    if args.output == Format::Json {
+        args.verbose = 0;
+    }
+}
 
-            let feature_result = device.send_feature_report(&data_buffer);
+/// Why [`run`] failed.
+enum Failure {
+    /// The request could not be carried out; the error is not reported yet.
+    Error(CliError),
+    /// Some displays failed. Their errors were reported along with the
+    /// results of the others.
+    Displays(CliError),
+}
+
+impl From<CliError> for Failure {
+    fn from(e: CliError) -> Self {
+        Failure::Error(e)
+    }
+}
+
+impl From<ws170120::Error> for Failure {
+    fn from(e: ws170120::Error) -> Self {
+        Failure::Error(e.into())
+    }
+}
+
+/// Carries out requests through the daemon if one is running, or directly.
+///
+/// Displays used directly are kept open for the next request, so that the
+/// loops of `schedule`, `idle` and `auto` don't enumerate the bus on every
+/// tick. The bus is only enumerated again after a request failed.
+struct Executor<'a> {
+    args: &'a Args,
+    config: &'a Config,
+    panels: Option<Panels>,
+    /// Whether the last request failed on some display.
+    stale: bool,
+}
+
+impl<'a> Executor<'a> {
+    fn new(args: &'a Args, config: &'a Config) -> Self {
+        Executor {
+            args,
+            config,
+            panels: None,
+            stale: false,
+        }
+    }
 
-            match feature_result {
-                Ok(()) => {
-                    if verbose > 0 {
-                        println!("Brightness has been set to {}%.", brightness);
-                    }
-                    Ok(())
+    fn execute(
+        &mut self,
+        selector: &Selector,
+        request: &Request,
+    ) -> Result<Vec<Outcome>, CliError> {
+        #[cfg(unix)]
+        if !self.args.no_daemon {
+            let socket = self
+                .args
+                .socket
+                .clone()
+                .unwrap_or_else(protocol::default_socket_path);
+            if let Some(stream) = protocol::connect(&socket) {
+                if self.args.verbose > 0 {
+                    println!("Using daemon at {}.", socket.display());
+                }
+                // Leave the displays to the daemon
+                self.panels = None;
+                return protocol::send(stream, selector, request);
+            }
+        }
+        if self.stale {
+            // The display may have been unplugged or plugged in again
+            if let Some(panels) = &mut self.panels {
+                if panels.refresh().is_err() {
+                    self.panels = None;
                 }
-                Err(e2) => Err(format!(
-                    "Failed to write brightness data via both regular write and feature report. Write error: {}, Feature report error: {}",
-                    e, e2
-                )),
             }
         }
+        let panels = match &mut self.panels {
+            Some(panels) => panels,
+            None => {
+                let mut panels = Panels::new()?;
+                panels.set_mapping(self.config.mapping.clone());
+                self.panels.insert(panels)
+            }
+        };
+        let outcomes = panels.execute(selector, request);
+        self.stale = match &outcomes {
+            Ok(outcomes) => outcomes.iter().any(|outcome| outcome.result.is_err()),
+            Err(_) => true,
+        };
+        Ok(outcomes?)
     }
 }
 
-fn main() {
-    let args = Args::parse();
+fn run(args: &Args, config: &Config) -> Result<(), Failure> {
+    match args.command {
+        Some(Command::List) => {
+            let displays = Panels::new()?.displays();
+            match args.output {
+                Format::Text => list(&displays),
+                Format::Json => output::print_list(&displays),
+            }
+            return Ok(());
+        }
+        Some(Command::Doctor(ref doctor_args)) => {
+            return Ok(doctor::run(doctor_args, config)?);
+        }
+        Some(Command::Descriptor(ref descriptor_args)) => {
+            let selector = selector(args, config)?;
+            return Ok(report_descriptor::run(
+                descriptor_args,
+                &selector,
+                args.output,
+            )?);
+        }
+        Some(Command::Raw { ref command }) => {
+            return Ok(raw::run(command, &selector(args, config)?, args.verbose)?);
+        }
+        Some(Command::Touch { ref command }) => {
+            let selector = selector(args, config)?;
+            return Ok(touchscreen::run(
+                command,
+                &selector,
+                config,
+                args.config.as_deref(),
+                args.output,
+                args.verbose,
+            )?);
+        }
+        Some(Command::Schedule(ref schedule_args)) => {
+            let schedule = scheduler::load(&schedule_args.file)?;
+            let selector = selector(args, config)?;
+            let mut executor = Executor::new(args, config);
+            scheduler::run(
+                &schedule,
+                schedule_args.interval,
+                args.verbose,
+                |brightness| {
+                    let request = Request {
+                        action: Action::Set(brightness),
+                        fade: None,
+                    };
+                    executor
+                        .execute(&selector, &request)?
+                        .into_iter()
+                        .try_for_each(|outcome| outcome.result.map(|_| ()))
+                },
+            );
+        }
+        Some(Command::Idle(ref idle_args)) => {
+            let settings = idle::Settings::new(idle_args, config)?;
+            let selector = selector(args, config)?;
+            let mut executor = Executor::new(args, config);
+            return Ok(idle::run(
+                &settings,
+                &selector,
+                fade(args),
+                args.verbose,
+                |selector, request| executor.execute(selector, request),
+            )?);
+        }
+        Some(Command::Auto(ref auto_args)) => {
+            let settings = auto::Settings::new(auto_args, config)?;
+            let selector = selector(args, config)?;
+            let mut executor = Executor::new(args, config);
+            auto::run(&settings, args.verbose, |brightness| {
+                let request = Request {
+                    action: Action::Set(brightness),
+                    fade: fade(args),
+                };
+                executor
+                    .execute(&selector, &request)?
+                    .into_iter()
+                    .try_for_each(|outcome| outcome.result.map(|_| ()))
+            });
+        }
+        #[cfg(unix)]
+        Some(Command::Daemon(ref daemon_args)) => {
+            let socket = args
+                .socket
+                .clone()
+                .unwrap_or_else(protocol::default_socket_path);
+            return Ok(daemon::run(&socket, daemon_args, config, args.verbose)?);
+        }
+        #[cfg(target_os = "linux")]
+        Some(Command::InstallUdevRules(ref udev_args)) => {
+            return Ok(udev::run(udev_args, args.verbose)?);
+        }
+        _ => {}
+    }
 
-    if args.verbose > 0 {
-        println!("Attempting to set brightness to {}%.", args.brightness);
+    let selector = selector(args, config)?;
+    let request = request(args, config)?;
+    if let (Action::Set(brightness), true) = (request.action, args.verbose > 0) {
+        println!("Attempting to set brightness to {}%.", brightness);
+    }
+    if let (Some(duration), true) = (args.fade, args.verbose > 0) {
+        println!("Fading over {}.", humantime::format_duration(duration));
     }
 
-    // Initialize HID API
-    let api = match HidApi::new() {
-        Ok(api) => api,
-        Err(e) => {
-            eprintln!("Failed to initialize HID API: {}", e);
-            process::exit(1);
+    let outcomes = Executor::new(args, config).execute(&selector, &request)?;
+    if args.output == Format::Json {
+        output::print_outcomes(&request, &outcomes);
+    }
+    let labelled = outcomes.len() > 1;
+    let mut result = Ok(());
+    for outcome in outcomes {
+        if args.output == Format::Text {
+            print_outcome(&outcome, &request, labelled, args.verbose);
         }
-    };
-
-    let device = match find_ws170120_device(&api) {
-        Ok(device) => device,
-        Err(e) => {
-            eprintln!("{}", e);
-            process::exit(1);
+        if let Err(e) = outcome.result {
+            // Keep going so one failing display doesn't hide the others
+            match args.output {
+                Format::Text if labelled => eprintln!("{}: {}", outcome.display.label(), e),
+                Format::Text => eprintln!("{}", e),
+                Format::Json => {}
+            }
+            // The first failure decides the exit status
+            if result.is_ok() {
+                result = Err(Failure::Displays(e));
+            }
         }
-    };
+    }
+    result
+}
 
-    if let Err(e) = set_brightness(&device, args.brightness, args.verbose) {
-        eprintln!("{}", e);
-        process::exit(1);
+fn main() {
+    let mut args = Args::parse();
+    if args.command.is_some() && args.brightness.is_some() {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "a brightness cannot be combined with a subcommand",
+            )
+            .exit();
+    }
+
+    let result = config::load(args.config.as_deref())
+        .map_err(Failure::Error)
+        .and_then(|config| {
+            apply_defaults(&mut args, &config);
+            run(&args, &config)
+        });
+    match result {
+        Ok(()) => {}
+        Err(Failure::Error(e)) => {
+            match args.output {
+                Format::Text => eprintln!("{}", e),
+                Format::Json => output::print_error(&e),
+            }
+            process::exit(e.exit_code());
+        }
+        Err(Failure::Displays(e)) => process::exit(e.exit_code()),
     }
 }
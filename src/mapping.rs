@@ -0,0 +1,312 @@
+//! Translation between brightness percentages and the values the firmware
+//! receives.
+//!
+//! The firmware takes the percentage as is, but the light the panel gives
+//! off is far from proportional to it. A [`Mapping`] picks the raw value for
+//! each percentage, e.g. so that 50% looks half as bright as 100%.
+
+use crate::fade::luminance;
+use crate::MAX_BRIGHTNESS;
+
+const STEPS: usize = MAX_BRIGHTNESS as usize + 1;
+
+/// The raw value written for each brightness percentage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    raw: [u8; STEPS],
+}
+
+impl Default for Mapping {
+    fn default() -> Self {
+        Self::linear()
+    }
+}
+
+impl Mapping {
+    /// Writes the percentage as is, like the firmware expects.
+    pub fn linear() -> Self {
+        Self::from_fn(|percent| percent as f64)
+    }
+
+    /// Raises the brightness to the power of `gamma`, so that `max` is
+    /// written for 100%.
+    pub fn gamma(gamma: f64, max: u8) -> Result<Self, String> {
+        if !gamma.is_finite() || gamma <= 0.0 {
+            return Err(format!(
+                "gamma {gamma} is invalid, expected a number above 0"
+            ));
+        }
+        Ok(Self::from_fn(|percent| {
+            f64::from(max) * (percent as f64 / 100.0).powf(gamma)
+        }))
+    }
+
+    /// Treats the percentage as perceived lightness (CIE L*), assuming the
+    /// light given off grows in proportion to the raw value up to `max`.
+    pub fn perceptual(max: u8) -> Self {
+        Self::from_fn(|percent| f64::from(max) * luminance(percent as f64) / 100.0)
+    }
+
+    /// Interpolates linearly between `(percent, raw)` points, holding the
+    /// first and last raw value beyond them.
+    pub fn from_table(points: &[(u8, u8)]) -> Result<Self, String> {
+        if points.is_empty() {
+            return Err("the table needs at least one point".to_string());
+        }
+        if let Some((percent, _)) = points.iter().find(|(percent, _)| *percent > MAX_BRIGHTNESS) {
+            return Err(format!(
+                "percentage {percent} is out of range, expected a value between 0 and {MAX_BRIGHTNESS}"
+            ));
+        }
+        if let Some(pair) = points.windows(2).find(|pair| pair[0].0 >= pair[1].0) {
+            return Err(format!(
+                "percentage {} must come after {}, percentages must grow from point to point",
+                pair[0].0, pair[1].0
+            ));
+        }
+        if let Some(pair) = points.windows(2).find(|pair| pair[0].1 > pair[1].1) {
+            return Err(format!(
+                "raw value {} at {}% is below {} at {}%, raw values must not shrink",
+                pair[1].1, pair[1].0, pair[0].1, pair[0].0
+            ));
+        }
+        let points: Vec<(f64, f64)> = points
+            .iter()
+            .map(|&(percent, raw)| (f64::from(percent), f64::from(raw)))
+            .collect();
+        Ok(Self::from_fn(|percent| {
+            interpolate(&points, percent as f64)
+        }))
+    }
+
+    /// Uses `(raw, nits)` measurements, e.g. taken with a light meter, to
+    /// pick raw values whose measured light grows in perceived lightness
+    /// (CIE L*) with the percentage.
+    ///
+    /// The lowest and highest measured raw values become 0% and 100%. Any
+    /// unit proportional to the luminance works, such as the lux measured
+    /// with a meter held against the panel.
+    pub fn from_nits(measurements: &[(u8, f64)]) -> Result<Self, String> {
+        if measurements.len() < 2 {
+            return Err("the measurements need at least two points".to_string());
+        }
+        if let Some((raw, nits)) = measurements
+            .iter()
+            .find(|(_, nits)| !nits.is_finite() || *nits < 0.0)
+        {
+            return Err(format!(
+                "luminance {nits} at raw value {raw} is invalid, expected a number of at least 0"
+            ));
+        }
+        if let Some(pair) = measurements.windows(2).find(|pair| pair[0].0 >= pair[1].0) {
+            return Err(format!(
+                "raw value {} must come after {}, raw values must grow from point to point",
+                pair[0].0, pair[1].0
+            ));
+        }
+        if let Some(pair) = measurements.windows(2).find(|pair| pair[0].1 > pair[1].1) {
+            return Err(format!(
+                "luminance {} at raw value {} is below {} at {}, the luminance must not shrink",
+                pair[1].1, pair[1].0, pair[0].1, pair[0].0
+            ));
+        }
+        let (darkest, brightest) = (measurements[0].1, measurements[measurements.len() - 1].1);
+        if darkest == brightest {
+            return Err("the luminance must differ between the first and last point".to_string());
+        }
+        // Inverse of the measured curve, from luminance to raw value
+        let points: Vec<(f64, f64)> = measurements
+            .iter()
+            .map(|&(raw, nits)| (nits, f64::from(raw)))
+            .collect();
+        Ok(Self::from_fn(|percent| {
+            let nits = darkest + (brightest - darkest) * luminance(percent as f64) / 100.0;
+            interpolate(&points, nits)
+        }))
+    }
+
+    fn from_fn(raw: impl Fn(usize) -> f64) -> Self {
+        Self {
+            raw: std::array::from_fn(|percent| raw(percent).round().clamp(0.0, 255.0) as u8),
+        }
+    }
+
+    /// Returns the raw value for `percent`, which must be at most
+    /// [`MAX_BRIGHTNESS`].
+    pub fn raw(&self, percent: u8) -> u8 {
+        self.raw[usize::from(percent.min(MAX_BRIGHTNESS))]
+    }
+
+    /// Returns the percentage whose raw value is closest to `raw`, the
+    /// lowest one if several are. Values above the one for 100% have no
+    /// percentage.
+    pub fn percent(&self, raw: u8) -> Option<u8> {
+        if raw > self.raw[STEPS - 1] {
+            return None;
+        }
+        (0..=MAX_BRIGHTNESS).min_by_key(|percent| self.raw(*percent).abs_diff(raw))
+    }
+}
+
+/// Interpolates linearly between `points` sorted by their first value,
+/// holding the first and last second value beyond them. Where points share
+/// the first value, the last of them wins.
+fn interpolate(points: &[(f64, f64)], x: f64) -> f64 {
+    match points.iter().position(|(px, _)| *px > x) {
+        Some(0) => points[0].1,
+        Some(index) => {
+            let (x0, y0) = points[index - 1];
+            let (x1, y1) = points[index];
+            y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+        }
+        None => points[points.len() - 1].1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw_values(mapping: &Mapping, percents: &[u8]) -> Vec<u8> {
+        percents
+            .iter()
+            .map(|&percent| mapping.raw(percent))
+            .collect()
+    }
+
+    /// Checks that every raw value maps back to the lowest percentage
+    /// giving it.
+    fn assert_inverts(mapping: &Mapping) {
+        for percent in 0..=MAX_BRIGHTNESS {
+            let raw = mapping.raw(percent);
+            let back = mapping.percent(raw).unwrap();
+            assert_eq!(mapping.raw(back), raw, "{percent}% back to {back}%");
+            assert!(back <= percent, "{percent}% back to {back}%");
+        }
+    }
+
+    #[test]
+    fn linear_writes_the_percentage() {
+        let mapping = Mapping::linear();
+        for percent in 0..=MAX_BRIGHTNESS {
+            assert_eq!(mapping.raw(percent), percent);
+            assert_eq!(mapping.percent(percent), Some(percent));
+        }
+        assert_eq!(mapping.percent(101), None);
+        assert_eq!(mapping.raw(200), 100);
+    }
+
+    #[test]
+    fn gamma_raises_to_the_power() {
+        let mapping = Mapping::gamma(2.0, 100).unwrap();
+        assert_eq!(
+            raw_values(&mapping, &[0, 10, 50, 90, 100]),
+            [0, 1, 25, 81, 100]
+        );
+        assert_inverts(&mapping);
+
+        let mapping = Mapping::gamma(1.0, 255).unwrap();
+        assert_eq!(raw_values(&mapping, &[0, 50, 100]), [0, 128, 255]);
+        assert_eq!(mapping.percent(128), Some(50));
+        assert_eq!(mapping.percent(255), Some(100));
+        assert_inverts(&mapping);
+    }
+
+    #[test]
+    fn rejects_invalid_gamma() {
+        for gamma in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(Mapping::gamma(gamma, 100).is_err(), "gamma {gamma}");
+        }
+    }
+
+    #[test]
+    fn perceptual_follows_lightness() {
+        let mapping = Mapping::perceptual(100);
+        assert_eq!(raw_values(&mapping, &[0, 50, 100]), [0, 18, 100]);
+        assert_inverts(&mapping);
+    }
+
+    #[test]
+    fn table_interpolates_between_points() {
+        let mapping = Mapping::from_table(&[(0, 5), (50, 20), (100, 100)]).unwrap();
+        assert_eq!(
+            raw_values(&mapping, &[0, 25, 50, 75, 100]),
+            [5, 13, 20, 60, 100]
+        );
+        // 49% rounds to the same raw value as 50%
+        assert_eq!(mapping.percent(20), Some(49));
+        assert_eq!(mapping.percent(60), Some(75));
+        assert_eq!(mapping.percent(0), Some(0));
+        assert_inverts(&mapping);
+    }
+
+    #[test]
+    fn table_holds_beyond_the_end_points() {
+        let mapping = Mapping::from_table(&[(20, 10), (80, 90)]).unwrap();
+        assert_eq!(
+            raw_values(&mapping, &[0, 10, 20, 50, 80, 90, 100]),
+            [10, 10, 10, 50, 90, 90, 90]
+        );
+        // The lowest of the percentages sharing a raw value is taken
+        assert_eq!(mapping.percent(10), Some(0));
+        assert_eq!(mapping.percent(90), Some(80));
+        assert_eq!(mapping.percent(91), None);
+        assert_inverts(&mapping);
+
+        let mapping = Mapping::from_table(&[(40, 30)]).unwrap();
+        assert_eq!(raw_values(&mapping, &[0, 40, 100]), [30, 30, 30]);
+    }
+
+    #[test]
+    fn rejects_invalid_tables() {
+        for table in [
+            &[][..],
+            &[(101, 50)],
+            &[(50, 10), (50, 20)],
+            &[(60, 10), (50, 20)],
+            &[(0, 20), (100, 10)],
+        ] {
+            assert!(Mapping::from_table(table).is_err(), "table {table:?}");
+        }
+    }
+
+    #[test]
+    fn nits_are_spread_evenly_in_lightness() {
+        // A panel whose light grows in proportion to the raw value
+        let mapping = Mapping::from_nits(&[(0, 0.0), (100, 100.0)]).unwrap();
+        assert_eq!(mapping, Mapping::perceptual(100));
+
+        // The darkest and brightest measurements become 0% and 100%
+        let mapping = Mapping::from_nits(&[(10, 5.0), (255, 405.0)]).unwrap();
+        assert_eq!(raw_values(&mapping, &[0, 100]), [10, 255]);
+        assert_inverts(&mapping);
+    }
+
+    #[test]
+    fn nits_interpolate_the_measured_curve() {
+        let mapping = Mapping::from_nits(&[(0, 0.0), (50, 10.0), (100, 100.0)]).unwrap();
+        // 10% of the luminance is a lightness of about 38%
+        assert_eq!(mapping.raw(38), 50);
+        assert_eq!(mapping.percent(50), Some(38));
+        assert_eq!(raw_values(&mapping, &[0, 100]), [0, 100]);
+        assert_inverts(&mapping);
+    }
+
+    #[test]
+    fn rejects_invalid_measurements() {
+        for measurements in [
+            &[(0, 0.0)][..],
+            &[(0, -1.0), (100, 10.0)],
+            &[(0, f64::NAN), (100, 10.0)],
+            &[(50, 1.0), (50, 10.0)],
+            &[(60, 1.0), (50, 10.0)],
+            &[(0, 10.0), (100, 1.0)],
+            &[(0, 10.0), (100, 10.0)],
+        ] {
+            assert!(
+                Mapping::from_nits(measurements).is_err(),
+                "measurements {measurements:?}"
+            );
+        }
+    }
+}
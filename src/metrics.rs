@@ -0,0 +1,299 @@
+//! Counts what happens to the displays and exports it for Prometheus.
+//!
+//! [`Panels`](crate::panels::Panels) records into [`Metrics`] as it opens,
+//! closes and writes to displays. The exporter renders them in the text
+//! exposition format on `/metrics`. Displays are identified by their serial
+//! number, or by their HID path if they have none, so that a display keeps
+//! its series when it is plugged in again under another path.
+
+use crate::cli_error::CliError;
+use crate::panels::{Action, Applied};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Header, Method, Response, Server};
+use ws170120::{DisplayInfo, Error, ReportPath};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:9170";
+
+#[derive(clap::Args)]
+pub struct MetricsArgs {
+    /// Also export Prometheus metrics on this address [default: 127.0.0.1:9170]
+    #[arg(long, value_name = "ADDRESS", num_args = 0..=1, default_missing_value = DEFAULT_ADDRESS)]
+    pub metrics: Option<SocketAddr>,
+}
+
+/// What was recorded for one display.
+#[derive(Default)]
+struct Device {
+    connected: bool,
+    brightness: Option<u8>,
+    opened: u64,
+    writes: u64,
+    output_failures: u64,
+    feature_fallbacks: u64,
+    write_failures: u64,
+}
+
+/// The metrics of every display seen since the start.
+#[derive(Default)]
+pub struct Metrics {
+    devices: Mutex<Vec<(String, Device)>>,
+}
+
+impl Metrics {
+    fn update(&self, info: &DisplayInfo, update: impl FnOnce(&mut Device)) {
+        let mut devices = self.devices.lock().unwrap();
+        let label = info.label();
+        let index = match devices.iter().position(|(known, _)| known == label) {
+            Some(index) => index,
+            None => {
+                devices.push((label.to_string(), Device::default()));
+                devices.len() - 1
+            }
+        };
+        update(&mut devices[index].1);
+    }
+
+    pub fn opened(&self, info: &DisplayInfo) {
+        self.update(info, |device| {
+            device.connected = true;
+            device.opened += 1;
+        });
+    }
+
+    pub fn closed(&self, info: &DisplayInfo) {
+        self.update(info, |device| device.connected = false);
+    }
+
+    /// Records the result of carrying out `action` on a display, which sent
+    /// a report on each of the paths in `sent`.
+    ///
+    /// Every report counts, so a fade adds one write per step.
+    pub fn record(
+        &self,
+        info: &DisplayInfo,
+        action: Action,
+        sent: &[ReportPath],
+        result: &Result<Applied, Error>,
+    ) {
+        self.update(info, |device| {
+            for path in sent {
+                device.writes += 1;
+                if *path == ReportPath::Feature {
+                    device.output_failures += 1;
+                    device.feature_fallbacks += 1;
+                }
+            }
+            match result {
+                Ok(applied) => device.brightness = Some(applied.brightness),
+                Err(Error::WriteFailed { .. }) => {
+                    device.output_failures += 1;
+                    device.write_failures += 1;
+                }
+                Err(Error::ShortWrite { .. }) => device.write_failures += 1,
+                Err(_) if !matches!(action, Action::Get | Action::Recall) => {
+                    device.write_failures += 1
+                }
+                Err(_) => {}
+            }
+        });
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let devices = self.devices.lock().unwrap();
+        let mut text = String::new();
+        let mut family =
+            |name: &str, kind: &str, help: &str, value: &dyn Fn(&Device) -> Option<u64>| {
+                let _ = writeln!(text, "# HELP {name} {help}");
+                let _ = writeln!(text, "# TYPE {name} {kind}");
+                for (label, device) in devices.iter() {
+                    if let Some(value) = value(device) {
+                        let _ = writeln!(text, "{name}{{display=\"{}\"}} {value}", escape(label));
+                    }
+                }
+            };
+        family(
+            "ws170120_connected",
+            "gauge",
+            "Whether the display is open (1) or was lost (0).",
+            &|device| Some(device.connected.into()),
+        );
+        family(
+            "ws170120_brightness_percent",
+            "gauge",
+            "Brightness last read from or written to the display.",
+            &|device| device.brightness.map(u64::from),
+        );
+        family(
+            "ws170120_writes_total",
+            "counter",
+            "Brightness reports written successfully, one per step of a fade.",
+            &|device| Some(device.writes),
+        );
+        family(
+            "ws170120_output_report_failures_total",
+            "counter",
+            "Brightness reports whose output report failed.",
+            &|device| Some(device.output_failures),
+        );
+        family(
+            "ws170120_feature_report_fallbacks_total",
+            "counter",
+            "Brightness reports written as feature report after the output report failed.",
+            &|device| Some(device.feature_fallbacks),
+        );
+        family(
+            "ws170120_write_failures_total",
+            "counter",
+            "Brightness changes that failed.",
+            &|device| Some(device.write_failures),
+        );
+        family(
+            "ws170120_reconnects_total",
+            "counter",
+            "Times the display was opened again after it was lost.",
+            &|device| Some(device.opened.saturating_sub(1)),
+        );
+        text
+    }
+}
+
+/// Escapes a label value as the exposition format requires.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// Starts exporting `metrics` on the address given in `args`, if any, in
+/// the background.
+pub fn start(metrics: Arc<Metrics>, args: &MetricsArgs, verbose: u8) -> Result<(), CliError> {
+    let Some(address) = args.metrics else {
+        return Ok(());
+    };
+    let server = Server::http(address).map_err(CliError::Http)?;
+    if verbose > 0 {
+        println!("Exporting metrics on http://{}/metrics.", address);
+    }
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if *request.method() == Method::Get && request.url() == "/metrics" {
+                Response::from_string(metrics.render()).with_header(
+                    Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap(),
+                )
+            } else {
+                Response::from_string("Not found.\n").with_status_code(404)
+            };
+            let _ = request.respond(response);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::panels::{MockBus, Panels, Request};
+    use crate::state::State;
+    use crate::test_dir::TestDir;
+    use std::time::Duration;
+    use ws170120::{brightness_report, Curve, Fade, Selector};
+
+    fn display(path: &str, serial: Option<&str>) -> DisplayInfo {
+        DisplayInfo {
+            path: path.to_string(),
+            serial: serial.map(str::to_string),
+            interface_number: 0,
+            usage_page: 0xff00,
+        }
+    }
+
+    #[test]
+    fn renders_what_the_panels_did() {
+        let dir = TestDir::new("metrics-panels");
+        let first = display("/dev/hidraw1", Some("A\"1"));
+        let second = display("/dev/hidraw3", None);
+        let bus = MockBus::default();
+        let first_transport = bus.attach(first.clone());
+        let second_transport = bus.attach(second.clone());
+        let mut panels = Panels::with_bus(bus.clone(), State::in_dir(dir.path()));
+        let metrics = panels.metrics();
+        let on_first = Selector::Serial("A\"1".to_string());
+        let on_second = Selector::Path(second.path.clone());
+        let mut run = |selector: &Selector, action: Action, fade: Option<Fade>| {
+            let request = Request { action, fade };
+            let mut outcomes = panels.execute(selector, &request).unwrap();
+            outcomes.remove(0).result.map(|applied| applied.brightness)
+        };
+
+        assert_eq!(run(&on_first, Action::Set(20), None).unwrap(), 20);
+        // Every step of the fade only gets through as feature report
+        first_transport.fail_output(true);
+        let fade = Fade::new(Duration::from_millis(150), Curve::Linear);
+        let steps = fade.steps(20, 60).len();
+        assert!(steps > 1);
+        assert_eq!(run(&on_first, Action::Set(60), Some(fade)).unwrap(), 60);
+        // Neither report gets through, which closes the display
+        first_transport.fail_feature(true);
+        assert!(run(&on_first, Action::Step(10), None).is_err());
+        // Opened again, it takes only part of the report
+        first_transport.fail_output(false);
+        first_transport.fail_feature(false);
+        first_transport.short_write(Some(3));
+        assert!(run(&on_first, Action::Set(0), None).is_err());
+        assert_eq!(first_transport.reports().len(), 1 + steps + 1);
+
+        second_transport.push_feature_response(brightness_report(25));
+        assert_eq!(run(&on_second, Action::Get, None).unwrap(), 25);
+        assert!(run(&on_second, Action::Get, None).is_err());
+        assert_eq!(run(&on_second, Action::Set(30), None).unwrap(), 30);
+        bus.detach(&second);
+        assert_eq!(panels.refresh().unwrap(), [second]);
+
+        let expected = format!(
+            r#"# HELP ws170120_connected Whether the display is open (1) or was lost (0).
+# TYPE ws170120_connected gauge
+ws170120_connected{{display="A\"1"}} 1
+ws170120_connected{{display="/dev/hidraw3"}} 0
+# HELP ws170120_brightness_percent Brightness last read from or written to the display.
+# TYPE ws170120_brightness_percent gauge
+ws170120_brightness_percent{{display="A\"1"}} 60
+ws170120_brightness_percent{{display="/dev/hidraw3"}} 30
+# HELP ws170120_writes_total Brightness reports written successfully, one per step of a fade.
+# TYPE ws170120_writes_total counter
+ws170120_writes_total{{display="A\"1"}} {writes}
+ws170120_writes_total{{display="/dev/hidraw3"}} 1
+# HELP ws170120_output_report_failures_total Brightness reports whose output report failed.
+# TYPE ws170120_output_report_failures_total counter
+ws170120_output_report_failures_total{{display="A\"1"}} {output_failures}
+ws170120_output_report_failures_total{{display="/dev/hidraw3"}} 0
+# HELP ws170120_feature_report_fallbacks_total Brightness reports written as feature report after the output report failed.
+# TYPE ws170120_feature_report_fallbacks_total counter
+ws170120_feature_report_fallbacks_total{{display="A\"1"}} {steps}
+ws170120_feature_report_fallbacks_total{{display="/dev/hidraw3"}} 0
+# HELP ws170120_write_failures_total Brightness changes that failed.
+# TYPE ws170120_write_failures_total counter
+ws170120_write_failures_total{{display="A\"1"}} 2
+ws170120_write_failures_total{{display="/dev/hidraw3"}} 0
+# HELP ws170120_reconnects_total Times the display was opened again after it was lost.
+# TYPE ws170120_reconnects_total counter
+ws170120_reconnects_total{{display="A\"1"}} 1
+ws170120_reconnects_total{{display="/dev/hidraw3"}} 0
+"#,
+            writes = 1 + steps,
+            output_failures = steps + 1,
+        );
+        assert_eq!(metrics.render(), expected);
+    }
+
+    #[test]
+    fn renders_nothing_but_headers_without_displays() {
+        let text = Metrics::default().render();
+        assert_eq!(text.lines().count(), 14);
+        assert!(text.lines().all(|line| line.starts_with("# ")));
+    }
+}
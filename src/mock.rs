@@ -0,0 +1,108 @@
+//! An in-memory [`Transport`] for running the protocol without hardware.
+
+use crate::transport::Transport;
+use hidapi::{HidError, HidResult};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+/// A report sent through a [`MockTransport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Report {
+    Output(Vec<u8>),
+    Feature(Vec<u8>),
+}
+
+/// A transport that records every report instead of talking to a device.
+///
+/// Failures can be injected to exercise the fallback and error paths.
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    reports: RefCell<Vec<Report>>,
+    feature_responses: RefCell<VecDeque<Vec<u8>>>,
+    fail_output: Cell<bool>,
+    fail_feature: Cell<bool>,
+    short_write: Cell<Option<usize>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes output reports fail, forcing the feature report fallback.
+    pub fn fail_output(&self, fail: bool) {
+        self.fail_output.set(fail);
+    }
+
+    /// Makes sending and reading feature reports fail.
+    pub fn fail_feature(&self, fail: bool) {
+        self.fail_feature.set(fail);
+    }
+
+    /// Makes output reports claim to have written only `len` bytes.
+    pub fn short_write(&self, len: Option<usize>) {
+        self.short_write.set(len);
+    }
+
+    /// Queues the data returned by the next feature report read.
+    pub fn push_feature_response(&self, data: impl Into<Vec<u8>>) {
+        self.feature_responses.borrow_mut().push_back(data.into());
+    }
+
+    /// Returns all reports sent so far, in order.
+    pub fn reports(&self) -> Vec<Report> {
+        self.reports.borrow().clone()
+    }
+
+    /// Returns the most recently sent report.
+    pub fn last_report(&self) -> Option<Report> {
+        self.reports.borrow().last().cloned()
+    }
+
+    /// Forgets all recorded reports.
+    pub fn clear(&self) {
+        self.reports.borrow_mut().clear();
+    }
+}
+
+fn injected(operation: &str) -> HidError {
+    HidError::HidApiError {
+        message: format!("mock {operation} failure"),
+    }
+}
+
+impl Transport for MockTransport {
+    fn write_output_report(&self, data: &[u8]) -> HidResult<usize> {
+        if self.fail_output.get() {
+            return Err(injected("output report"));
+        }
+        self.reports
+            .borrow_mut()
+            .push(Report::Output(data.to_vec()));
+        Ok(self.short_write.get().unwrap_or(data.len()))
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> HidResult<()> {
+        if self.fail_feature.get() {
+            return Err(injected("feature report"));
+        }
+        self.reports
+            .borrow_mut()
+            .push(Report::Feature(data.to_vec()));
+        Ok(())
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize> {
+        if self.fail_feature.get() {
+            return Err(injected("feature report"));
+        }
+        let response = self
+            .feature_responses
+            .borrow_mut()
+            .pop_front()
+            .ok_or_else(|| injected("feature report read"))?;
+        let len = response.len().min(buf.len());
+        buf[..len].copy_from_slice(&response[..len]);
+        Ok(len)
+    }
+}
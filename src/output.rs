@@ -0,0 +1,399 @@
+//! Machine-readable results for `--output json`.
+//!
+//! Every invocation prints exactly one JSON document on a single line, except
+//! for monitoring commands, which print one document per event.
+
+use crate::cli_error::CliError;
+#[cfg(feature = "http")]
+use crate::panels::Change;
+use crate::panels::{Action, Outcome, Request};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::Path;
+use ws170120::descriptor::{Node, ReportDescriptor, Usage};
+use ws170120::touch::{TouchEvent, Transform};
+use ws170120::{DisplayInfo, ReportPath};
+
+/// How results are printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Human-readable messages
+    #[default]
+    Text,
+    /// One JSON document on stdout
+    Json,
+}
+
+#[derive(Serialize)]
+struct DisplayJson<'a> {
+    path: &'a str,
+    serial: Option<&'a str>,
+    interface: i32,
+}
+
+impl<'a> From<&'a DisplayInfo> for DisplayJson<'a> {
+    fn from(info: &'a DisplayInfo) -> Self {
+        DisplayJson {
+            path: &info.path,
+            serial: info.serial.as_deref(),
+            interface: info.interface_number,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorJson {
+    kind: String,
+    message: String,
+}
+
+impl From<&CliError> for ErrorJson {
+    fn from(e: &CliError) -> Self {
+        ErrorJson {
+            kind: e.kind().to_string(),
+            message: e.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct OutcomeJson<'a> {
+    #[serde(flatten)]
+    display: DisplayJson<'a>,
+    ok: bool,
+    /// The brightness read or written.
+    brightness: Option<u8>,
+    /// `output`, or `feature` if the output report failed and the feature
+    /// report fallback was used. `None` if nothing was written.
+    transport: Option<&'static str>,
+    error: Option<ErrorJson>,
+}
+
+#[derive(Serialize)]
+struct RequestJson<'a> {
+    action: &'static str,
+    requested: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    step: Option<i16>,
+    displays: Vec<OutcomeJson<'a>>,
+}
+
+#[derive(Serialize)]
+struct ListJson<'a> {
+    displays: Vec<DisplayJson<'a>>,
+}
+
+#[cfg(feature = "http")]
+#[derive(Serialize)]
+struct ChangeJson<'a> {
+    event: &'static str,
+    #[serde(flatten)]
+    display: DisplayJson<'a>,
+    brightness: u8,
+}
+
+#[derive(Serialize)]
+struct FailureJson {
+    error: ErrorJson,
+}
+
+fn print(document: &impl Serialize) {
+    match serde_json::to_string(document) {
+        Ok(json) => println!("{json}"),
+        Err(e) => eprintln!("Encoding the result failed: {e}"),
+    }
+}
+
+/// Prints the attached displays.
+pub fn print_list(displays: &[DisplayInfo]) {
+    print(&list(displays));
+}
+
+/// The document listing the attached displays.
+pub fn list(displays: &[DisplayInfo]) -> impl Serialize + '_ {
+    ListJson {
+        displays: displays.iter().map(DisplayJson::from).collect(),
+    }
+}
+
+/// Prints the result of `request` for each selected display.
+pub fn print_outcomes(request: &Request, outcomes: &[Outcome]) {
+    print(&self::outcomes(request, outcomes));
+}
+
+/// The document with the result of `request` for each selected display.
+pub fn outcomes<'a>(request: &Request, outcomes: &'a [Outcome]) -> impl Serialize + 'a {
+    let (action, requested, step) = match request.action {
+        Action::Get => ("get", None, None),
+        Action::Recall => ("recall", None, None),
+        Action::Set(brightness) => ("set", Some(brightness), None),
+        Action::Step(delta) => ("step", None, Some(delta)),
+    };
+    let displays = outcomes
+        .iter()
+        .map(|outcome| {
+            let applied = outcome.result.as_ref().ok();
+            OutcomeJson {
+                display: DisplayJson::from(&outcome.display),
+                ok: applied.is_some(),
+                brightness: applied.map(|applied| applied.brightness),
+                transport: applied
+                    .and_then(|applied| applied.path)
+                    .map(|path| match path {
+                        ReportPath::Output => "output",
+                        ReportPath::Feature => "feature",
+                    }),
+                error: outcome.result.as_ref().err().map(ErrorJson::from),
+            }
+        })
+        .collect();
+    RequestJson {
+        action,
+        requested,
+        step,
+        displays,
+    }
+}
+
+/// The document announcing a brightness change.
+#[cfg(feature = "http")]
+pub fn change(change: &Change) -> impl Serialize + '_ {
+    ChangeJson {
+        event: "brightness",
+        display: DisplayJson::from(&change.display),
+        brightness: change.brightness,
+    }
+}
+
+fn usage_json(usage: &Usage) -> Value {
+    json!({
+        "page": usage.page,
+        "id": usage.id,
+        "name": usage.to_string(),
+    })
+}
+
+fn node_json(node: &Node) -> Value {
+    match node {
+        Node::Collection(collection) => json!({
+            "type": "collection",
+            "kind": collection.kind.to_string(),
+            "usage": collection.usage.as_ref().map(usage_json),
+            "children": collection.children.iter().map(node_json).collect::<Vec<_>>(),
+        }),
+        Node::Field(field) => json!({
+            "type": "field",
+            "kind": field.kind.to_string(),
+            "report_id": field.report_id,
+            "usages": field.usages.iter().map(usage_json).collect::<Vec<_>>(),
+            "usage_minimum": field.usage_range.map(|(minimum, _)| usage_json(&minimum)),
+            "usage_maximum": field.usage_range.map(|(_, maximum)| usage_json(&maximum)),
+            "report_size": field.report_size,
+            "report_count": field.report_count,
+            "logical_minimum": field.logical_minimum,
+            "logical_maximum": field.logical_maximum,
+            "constant": field.is_constant(),
+            "variable": field.is_variable(),
+            "relative": field.is_relative(),
+        }),
+    }
+}
+
+/// Prints a report descriptor as a tree, with a summary of its reports.
+pub fn print_descriptor(bytes: &[u8], descriptor: &ReportDescriptor) {
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    let reports: Vec<Value> = descriptor
+        .reports()
+        .iter()
+        .map(|report| {
+            json!({
+                "report_id": report.report_id,
+                "kind": report.kind.to_string(),
+                "bits": report.bits,
+                "bytes": report.bytes(),
+            })
+        })
+        .collect();
+    print(&json!({
+        "bytes": hex,
+        "reports": reports,
+        "tree": descriptor.nodes.iter().map(node_json).collect::<Vec<_>>(),
+    }));
+}
+
+/// Prints a touch event.
+pub fn print_touch_event(event: &TouchEvent) {
+    print(&json!({
+        "event": event.phase.to_string(),
+        "id": event.contact.id,
+        "x": event.contact.x,
+        "y": event.contact.y,
+        "tip": event.contact.tip,
+    }));
+}
+
+/// Prints the result of a touch calibration.
+pub fn print_calibration(transform: &Transform, deviation: f64, saved: Option<&Path>) {
+    print(&json!({
+        "transform": transform.0,
+        "deviation": deviation,
+        "saved": saved,
+    }));
+}
+
+/// Prints an error that kept the request from being carried out at all.
+pub fn print_error(e: &CliError) {
+    print(&failure(e));
+}
+
+/// The document reporting an error that kept the request from being carried
+/// out at all.
+pub fn failure(e: &CliError) -> impl Serialize {
+    FailureJson { error: e.into() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::panels::apply;
+    use crate::state::State;
+    use crate::test_dir::TestDir;
+    use ws170120::mock::MockTransport;
+    use ws170120::{brightness_report, Display, Error};
+
+    fn info(path: &str, serial: Option<&str>) -> DisplayInfo {
+        DisplayInfo {
+            path: path.to_string(),
+            serial: serial.map(str::to_string),
+            interface_number: 0,
+            usage_page: 0xff00,
+        }
+    }
+
+    /// Carries out `request` on `display` like the panels do and returns
+    /// the JSON document of the outcome.
+    fn run(display: &Display<MockTransport>, state: &State, request: Request) -> Value {
+        let outcomes = [Outcome {
+            display: info("/dev/hidraw3", Some("0123")),
+            result: apply(display, state, None, &request, &mut Vec::new()).map_err(CliError::from),
+        }];
+        serde_json::to_value(self::outcomes(&request, &outcomes)).unwrap()
+    }
+
+    fn request(action: Action) -> Request {
+        Request { action, fade: None }
+    }
+
+    #[test]
+    fn reports_what_was_read() {
+        let dir = TestDir::new("output-get");
+        let state = State::in_dir(dir.path());
+        let display = Display::new(MockTransport::new());
+        display
+            .transport()
+            .push_feature_response(brightness_report(55));
+        assert_eq!(
+            run(&display, &state, request(Action::Get)),
+            json!({
+                "action": "get",
+                "requested": null,
+                "displays": [{
+                    "path": "/dev/hidraw3",
+                    "serial": "0123",
+                    "interface": 0,
+                    "ok": true,
+                    "brightness": 55,
+                    "transport": null,
+                    "error": null,
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn reports_how_the_brightness_was_written() {
+        let dir = TestDir::new("output-set");
+        let state = State::in_dir(dir.path());
+        let display = Display::new(MockTransport::new());
+        let written = |document: Value| document["displays"][0]["transport"].clone();
+        assert_eq!(
+            written(run(&display, &state, request(Action::Set(40)))),
+            "output"
+        );
+
+        display.transport().fail_output(true);
+        let document = run(&display, &state, request(Action::Set(40)));
+        assert_eq!(document["action"], "set");
+        assert_eq!(document["requested"], 40);
+        assert!(document.get("step").is_none());
+        assert_eq!(document["displays"][0]["ok"], true);
+        assert_eq!(document["displays"][0]["brightness"], 40);
+        assert_eq!(written(document), "feature");
+    }
+
+    #[test]
+    fn reports_errors_per_display() {
+        let dir = TestDir::new("output-step");
+        let state = State::in_dir(dir.path());
+        // Nothing to step from, as the display can't be read back
+        let display = Display::new(MockTransport::new());
+        let document = run(&display, &state, request(Action::Step(-10)));
+        assert_eq!(document["action"], "step");
+        assert_eq!(document["requested"], Value::Null);
+        assert_eq!(document["step"], -10);
+        let outcome = &document["displays"][0];
+        assert_eq!(outcome["ok"], false);
+        assert_eq!(outcome["brightness"], Value::Null);
+        assert_eq!(outcome["transport"], Value::Null);
+        assert_eq!(outcome["error"]["kind"], "read-failed");
+        assert!(outcome["error"]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Failed to read brightness"));
+
+        display.transport().fail_output(true);
+        display.transport().fail_feature(true);
+        let document = run(&display, &state, request(Action::Set(40)));
+        assert_eq!(document["displays"][0]["error"]["kind"], "write-failed");
+    }
+
+    #[test]
+    fn lists_displays() {
+        let displays = [
+            info("/dev/hidraw3", Some("0123")),
+            info("/dev/hidraw5", None),
+        ];
+        assert_eq!(
+            serde_json::to_value(list(&displays)).unwrap(),
+            json!({
+                "displays": [
+                    { "path": "/dev/hidraw3", "serial": "0123", "interface": 0 },
+                    { "path": "/dev/hidraw5", "serial": null, "interface": 0 },
+                ],
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(list(&[])).unwrap(),
+            json!({ "displays": [] })
+        );
+    }
+
+    #[test]
+    fn reports_failures() {
+        let error = CliError::from(Error::NotConnected);
+        assert_eq!(
+            serde_json::to_value(failure(&error)).unwrap(),
+            json!({
+                "error": {
+                    "kind": "not-connected",
+                    "message": "Waveshare monitor WS170120 is not connected.",
+                },
+            })
+        );
+        let error = CliError::InvalidArgument("Unknown preset 'dusk'.".to_string());
+        assert_eq!(
+            serde_json::to_value(failure(&error)).unwrap()["error"]["kind"],
+            "invalid-argument"
+        );
+    }
+}
@@ -0,0 +1,536 @@
+//! Open displays and the commands run on them, shared by one-shot
+//! invocations and the daemon.
+
+use crate::cli_error::CliError;
+#[cfg(all(unix, feature = "metrics"))]
+use crate::metrics::Metrics;
+use crate::state::State;
+use hidapi::{HidApi, HidDevice};
+#[cfg(test)]
+use std::cell::RefCell;
+#[cfg(test)]
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender};
+#[cfg(all(unix, feature = "metrics"))]
+use std::sync::Arc;
+#[cfg(test)]
+use ws170120::mock::MockTransport;
+use ws170120::{
+    find_displays, step_brightness, Display, DisplayInfo, Error, Fade, Mapping, ReportPath,
+    Selector, Transport,
+};
+
+/// What to do with each selected display.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Get,
+    /// Like `Get`, but recalls the last brightness written if the display
+    /// can't be read back. Only used internally, e.g. to restore it later.
+    Recall,
+    Set(u8),
+    Step(i16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Request {
+    pub action: Action,
+    /// Fade to the new brightness instead of jumping. Ignored for `Get`.
+    pub fade: Option<Fade>,
+}
+
+/// The brightness of a display after a request was carried out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Applied {
+    pub brightness: u8,
+    /// How the last report was sent, `None` if nothing was written.
+    pub path: Option<ReportPath>,
+}
+
+/// The result of a request for one display.
+#[derive(Debug)]
+pub struct Outcome {
+    pub display: DisplayInfo,
+    pub result: Result<Applied, CliError>,
+}
+
+/// A brightness written through [`Panels`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub display: DisplayInfo,
+    pub brightness: u8,
+}
+
+/// Where [`Panels`] finds and opens displays.
+pub trait Bus {
+    type Transport: Transport;
+
+    /// Lists all attached displays as of the last enumeration.
+    fn displays(&self) -> Vec<DisplayInfo>;
+
+    /// Enumerates the attached displays again.
+    fn refresh(&mut self) -> Result<(), Error>;
+
+    /// Opens the display `info` found on this bus.
+    fn open(&self, info: &DisplayInfo) -> Result<Display<Self::Transport>, Error>;
+}
+
+impl Bus for HidApi {
+    type Transport = HidDevice;
+
+    fn displays(&self) -> Vec<DisplayInfo> {
+        find_displays(self)
+    }
+
+    fn refresh(&mut self) -> Result<(), Error> {
+        self.refresh_devices().map_err(Error::HidInit)
+    }
+
+    fn open(&self, info: &DisplayInfo) -> Result<Display, Error> {
+        info.open(self)
+    }
+}
+
+/// A bus of displays whose transports the test keeps a handle on, to inject
+/// failures and inspect the reports sent while [`Panels`] has them open.
+///
+/// Clones share the attached displays, so that a test can plug displays in
+/// and out after handing the bus to [`Panels`].
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MockBus {
+    attached: Rc<RefCell<Vec<Attached>>>,
+}
+
+#[cfg(test)]
+type Attached = (DisplayInfo, Rc<MockTransport>);
+
+#[cfg(test)]
+impl MockBus {
+    /// Attaches a display, returning its transport.
+    pub fn attach(&self, info: DisplayInfo) -> Rc<MockTransport> {
+        let transport = Rc::new(MockTransport::new());
+        self.attached
+            .borrow_mut()
+            .push((info, Rc::clone(&transport)));
+        transport
+    }
+
+    /// Unplugs a display, which [`Panels::refresh`] then closes.
+    pub fn detach(&self, info: &DisplayInfo) {
+        self.attached
+            .borrow_mut()
+            .retain(|(attached, _)| attached != info);
+    }
+}
+
+#[cfg(test)]
+impl Bus for MockBus {
+    type Transport = Rc<MockTransport>;
+
+    fn displays(&self) -> Vec<DisplayInfo> {
+        let attached = self.attached.borrow();
+        attached.iter().map(|(info, _)| info.clone()).collect()
+    }
+
+    fn refresh(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn open(&self, info: &DisplayInfo) -> Result<Display<Self::Transport>, Error> {
+        self.attached
+            .borrow()
+            .iter()
+            .find(|(attached, _)| attached == info)
+            .map(|(_, transport)| Display::new(Rc::clone(transport)))
+            .ok_or(Error::NotConnected)
+    }
+}
+
+/// The bus together with the displays opened so far.
+pub struct Panels<B: Bus = HidApi> {
+    bus: B,
+    open: Vec<(DisplayInfo, Display<B::Transport>)>,
+    mapping: Mapping,
+    state: State,
+    subscribers: Vec<Sender<Change>>,
+    #[cfg(all(unix, feature = "metrics"))]
+    metrics: Arc<Metrics>,
+}
+
+impl Panels {
+    pub fn new() -> Result<Self, Error> {
+        // Initialize HID API
+        let api = HidApi::new().map_err(Error::HidInit)?;
+        Ok(Self::with_bus(api, State::from_env()))
+    }
+
+    /// Creates panels that see no displays until refreshed, for tests that
+    /// must not touch attached hardware.
+    #[cfg(test)]
+    pub fn without_displays() -> Result<Self, Error> {
+        let mut panels = Self::new()?;
+        panels.bus.reset_devices().map_err(Error::HidInit)?;
+        Ok(panels)
+    }
+}
+
+impl<B: Bus> Panels<B> {
+    /// Creates panels for the displays on `bus`, remembering their
+    /// brightness in `state`.
+    pub fn with_bus(bus: B, state: State) -> Self {
+        Self {
+            bus,
+            open: Vec::new(),
+            mapping: Mapping::default(),
+            state,
+            subscribers: Vec::new(),
+            #[cfg(all(unix, feature = "metrics"))]
+            metrics: Arc::default(),
+        }
+    }
+
+    /// Returns the metrics recorded for the displays, which can be read
+    /// without locking the panels during a fade.
+    #[cfg(all(unix, feature = "metrics"))]
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Translates percentages through `mapping` on every display, see
+    /// [`Display::set_mapping`].
+    pub fn set_mapping(&mut self, mapping: Mapping) {
+        for (_, display) in &mut self.open {
+            display.set_mapping(mapping.clone());
+        }
+        self.mapping = mapping;
+    }
+
+    /// Returns a receiver for every brightness change made from now on.
+    pub fn subscribe(&mut self) -> Receiver<Change> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    fn notify(&mut self, change: Change) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(change.clone()).is_ok());
+    }
+
+    /// Lists all attached displays as of the last enumeration.
+    pub fn displays(&self) -> Vec<DisplayInfo> {
+        self.bus.displays()
+    }
+
+    /// Re-enumerates the bus and closes displays that have disappeared.
+    ///
+    /// Returns the displays that were closed.
+    pub fn refresh(&mut self) -> Result<Vec<DisplayInfo>, Error> {
+        self.bus.refresh()?;
+        let present = self.displays();
+        let (kept, gone) = self
+            .open
+            .drain(..)
+            .partition(|(info, _)| present.contains(info));
+        self.open = kept;
+        #[cfg(all(unix, feature = "metrics"))]
+        for (info, _) in &gone {
+            self.metrics.closed(info);
+        }
+        Ok(gone.into_iter().map(|(info, _)| info).collect())
+    }
+
+    /// Opens every attached display that is not open yet.
+    ///
+    /// Returns the displays that were opened, and those that failed to open.
+    pub fn connect_all(&mut self) -> (Vec<DisplayInfo>, Vec<(DisplayInfo, Error)>) {
+        let mut opened = Vec::new();
+        let mut failed = Vec::new();
+        for info in self.displays() {
+            if self.is_open(&info) {
+                continue;
+            }
+            match self.bus.open(&info) {
+                Ok(display) => {
+                    opened.push(info.clone());
+                    self.add(info, display);
+                }
+                Err(e) => failed.push((info, e)),
+            }
+        }
+        (opened, failed)
+    }
+
+    fn add(&mut self, info: DisplayInfo, mut display: Display<B::Transport>) {
+        display.set_mapping(self.mapping.clone());
+        #[cfg(all(unix, feature = "metrics"))]
+        self.metrics.opened(&info);
+        self.open.push((info, display));
+    }
+
+    fn is_open(&self, info: &DisplayInfo) -> bool {
+        self.open.iter().any(|(open, _)| open == info)
+    }
+
+    /// Returns the handle for `info`, opening the display if necessary.
+    fn display(&mut self, info: &DisplayInfo) -> Result<&Display<B::Transport>, Error> {
+        let index = match self.open.iter().position(|(open, _)| open == info) {
+            Some(index) => index,
+            None => {
+                let display = self.bus.open(info)?;
+                self.add(info.clone(), display);
+                self.open.len() - 1
+            }
+        };
+        Ok(&self.open[index].1)
+    }
+
+    /// Carries out `request` on every display chosen by `selector`.
+    pub fn execute(
+        &mut self,
+        selector: &Selector,
+        request: &Request,
+    ) -> Result<Vec<Outcome>, Error> {
+        let selected = selector.select(self.displays())?;
+        Ok(selected
+            .into_iter()
+            .map(|info| {
+                let mut sent = Vec::new();
+                let result = self.execute_on(&info, request, &mut sent);
+                #[cfg(all(unix, feature = "metrics"))]
+                self.metrics.record(&info, request.action, &sent, &result);
+                match &result {
+                    Ok(applied) if applied.path.is_some() => self.notify(Change {
+                        display: info.clone(),
+                        brightness: applied.brightness,
+                    }),
+                    Err(e) if is_connection_error(e) => {
+                        // Reopen on next use, the device may have been replugged
+                        self.open.retain(|(open, _)| open != &info);
+                        #[cfg(all(unix, feature = "metrics"))]
+                        self.metrics.closed(&info);
+                    }
+                    _ => {}
+                }
+                Outcome {
+                    display: info,
+                    result: result.map_err(CliError::from),
+                }
+            })
+            .collect())
+    }
+
+    fn execute_on(
+        &mut self,
+        info: &DisplayInfo,
+        request: &Request,
+        sent: &mut Vec<ReportPath>,
+    ) -> Result<Applied, Error> {
+        let state = self.state.clone();
+        let serial = info.serial.as_deref();
+        apply(self.display(info)?, &state, serial, request, sent)
+    }
+}
+
+/// Carries out `request` on an open display, keeping the state file of the
+/// display with the serial number `serial` in `state` up to date.
+///
+/// The path of every report sent is added to `sent`, including those of a
+/// fade that fails halfway.
+pub fn apply<T: Transport>(
+    display: &Display<T>,
+    state: &State,
+    serial: Option<&str>,
+    request: &Request,
+    sent: &mut Vec<ReportPath>,
+) -> Result<Applied, Error> {
+    match request.action {
+        Action::Get => Ok(Applied {
+            brightness: display.brightness()?,
+            path: None,
+        }),
+        Action::Recall => Ok(Applied {
+            brightness: recall(display, state, serial)?,
+            path: None,
+        }),
+        Action::Set(brightness) => {
+            if request.fade.is_some() {
+                // Without a known starting point the fade degrades to a jump
+                let _ = recall(display, state, serial);
+            }
+            set(display, state, serial, brightness, request.fade, sent)
+        }
+        Action::Step(delta) => {
+            let brightness = step_brightness(recall(display, state, serial)?, delta);
+            set(display, state, serial, brightness, request.fade, sent)
+        }
+    }
+}
+
+fn is_connection_error(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::Device { .. } | Error::AccessDenied { .. } | Error::WriteFailed { .. }
+    )
+}
+
+fn set<T: Transport>(
+    display: &Display<T>,
+    state: &State,
+    serial: Option<&str>,
+    brightness: u8,
+    fade: Option<Fade>,
+    sent: &mut Vec<ReportPath>,
+) -> Result<Applied, Error> {
+    let path = match fade {
+        Some(fade) if display.last_brightness().is_some() => {
+            display.fade_with(brightness, &fade, |_, path| sent.push(path))?
+        }
+        _ => {
+            let path = display.set_brightness(brightness)?;
+            sent.push(path);
+            path
+        }
+    };
+    // The state file is only a fallback, so failing to write it is not fatal
+    let _ = state.store_brightness(serial, brightness);
+    Ok(Applied {
+        brightness,
+        path: Some(path),
+    })
+}
+
+/// Reads the current brightness, falling back to the last value written
+/// through `display`, or else to the one in the state file.
+fn recall<T: Transport>(
+    display: &Display<T>,
+    state: &State,
+    serial: Option<&str>,
+) -> Result<u8, Error> {
+    match display.brightness() {
+        Ok(brightness) => Ok(brightness),
+        Err(e) => match display
+            .last_brightness()
+            .or_else(|| state.load_brightness(serial))
+        {
+            Some(brightness) => {
+                display.remember_brightness(brightness);
+                Ok(brightness)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use ws170120::brightness_report;
+    use ws170120::mock::MockTransport;
+
+    const SERIAL: Option<&str> = Some("panels-test");
+
+    fn run(display: &Display<MockTransport>, state: &State, action: Action) -> Result<u8, Error> {
+        let request = Request { action, fade: None };
+        apply(display, state, SERIAL, &request, &mut Vec::new()).map(|applied| applied.brightness)
+    }
+
+    #[test]
+    fn get_only_reports_what_is_read_back() {
+        let dir = TestDir::new("panels-get");
+        let state = State::in_dir(dir.path());
+        state.store_brightness(SERIAL, 70).unwrap();
+        let display = Display::new(MockTransport::new());
+
+        assert!(matches!(
+            run(&display, &state, Action::Get),
+            Err(Error::ReadFailed(_))
+        ));
+        run(&display, &state, Action::Set(40)).unwrap();
+        assert!(run(&display, &state, Action::Get).is_err());
+
+        display
+            .transport()
+            .push_feature_response(brightness_report(55));
+        assert_eq!(run(&display, &state, Action::Get).unwrap(), 55);
+    }
+
+    #[test]
+    fn recall_and_step_fall_back_to_the_last_value_written() {
+        let dir = TestDir::new("panels-recall");
+        let state = State::in_dir(dir.path());
+        let display = Display::new(MockTransport::new());
+        assert!(run(&display, &state, Action::Recall).is_err());
+        assert!(run(&display, &state, Action::Step(5)).is_err());
+
+        // From the state file, before anything was written
+        state.store_brightness(SERIAL, 70).unwrap();
+        assert_eq!(run(&display, &state, Action::Recall).unwrap(), 70);
+        assert_eq!(run(&display, &state, Action::Step(-10)).unwrap(), 60);
+        assert_eq!(state.load_brightness(SERIAL), Some(60));
+
+        // The value written through the display wins over the state file
+        state.store_brightness(SERIAL, 20).unwrap();
+        assert_eq!(run(&display, &state, Action::Recall).unwrap(), 60);
+
+        // A value read back wins over both
+        display
+            .transport()
+            .push_feature_response(brightness_report(35));
+        assert_eq!(run(&display, &state, Action::Step(5)).unwrap(), 40);
+    }
+
+    #[test]
+    fn fails_without_displays() {
+        let mut panels = Panels::without_displays().unwrap();
+        assert!(panels.displays().is_empty());
+        let request = Request {
+            action: Action::Step(5),
+            fade: None,
+        };
+        assert!(matches!(
+            panels.execute(&Selector::First, &request),
+            Err(Error::NotConnected)
+        ));
+    }
+
+    #[test]
+    fn reopens_displays_after_losing_them() {
+        let dir = TestDir::new("panels-reopen");
+        let info = DisplayInfo {
+            path: "/dev/hidraw5".to_string(),
+            serial: SERIAL.map(str::to_string),
+            interface_number: 0,
+            usage_page: 0xff00,
+        };
+        let bus = MockBus::default();
+        let transport = bus.attach(info.clone());
+        let mut panels = Panels::with_bus(bus.clone(), State::in_dir(dir.path()));
+        let changes = panels.subscribe();
+        let set = |panels: &mut Panels<MockBus>, brightness| {
+            let request = Request {
+                action: Action::Set(brightness),
+                fade: None,
+            };
+            let mut outcomes = panels.execute(&Selector::First, &request).unwrap();
+            outcomes.remove(0).result.map(|applied| applied.path)
+        };
+
+        assert_eq!(set(&mut panels, 30).unwrap(), Some(ReportPath::Output));
+        assert_eq!(panels.open.len(), 1);
+        transport.fail_output(true);
+        transport.fail_feature(true);
+        assert!(set(&mut panels, 40).is_err());
+        assert!(panels.open.is_empty());
+        transport.fail_feature(false);
+        assert_eq!(set(&mut panels, 50).unwrap(), Some(ReportPath::Feature));
+        assert_eq!(panels.open.len(), 1);
+
+        let brightness: Vec<u8> = changes.try_iter().map(|change| change.brightness).collect();
+        assert_eq!(brightness, [30, 50]);
+        bus.detach(&info);
+        assert_eq!(panels.refresh().unwrap(), [info]);
+        assert!(panels.displays().is_empty());
+    }
+}
@@ -0,0 +1,501 @@
+//! The line based protocol spoken on the daemon's control socket.
+//!
+//! A client sends one request per line:
+//!
+//! ```text
+//! get [serial=<serial>|path=<path>|all]
+//! recall [selector]
+//! set <brightness> [fade=<duration>] [curve=<curve>] [rate=<steps>] [selector]
+//! step <+/-delta> [fade=<duration>] [curve=<curve>] [rate=<steps>] [selector]
+//! ```
+//!
+//! The daemon answers with one line per selected display, followed by a
+//! final `ok` line, or a single `error` line if the request failed as a whole:
+//!
+//! ```text
+//! display <path> <serial|-> <interface> ok <brightness> <output|feature|->
+//! display <path> <serial|-> <interface> error <kind> <message>
+//! ok
+//! error <kind> <message>
+//! ```
+//!
+//! `recall` is `get` falling back to the last brightness written when the
+//! display can't be read back, as `idle` needs it.
+//!
+//! Serial numbers and paths are escaped so that each stays one word: a
+//! backslash goes before backslashes and blanks, tabs and line breaks are
+//! written as `\t`, `\n` and `\r`, and a serial number of just `-` is
+//! written as `\-`. Error messages take up the rest of the line as they are.
+
+use crate::cli_error::{CliError, Kind};
+use crate::panels::{Action, Applied, Outcome, Request};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{env, fmt};
+use ws170120::{Curve, DisplayInfo, Fade, ReportPath, Selector, DEFAULT_STEPS_PER_SECOND};
+
+const SOCKET_FILE_NAME: &str = "ws170120-ctl.sock";
+
+/// Returns `$XDG_RUNTIME_DIR/ws170120-ctl.sock`, or a path in `/tmp` if
+/// there is no runtime directory.
+pub fn default_socket_path() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+        .join(SOCKET_FILE_NAME)
+}
+
+/// Formats a request as a protocol line, without the line terminator.
+pub fn format_request(selector: &Selector, request: &Request) -> String {
+    let mut line = match request.action {
+        Action::Get => "get".to_string(),
+        Action::Recall => "recall".to_string(),
+        Action::Set(brightness) => format!("set {brightness}"),
+        Action::Step(delta) => format!("step {delta:+}"),
+    };
+    if let Some(fade) = request.fade {
+        line += &format!(
+            " fade={} curve={} rate={}",
+            humantime::format_duration(fade.duration)
+                .to_string()
+                .replace(' ', ""),
+            fade.curve,
+            fade.steps_per_second
+        );
+    }
+    match selector {
+        Selector::First => {}
+        Selector::All => line += " all",
+        Selector::Serial(serial) => line += &format!(" serial={}", escape(serial)),
+        Selector::Path(path) => line += &format!(" path={}", escape(path)),
+    }
+    line
+}
+
+/// Parses a protocol line into a selector and request.
+pub fn parse_request(line: &str) -> Result<(Selector, Request), String> {
+    let mut words = Words::new(line);
+    let action = match words.next() {
+        Some("get") => Action::Get,
+        Some("recall") => Action::Recall,
+        Some("set") => Action::Set(argument(words.next(), "brightness")?),
+        Some("step") => Action::Step(argument(words.next(), "step")?),
+        Some(command) => return Err(format!("invalid request '{command}'")),
+        None => return Err("empty request".to_string()),
+    };
+
+    let mut selector = Selector::First;
+    let mut duration = None;
+    let mut curve = Curve::default();
+    let mut steps_per_second = DEFAULT_STEPS_PER_SECOND;
+    for word in words {
+        let (key, value) = word.split_once('=').unwrap_or((word, ""));
+        match key {
+            "all" => selector = Selector::All,
+            "serial" => selector = Selector::Serial(unescape(value)),
+            "path" => selector = Selector::Path(unescape(value)),
+            "fade" => {
+                duration = Some(
+                    humantime::parse_duration(value)
+                        .map_err(|e| format!("invalid fade duration '{value}': {e}"))?,
+                )
+            }
+            "curve" => curve = value.parse()?,
+            "rate" => {
+                steps_per_second = argument(Some(value), "fade rate")?;
+                if steps_per_second == 0 {
+                    return Err("invalid fade rate '0'".to_string());
+                }
+            }
+            _ => return Err(format!("unknown option '{word}'")),
+        }
+    }
+    let fade = duration.map(|duration| Fade {
+        duration,
+        curve,
+        steps_per_second,
+    });
+    Ok((selector, Request { action, fade }))
+}
+
+/// Escapes `value` so that it is read back as one word, see the module
+/// documentation.
+fn escape(value: &str) -> String {
+    if value == "-" {
+        return r"\-".to_string();
+    }
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\t' => escaped.push_str(r"\t"),
+            '\n' => escaped.push_str(r"\n"),
+            '\r' => escaped.push_str(r"\r"),
+            c if c == '\\' || c.is_whitespace() => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reverses [`escape`].
+fn unescape(word: &str) -> String {
+    let mut value = String::with_capacity(word.len());
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => value.push('\t'),
+            Some('n') => value.push('\n'),
+            Some('r') => value.push('\r'),
+            Some(c) => value.push(c),
+            None => value.push('\\'),
+        }
+    }
+    value
+}
+
+/// The words of a line, split at blanks that are not escaped. The words
+/// are returned as they are, still escaped.
+struct Words<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Words<'a> {
+    fn new(line: &'a str) -> Self {
+        Words { rest: line }
+    }
+
+    /// Returns what is left of the line after the words taken so far.
+    fn rest(&self) -> &'a str {
+        self.rest.trim_start()
+    }
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let line = self.rest();
+        if line.is_empty() {
+            return None;
+        }
+        let mut escaped = false;
+        let end = line
+            .char_indices()
+            .find(|&(_, c)| {
+                let blank = !escaped && c.is_whitespace();
+                escaped = !escaped && c == '\\';
+                blank
+            })
+            .map_or(line.len(), |(index, _)| index);
+        let (word, rest) = line.split_at(end);
+        self.rest = rest;
+        Some(word)
+    }
+}
+
+fn argument<T: FromStr>(word: Option<&str>, what: &str) -> Result<T, String> {
+    let word = word.ok_or_else(|| format!("missing {what}"))?;
+    word.parse().map_err(|_| format!("invalid {what} '{word}'"))
+}
+
+/// A response line sent by the daemon.
+pub enum Response<'a> {
+    Outcome(&'a Outcome),
+    Ok,
+    Error(&'a CliError),
+}
+
+impl fmt::Display for Response<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Outcome(outcome) => {
+                let display = &outcome.display;
+                write!(
+                    f,
+                    "display {} {} {} ",
+                    escape(&display.path),
+                    display.serial.as_deref().map_or("-".to_string(), escape),
+                    display.interface_number
+                )?;
+                match &outcome.result {
+                    Ok(applied) => write!(
+                        f,
+                        "ok {} {}",
+                        applied.brightness,
+                        match applied.path {
+                            Some(ReportPath::Output) => "output",
+                            Some(ReportPath::Feature) => "feature",
+                            None => "-",
+                        }
+                    ),
+                    Err(e) => write!(f, "error {} {}", e.kind(), single_line(e)),
+                }
+            }
+            Response::Ok => write!(f, "ok"),
+            Response::Error(e) => write!(f, "error {} {}", e.kind(), single_line(e)),
+        }
+    }
+}
+
+fn single_line(e: &CliError) -> String {
+    e.to_string().replace('\n', " ")
+}
+
+/// Splits the `<kind> <message>` fields of an error into a [`CliError`].
+fn parse_error(fields: &str) -> CliError {
+    let (kind, message) = fields.trim().split_once(' ').unwrap_or((fields.trim(), ""));
+    CliError::Daemon {
+        kind: Kind::from_name(if kind.is_empty() { "unknown" } else { kind }),
+        message: message.trim_start().to_string(),
+    }
+}
+
+fn parse_outcome(line: &str) -> Option<Outcome> {
+    let mut words = Words::new(line);
+    let path = unescape(words.next()?);
+    let serial = match words.next()? {
+        "-" => None,
+        serial => Some(unescape(serial)),
+    };
+    let display = DisplayInfo {
+        path,
+        serial,
+        interface_number: words.next()?.parse().ok()?,
+        usage_page: 0,
+    };
+    let result = match words.next()? {
+        "ok" => Ok(Applied {
+            brightness: words.next()?.parse().ok()?,
+            path: match words.next()? {
+                "output" => Some(ReportPath::Output),
+                "feature" => Some(ReportPath::Feature),
+                _ => None,
+            },
+        }),
+        "error" => Err(parse_error(words.rest())),
+        _ => return None,
+    };
+    Some(Outcome { display, result })
+}
+
+fn invalid_response(line: &str) -> CliError {
+    CliError::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected response '{line}'"),
+    ))
+}
+
+/// Connects to a running daemon, if there is one listening on `socket`.
+pub fn connect(socket: &Path) -> Option<UnixStream> {
+    UnixStream::connect(socket).ok()
+}
+
+/// Sends a request to the daemon and collects its response.
+pub fn send(
+    stream: UnixStream,
+    selector: &Selector,
+    request: &Request,
+) -> Result<Vec<Outcome>, CliError> {
+    let mut writer = &stream;
+    writeln!(writer, "{}", format_request(selector, request))?;
+
+    let mut outcomes = Vec::new();
+    for line in BufReader::new(&stream).lines() {
+        let line = line?;
+        match line.split_once(' ') {
+            None if line == "ok" => return Ok(outcomes),
+            Some(("display", rest)) => {
+                outcomes.push(parse_outcome(rest).ok_or_else(|| invalid_response(&line))?)
+            }
+            Some(("error", rest)) => return Err(parse_error(rest)),
+            _ => return Err(invalid_response(&line)),
+        }
+    }
+    Err(CliError::Io(io::ErrorKind::UnexpectedEof.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use ws170120::ErrorKind;
+
+    fn request(action: Action, fade: Option<Fade>) -> Request {
+        Request { action, fade }
+    }
+
+    #[test]
+    fn round_trips_requests() {
+        let fade = Fade {
+            duration: Duration::from_millis(1500),
+            curve: Curve::Perceptual,
+            steps_per_second: 10,
+        };
+        let cases = [
+            (Selector::First, request(Action::Get, None)),
+            (Selector::All, request(Action::Set(0), None)),
+            (Selector::First, request(Action::Set(100), Some(fade))),
+            (Selector::All, request(Action::Step(-5), Some(fade))),
+            (Selector::First, request(Action::Step(3), None)),
+            (Selector::All, request(Action::Recall, None)),
+            (
+                Selector::Serial("0123456789".to_string()),
+                request(Action::Get, None),
+            ),
+            (
+                Selector::Serial("a b".to_string()),
+                request(Action::Set(50), Some(fade)),
+            ),
+            (
+                Selector::Serial("-".to_string()),
+                request(Action::Get, None),
+            ),
+            (
+                Selector::Serial("tab\tline\nend\r".to_string()),
+                request(Action::Get, None),
+            ),
+            (
+                Selector::Serial("key=value".to_string()),
+                request(Action::Get, None),
+            ),
+            (Selector::Serial(String::new()), request(Action::Get, None)),
+            (
+                Selector::Path("/dev/hidraw3".to_string()),
+                request(Action::Step(10), None),
+            ),
+            (
+                Selector::Path(r"\\?\hid#vid_0eef&pid_0005#7 a".to_string()),
+                request(Action::Get, None),
+            ),
+            (
+                Selector::Path("trailing\\".to_string()),
+                request(Action::Get, None),
+            ),
+        ];
+        for (selector, request) in cases {
+            let line = format_request(&selector, &request);
+            assert_eq!(
+                parse_request(&line),
+                Ok((selector.clone(), request)),
+                "line {line:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_escaped_values() {
+        let (selector, _) = parse_request(r"get serial=a\ b").unwrap();
+        assert_eq!(selector, Selector::Serial("a b".to_string()));
+        let (selector, _) = parse_request(r"get path=C:\\dev\ 1").unwrap();
+        assert_eq!(selector, Selector::Path(r"C:\dev 1".to_string()));
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        for line in [
+            "",
+            "frob",
+            "set",
+            "set bright",
+            "set 10 bogus",
+            "get serial=a b",
+            "step +5 fade=soon",
+            "step +5 fade=1s rate=0",
+            "step +5 fade=1s curve=wobbly",
+        ] {
+            assert!(parse_request(line).is_err(), "line {line:?}");
+        }
+    }
+
+    fn display(path: &str, serial: Option<&str>) -> DisplayInfo {
+        DisplayInfo {
+            path: path.to_string(),
+            serial: serial.map(str::to_string),
+            interface_number: 1,
+            usage_page: 0,
+        }
+    }
+
+    /// Formats `outcome` and parses the line back, without the leading
+    /// `display` word.
+    fn round_trip(outcome: &Outcome) -> Outcome {
+        let line = Response::Outcome(outcome).to_string();
+        let rest = line.strip_prefix("display ").expect("display line");
+        parse_outcome(rest).unwrap_or_else(|| panic!("unparsable line {line:?}"))
+    }
+
+    #[test]
+    fn round_trips_applied_outcomes() {
+        for (info, path) in [
+            (
+                display("/dev/hidraw1", Some("0123")),
+                Some(ReportPath::Output),
+            ),
+            (
+                display("/dev/hid raw", Some("a b")),
+                Some(ReportPath::Feature),
+            ),
+            (display("/dev/hidraw2", None), None),
+            (display("/dev/hidraw3", Some("-")), Some(ReportPath::Output)),
+        ] {
+            let applied = Applied {
+                brightness: 42,
+                path,
+            };
+            let outcome = round_trip(&Outcome {
+                display: info.clone(),
+                result: Ok(applied),
+            });
+            assert_eq!(outcome.display, info);
+            assert_eq!(outcome.result.unwrap(), applied);
+        }
+    }
+
+    #[test]
+    fn round_trips_failed_outcomes() {
+        let info = display("/dev/hidraw1", Some("x y"));
+        let outcome = round_trip(&Outcome {
+            display: info.clone(),
+            result: Err(CliError::Display(ws170120::Error::NotConnected)),
+        });
+        assert_eq!(outcome.display, info);
+        let e = outcome.result.unwrap_err();
+        assert_eq!(e.kind(), Kind::Display(ErrorKind::NotConnected));
+        assert_eq!(
+            e.to_string(),
+            CliError::Display(ws170120::Error::NotConnected).to_string()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_outcomes() {
+        for line in [
+            "",
+            "/dev/hidraw1",
+            "/dev/hidraw1 - x ok 50 output",
+            "/dev/hidraw1 - 0 ok",
+            "/dev/hidraw1 - 0 ok 500 output",
+            "/dev/hidraw1 - 0 maybe",
+        ] {
+            assert!(parse_outcome(line).is_none(), "line {line:?}");
+        }
+    }
+
+    #[test]
+    fn parses_errors() {
+        let e = parse_error("write-failed Both  reports failed.");
+        assert_eq!(e.kind(), Kind::Display(ErrorKind::WriteFailed));
+        assert_eq!(e.to_string(), "Both  reports failed.");
+        assert_eq!(parse_error("").kind().name(), "unknown");
+    }
+}
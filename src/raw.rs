@@ -0,0 +1,312 @@
+//! Sends and receives raw HID reports, for exploring the display protocol.
+
+use crate::cli_error::CliError;
+use clap::Subcommand;
+use hidapi::{HidApi, HidDevice, HidError};
+use std::time::{Duration, Instant};
+use ws170120::{find_displays, open_path, Display, Error, ReportPath, Selector, Transport};
+
+#[derive(Subcommand)]
+pub enum RawCommand {
+    /// Send a report given as hex bytes, starting with the report ID
+    Send {
+        /// Send as a feature report instead of an output report
+        #[arg(long)]
+        feature: bool,
+
+        /// Pad the report with zeros to this many bytes, e.g. 38 like the
+        /// brightness report
+        #[arg(long)]
+        pad: Option<usize>,
+
+        /// Report bytes, e.g. `04 aa 01 00` or `04aa0100`
+        #[arg(required = true)]
+        bytes: Vec<String>,
+    },
+    /// Read a feature report by ID
+    GetFeature {
+        /// Report ID, in hex
+        #[arg(value_parser = parse_byte)]
+        id: u8,
+
+        /// Size of the receive buffer, including the report ID
+        #[arg(long, default_value_t = 64)]
+        length: usize,
+    },
+    /// Read input reports sent by the display
+    Read {
+        /// Only show reports with this ID, in hex
+        #[arg(long, value_parser = parse_byte)]
+        id: Option<u8>,
+
+        /// Stop after this many reports
+        #[arg(long)]
+        count: Option<usize>,
+
+        /// Stop after this long without a report
+        #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+        timeout: Duration,
+
+        /// Size of the receive buffer
+        #[arg(long, default_value_t = 64)]
+        length: usize,
+    },
+    /// Write the brightness byte as is, bypassing the mapping and the range
+    /// check, or print it if no value is given
+    Brightness {
+        /// Value for the brightness byte, 0-255
+        value: Option<u8>,
+    },
+}
+
+/// Parses a byte such as `04`, `0x04` or `4` as hex.
+fn parse_byte(s: &str) -> Result<u8, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    u8::from_str_radix(digits, 16).map_err(|_| format!("{s} is not a hex byte"))
+}
+
+/// Parses hex bytes given as separate words (`04 aa`), runs of digits
+/// (`04aa`), or separated by colons or commas, with optional `0x` prefixes.
+pub fn parse_hex(words: &[String]) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for word in words
+        .iter()
+        .flat_map(|word| word.split([' ', ':', ',']))
+        .filter(|word| !word.is_empty())
+    {
+        let digits = word.trim_start_matches("0x").trim_start_matches("0X");
+        if digits.is_empty() {
+            return Err(format!("{word} has no hex digits"));
+        }
+        if digits.len() % 2 != 0 && digits.len() > 1 {
+            return Err(format!("{word} has an odd number of hex digits"));
+        }
+        for chunk in digits.as_bytes().chunks(2) {
+            let chunk = std::str::from_utf8(chunk).unwrap_or_default();
+            bytes.push(parse_byte(chunk).map_err(|_| format!("{word} is not hex"))?);
+        }
+    }
+    if bytes.is_empty() {
+        return Err("No report bytes given.".to_string());
+    }
+    Ok(bytes)
+}
+
+/// Formats `data` like `hexdump -C`.
+pub fn hexdump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (index, line) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|byte| format!("{byte:02x}")).collect();
+        let (left, right) = hex.split_at(hex.len().min(8));
+        let text: String = line
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        out.push_str(&format!(
+            "{:08x}  {:<23}  {:<23}  |{}|\n",
+            index * 16,
+            left.join(" "),
+            right.join(" "),
+            text
+        ));
+    }
+    out
+}
+
+fn device_error(context: &str, source: HidError) -> CliError {
+    Error::Device {
+        context: context.to_string(),
+        source,
+    }
+    .into()
+}
+
+/// Opens the display picked by `selector`. Raw reports go to a single
+/// display, so `--all` uses the first one.
+///
+/// A `--path` is opened as is, so that it can also name the display's other
+/// interfaces, e.g. the touch screen; otherwise the control interface of the
+/// selected display is used.
+pub fn open(selector: &Selector) -> Result<HidDevice, CliError> {
+    let api = HidApi::new().map_err(Error::HidInit)?;
+    let path = match selector {
+        Selector::Path(path) => path.clone(),
+        _ => {
+            selector
+                .select(find_displays(&api))?
+                .into_iter()
+                .next()
+                .ok_or(Error::NotConnected)?
+                .path
+        }
+    };
+    Ok(open_path(&api, &path)?)
+}
+
+pub fn run(command: &RawCommand, selector: &Selector, verbose: u8) -> Result<(), CliError> {
+    match command {
+        RawCommand::Send {
+            feature,
+            pad,
+            bytes,
+        } => {
+            let mut report = parse_hex(bytes).map_err(CliError::InvalidArgument)?;
+            if let Some(length) = pad {
+                if report.len() < *length {
+                    report.resize(*length, 0);
+                }
+            }
+            let device = open(selector)?;
+            if verbose > 0 {
+                print!("{}", hexdump(&report));
+            }
+            if *feature {
+                device
+                    .send_feature_report(&report)
+                    .map_err(|e| device_error("Failed to send feature report", e))?;
+                println!("Sent {} byte feature report.", report.len());
+            } else {
+                let written = device
+                    .write_output_report(&report)
+                    .map_err(|e| device_error("Failed to send output report", e))?;
+                println!("Sent {written} of {} bytes as output report.", report.len());
+            }
+        }
+        RawCommand::GetFeature { id, length } => {
+            let device = open(selector)?;
+            let mut buf = vec![0; (*length).max(1)];
+            buf[0] = *id;
+            let read = device
+                .get_feature_report(&mut buf)
+                .map_err(|e| device_error("Failed to read feature report", e))?;
+            print!("{}", hexdump(&buf[..read]));
+        }
+        RawCommand::Read {
+            id,
+            count,
+            timeout,
+            length,
+        } => {
+            let device = open(selector)?;
+            let mut buf = vec![0; (*length).max(1)];
+            let mut received = 0;
+            let mut deadline = Instant::now() + *timeout;
+            while count.is_none_or(|count| received < count) {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                let millis = i32::try_from(remaining.as_millis()).unwrap_or(i32::MAX);
+                let read = device
+                    .read_timeout(&mut buf, millis)
+                    .map_err(|e| device_error("Failed to read input report", e))?;
+                if read == 0 || id.is_some_and(|id| buf[0] != id) {
+                    continue;
+                }
+                received += 1;
+                deadline = Instant::now() + *timeout;
+                println!("Report {:02x}, {} bytes:", buf[0], read);
+                print!("{}", hexdump(&buf[..read]));
+            }
+            if verbose > 0 {
+                println!("Received {received} reports.");
+            }
+        }
+        RawCommand::Brightness { value } => {
+            let display = Display::new(open(selector)?);
+            match value {
+                Some(value) => {
+                    let path = display.set_raw_brightness(*value)?;
+                    if verbose > 0 {
+                        if path == ReportPath::Feature {
+                            println!("Regular write failed, used feature report instead.");
+                        }
+                        println!("Brightness byte has been set to {value}.");
+                    }
+                }
+                None => println!("{}", display.raw_brightness()?),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_hex_bytes() {
+        let report = vec![0x04, 0xaa, 0x01, 0x00];
+        for input in [
+            &["04", "aa", "01", "00"][..],
+            &["04aa0100"],
+            &["04 aa 01 00"],
+            &["04:aa:01:00"],
+            &["04,aa,", "01,00"],
+            &["0x04", "0xAA", "0X01", "0x00"],
+            &["0x04aa", "0100"],
+            &["4", "aa", "1", "0"],
+        ] {
+            assert_eq!(parse_hex(&words(input)), Ok(report.clone()), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_hex() {
+        for input in [
+            &[][..],
+            &[""],
+            &[" , "],
+            &["0x"],
+            &["04", "0x"],
+            &["04a"],
+            &["0xaa1"],
+            &["zz"],
+            &["04", "g1"],
+            &["-1"],
+        ] {
+            assert!(parse_hex(&words(input)).is_err(), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn parses_single_bytes() {
+        assert_eq!(parse_byte("04"), Ok(4));
+        assert_eq!(parse_byte("0x4"), Ok(4));
+        assert_eq!(parse_byte("FF"), Ok(255));
+        for invalid in ["", "0x", "100", "g"] {
+            assert!(parse_byte(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn dumps_like_hexdump() {
+        assert_eq!(hexdump(&[]), "");
+        assert_eq!(
+            hexdump(&[0x04, 0xaa, 0x01, 0x00, 0x00, 0x00, 0x32]),
+            "00000000  04 aa 01 00 00 00 32                              |......2|\n"
+        );
+        let data: Vec<u8> = (0x3a..0x5c).collect();
+        assert_eq!(
+            hexdump(&data),
+            "00000000  3a 3b 3c 3d 3e 3f 40 41  42 43 44 45 46 47 48 49  |:;<=>?@ABCDEFGHI|\n\
+             00000010  4a 4b 4c 4d 4e 4f 50 51  52 53 54 55 56 57 58 59  |JKLMNOPQRSTUVWXY|\n\
+             00000020  5a 5b                                             |Z[|\n"
+        );
+        assert_eq!(
+            hexdump(b" \x7f\x80~"),
+            "00000000  20 7f 80 7e                                       | ..~|\n"
+        );
+    }
+}
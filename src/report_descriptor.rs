@@ -0,0 +1,134 @@
+//! Prints the HID report descriptor of a display.
+
+use crate::cli_error::CliError;
+use crate::output::{self, Format};
+use crate::raw;
+use std::fs;
+use std::path::PathBuf;
+use ws170120::descriptor::{Field, Node, ReportDescriptor};
+use ws170120::{Error, Selector};
+
+#[derive(clap::Args)]
+pub struct DescriptorArgs {
+    /// Decode a descriptor saved to a file instead of asking the display,
+    /// e.g. /sys/class/hidraw/hidraw0/device/report_descriptor
+    #[arg(long)]
+    pub file: Option<PathBuf>,
+
+    /// Also print the descriptor bytes
+    #[arg(long)]
+    pub hex: bool,
+}
+
+pub fn run(args: &DescriptorArgs, selector: &Selector, format: Format) -> Result<(), CliError> {
+    let bytes = match &args.file {
+        Some(path) => fs::read(path).map_err(|e| CliError::InvalidFile {
+            path: path.clone(),
+            message: e.to_string(),
+        })?,
+        None => fetch(selector)?,
+    };
+    let descriptor = ReportDescriptor::parse(&bytes)?;
+
+    if format == Format::Json {
+        output::print_descriptor(&bytes, &descriptor);
+        return Ok(());
+    }
+    println!("Report descriptor, {} bytes", bytes.len());
+    if args.hex {
+        print!("{}", raw::hexdump(&bytes));
+    }
+    println!();
+    print_nodes(&descriptor.nodes, 0);
+    println!();
+    println!("Reports:");
+    for report in descriptor.reports() {
+        let id = report
+            .report_id
+            .map_or("--".to_string(), |id| format!("{id:02x}"));
+        println!(
+            "  {}  {:<8} {} bytes ({} bits)",
+            id,
+            report.kind.to_string(),
+            report.bytes(),
+            report.bits
+        );
+    }
+    Ok(())
+}
+
+fn fetch(selector: &Selector) -> Result<Vec<u8>, CliError> {
+    let device = raw::open(selector)?;
+    let mut buf = vec![0; hidapi::MAX_REPORT_DESCRIPTOR_SIZE];
+    let read = device
+        .get_report_descriptor(&mut buf)
+        .map_err(|source| Error::Device {
+            context: "Failed to read report descriptor".to_string(),
+            source,
+        })?;
+    buf.truncate(read);
+    Ok(buf)
+}
+
+fn print_nodes(nodes: &[Node], depth: usize) {
+    let indent = "  ".repeat(depth);
+    for node in nodes {
+        match node {
+            Node::Collection(collection) => {
+                match collection.usage {
+                    Some(usage) => println!("{indent}{} collection ({usage})", collection.kind),
+                    None => println!("{indent}{} collection", collection.kind),
+                }
+                print_nodes(&collection.children, depth + 1);
+            }
+            Node::Field(field) => println!("{indent}{}", describe(field)),
+        }
+    }
+}
+
+fn describe(field: &Field) -> String {
+    let id = field
+        .report_id
+        .map_or(String::new(), |id| format!(" {id:02x}"));
+    let mut usages: Vec<String> = field.usages.iter().map(ToString::to_string).collect();
+    if let Some((minimum, maximum)) = field.usage_range {
+        usages.push(format!("{minimum} .. 0x{:04x}", maximum.id));
+    }
+    let what = if usages.is_empty() {
+        if field.is_constant() {
+            "padding".to_string()
+        } else {
+            "no usage".to_string()
+        }
+    } else {
+        usages.join(", ")
+    };
+    let flags = [
+        if field.is_constant() {
+            "constant"
+        } else {
+            "data"
+        },
+        if field.is_variable() {
+            "variable"
+        } else {
+            "array"
+        },
+        if field.is_relative() {
+            "relative"
+        } else {
+            "absolute"
+        },
+    ];
+    format!(
+        "{}{}: {}, {} x {} bits, {}..{}, {}",
+        field.kind,
+        id,
+        what,
+        field.report_count,
+        field.report_size,
+        field.logical_minimum,
+        field.logical_maximum,
+        flags.join(" ")
+    )
+}
@@ -0,0 +1,363 @@
+//! Brightness that follows the time of day.
+
+use crate::solar::Location;
+use crate::{Curve, MAX_BRIGHTNESS};
+use chrono::{DateTime, Days, NaiveTime, TimeDelta, TimeZone};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// When a schedule point is reached on a given day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeOfDay {
+    /// A fixed local time, e.g. `07:30`.
+    Fixed(NaiveTime),
+    /// Sunrise shifted by an offset, e.g. `sunrise+30m`.
+    Sunrise(TimeDelta),
+    /// Sunset shifted by an offset, e.g. `sunset-1h`.
+    Sunset(TimeDelta),
+}
+
+impl TimeOfDay {
+    fn is_solar(&self) -> bool {
+        !matches!(self, TimeOfDay::Fixed(_))
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        for (event, solar) in [
+            ("sunrise", TimeOfDay::Sunrise as fn(TimeDelta) -> TimeOfDay),
+            ("sunset", TimeOfDay::Sunset),
+        ] {
+            if let Some(offset) = s.strip_prefix(event) {
+                return parse_offset(offset).map(solar);
+            }
+        }
+        NaiveTime::parse_from_str(s, "%H:%M")
+            .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M:%S"))
+            .map(TimeOfDay::Fixed)
+            .map_err(|_| {
+                format!(
+                    "invalid time '{s}', expected HH:MM, sunrise[+/-offset] or sunset[+/-offset]"
+                )
+            })
+    }
+}
+
+/// Parses `""`, `+30m` or `-1h 15m` into a signed offset.
+fn parse_offset(s: &str) -> Result<TimeDelta, String> {
+    let (sign, duration) = match s.chars().next() {
+        None => return Ok(TimeDelta::zero()),
+        Some('+') => (1, &s[1..]),
+        Some('-') => (-1, &s[1..]),
+        Some(_) => return Err(format!("invalid offset '{s}', expected +/-duration")),
+    };
+    let duration = humantime::parse_duration(duration.trim())
+        .map_err(|e| format!("invalid offset '{s}': {e}"))?;
+    TimeDelta::from_std(duration)
+        .map(|delta| delta * sign)
+        .map_err(|_| format!("offset '{s}' is too large"))
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (event, offset) = match self {
+            TimeOfDay::Fixed(time) => return write!(f, "{}", time.format("%H:%M")),
+            TimeOfDay::Sunrise(offset) => ("sunrise", offset),
+            TimeOfDay::Sunset(offset) => ("sunset", offset),
+        };
+        f.write_str(event)?;
+        if !offset.is_zero() {
+            let sign = if *offset < TimeDelta::zero() {
+                '-'
+            } else {
+                '+'
+            };
+            let magnitude = offset.abs().to_std().unwrap_or_default();
+            write!(f, "{sign}{}", humantime::format_duration(magnitude))?;
+        }
+        Ok(())
+    }
+}
+
+/// A brightness that is reached at a time of day.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SchedulePoint {
+    pub at: TimeOfDay,
+    pub brightness: u8,
+    /// How long before `at` the change starts. Zero switches instantly.
+    pub transition: Duration,
+}
+
+/// A daily brightness schedule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    /// Needed to resolve sunrise and sunset points.
+    pub location: Option<Location>,
+    /// How the brightness moves during a transition.
+    pub curve: Curve,
+    pub points: Vec<SchedulePoint>,
+}
+
+impl Schedule {
+    /// Checks that brightness values are in range and that a location is
+    /// known if any point refers to sunrise or sunset.
+    pub fn validate(&self) -> Result<(), String> {
+        for point in &self.points {
+            if point.brightness > MAX_BRIGHTNESS {
+                return Err(format!(
+                    "brightness {} at {} is out of range, expected a value between 0 and {}",
+                    point.brightness, point.at, MAX_BRIGHTNESS
+                ));
+            }
+            if point.at.is_solar() && self.location.is_none() {
+                return Err(format!(
+                    "{} needs a latitude and longitude to be configured",
+                    point.at
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the scheduled brightness at `now`, or `None` if no point
+    /// could be resolved (e.g. an empty schedule, or only sunrise points
+    /// during polar night).
+    pub fn brightness_at<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Option<u8> {
+        // Looking one day back and ahead is enough, as each point recurs daily
+        let today = now.date_naive();
+        let mut resolved: Vec<(DateTime<Tz>, &SchedulePoint)> = [
+            today.checked_sub_days(Days::new(1))?,
+            today,
+            today.checked_add_days(Days::new(1))?,
+        ]
+        .into_iter()
+        .flat_map(|date| {
+            self.points.iter().filter_map(move |point| {
+                let at = match point.at {
+                    TimeOfDay::Fixed(time) => now
+                        .timezone()
+                        .from_local_datetime(&date.and_time(time))
+                        .earliest()?,
+                    TimeOfDay::Sunrise(offset) => {
+                        let sun = self.location?.sun_times(date)?;
+                        sun.sunrise.with_timezone(&now.timezone()) + offset
+                    }
+                    TimeOfDay::Sunset(offset) => {
+                        let sun = self.location?.sun_times(date)?;
+                        sun.sunset.with_timezone(&now.timezone()) + offset
+                    }
+                };
+                Some((at, point))
+            })
+        })
+        .collect();
+        resolved.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let next_index = resolved.iter().position(|(at, _)| at > now)?;
+        let (previous_at, previous) = resolved.get(next_index.checked_sub(1)?)?;
+        let (next_at, next) = &resolved[next_index];
+
+        let transition = TimeDelta::from_std(next.transition).ok()?;
+        let start = (next_at.clone() - transition).max(previous_at.clone());
+        if *now < start {
+            return Some(previous.brightness);
+        }
+        let total = (next_at.clone() - start.clone()).num_milliseconds() as f64;
+        let elapsed = (now.clone() - start).num_milliseconds() as f64;
+        let value = self.curve.interpolate(
+            f64::from(previous.brightness),
+            f64::from(next.brightness),
+            elapsed / total,
+        );
+        Some(value.round() as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, NaiveDateTime, Utc};
+
+    fn time(s: &str) -> TimeOfDay {
+        s.parse().unwrap()
+    }
+
+    fn point(at: &str, brightness: u8, transition: &str) -> SchedulePoint {
+        SchedulePoint {
+            at: time(at),
+            brightness,
+            transition: humantime::parse_duration(transition).unwrap(),
+        }
+    }
+
+    fn at(s: &str) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")
+            .unwrap()
+            .and_utc()
+    }
+
+    #[test]
+    fn parses_times_of_day() {
+        let fixed = |h, m, s| TimeOfDay::Fixed(NaiveTime::from_hms_opt(h, m, s).unwrap());
+        assert_eq!(time("07:30"), fixed(7, 30, 0));
+        assert_eq!(time("23:59:30"), fixed(23, 59, 30));
+        assert_eq!(time("sunrise"), TimeOfDay::Sunrise(TimeDelta::zero()));
+        assert_eq!(
+            time("sunrise+30m"),
+            TimeOfDay::Sunrise(TimeDelta::minutes(30))
+        );
+        assert_eq!(
+            time("sunset-1h 15m"),
+            TimeOfDay::Sunset(TimeDelta::minutes(-75))
+        );
+        assert_eq!(time("sunset+ 2h"), TimeOfDay::Sunset(TimeDelta::hours(2)));
+        for s in ["07:30", "sunrise", "sunrise+30m", "sunset-1h 15m"] {
+            assert_eq!(time(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_times_of_day() {
+        for invalid in [
+            "",
+            "7",
+            "24:00",
+            "07:60",
+            "noon",
+            "sunrise30m",
+            "sunrise+",
+            "sunrise+soon",
+            "sunset*2",
+            "sunset-30",
+        ] {
+            assert!(invalid.parse::<TimeOfDay>().is_err(), "time {invalid:?}");
+        }
+    }
+
+    #[test]
+    fn validates_points() {
+        let mut schedule = Schedule {
+            location: None,
+            curve: Curve::Linear,
+            points: vec![point("07:00", 100, "0s")],
+        };
+        assert_eq!(schedule.validate(), Ok(()));
+        schedule.points.push(point("22:00", 101, "0s"));
+        assert!(schedule.validate().is_err());
+        schedule.points[1] = point("sunset", 20, "0s");
+        assert!(schedule.validate().is_err());
+        schedule.location = Some(Location {
+            latitude: 48.21,
+            longitude: 16.37,
+        });
+        assert_eq!(schedule.validate(), Ok(()));
+    }
+
+    #[test]
+    fn interpolates_across_midnight() {
+        let schedule = Schedule {
+            location: None,
+            curve: Curve::Linear,
+            points: vec![point("07:00", 80, "1h"), point("22:00", 20, "2h")],
+        };
+        let brightness = |s| schedule.brightness_at(&at(s));
+        assert_eq!(brightness("2024-05-01 12:00"), Some(80));
+        assert_eq!(brightness("2024-05-01 20:00"), Some(80));
+        assert_eq!(brightness("2024-05-01 21:00"), Some(50));
+        assert_eq!(brightness("2024-05-01 22:00"), Some(20));
+        assert_eq!(brightness("2024-05-01 23:30"), Some(20));
+        assert_eq!(brightness("2024-05-02 00:30"), Some(20));
+        assert_eq!(brightness("2024-05-02 06:00"), Some(20));
+        assert_eq!(brightness("2024-05-02 06:30"), Some(50));
+        assert_eq!(brightness("2024-05-02 06:45"), Some(65));
+        assert_eq!(brightness("2024-05-02 07:00"), Some(80));
+    }
+
+    #[test]
+    fn transitions_start_at_the_previous_point_at_the_latest() {
+        let schedule = Schedule {
+            location: None,
+            curve: Curve::Linear,
+            points: vec![point("23:00", 0, "0s"), point("01:00", 100, "5h")],
+        };
+        let brightness = |s| schedule.brightness_at(&at(s));
+        assert_eq!(brightness("2024-05-01 22:59"), Some(100));
+        assert_eq!(brightness("2024-05-01 23:00"), Some(0));
+        assert_eq!(brightness("2024-05-02 00:00"), Some(50));
+        assert_eq!(brightness("2024-05-02 01:00"), Some(100));
+    }
+
+    #[test]
+    fn uses_the_local_time_of_the_given_zone() {
+        let schedule = Schedule {
+            location: None,
+            curve: Curve::Linear,
+            points: vec![point("07:00", 80, "0s"), point("22:00", 20, "0s")],
+        };
+        let vienna = FixedOffset::east_opt(2 * 3600).unwrap();
+        // 06:00 UTC is 08:00 in Vienna, but still night in UTC
+        assert_eq!(schedule.brightness_at(&at("2024-05-01 06:00")), Some(20));
+        assert_eq!(
+            schedule.brightness_at(&at("2024-05-01 06:00").with_timezone(&vienna)),
+            Some(80)
+        );
+    }
+
+    #[test]
+    fn follows_the_sun() {
+        let schedule = Schedule {
+            location: Some(Location {
+                latitude: 48.21,
+                longitude: 16.37,
+            }),
+            curve: Curve::Linear,
+            points: vec![point("sunrise", 90, "0s"), point("sunset+1h", 20, "0s")],
+        };
+        // Sunrise is about 02:54 UTC and sunset about 18:58 UTC
+        let brightness = |s| schedule.brightness_at(&at(s));
+        assert_eq!(brightness("2024-06-21 02:45"), Some(20));
+        assert_eq!(brightness("2024-06-21 03:05"), Some(90));
+        assert_eq!(brightness("2024-06-21 19:45"), Some(90));
+        assert_eq!(brightness("2024-06-21 20:05"), Some(20));
+    }
+
+    #[test]
+    fn has_no_brightness_without_resolvable_points() {
+        let empty = Schedule {
+            location: None,
+            curve: Curve::Linear,
+            points: vec![],
+        };
+        assert_eq!(empty.brightness_at(&at("2024-05-01 12:00")), None);
+
+        // The sun neither rises nor sets in Tromsø around the solstices
+        let tromso = Schedule {
+            location: Some(Location {
+                latitude: 69.65,
+                longitude: 18.96,
+            }),
+            curve: Curve::Linear,
+            points: vec![point("sunrise", 90, "0s"), point("sunset", 20, "0s")],
+        };
+        assert_eq!(tromso.brightness_at(&at("2024-12-21 12:00")), None);
+        assert_eq!(tromso.brightness_at(&at("2024-06-21 12:00")), None);
+        assert!(tromso.brightness_at(&at("2024-03-20 12:00")).is_some());
+    }
+
+    #[test]
+    fn applies_the_curve() {
+        let schedule = |curve| Schedule {
+            location: None,
+            curve,
+            points: vec![point("07:00", 0, "0s"), point("08:00", 100, "1h")],
+        };
+        let brightness = |curve, s| schedule(curve).brightness_at(&at(s));
+        assert_eq!(brightness(Curve::Linear, "2024-05-01 07:15"), Some(25));
+        assert_eq!(brightness(Curve::EaseInOut, "2024-05-01 07:15"), Some(16));
+        assert_eq!(brightness(Curve::Perceptual, "2024-05-01 07:30"), Some(18));
+    }
+}
@@ -0,0 +1,263 @@
+//! Drives the brightness from a daily schedule file.
+//!
+//! ```toml
+//! latitude = 48.21
+//! longitude = 16.37
+//! transition = "30m"
+//! curve = "perceptual"
+//!
+//! [[point]]
+//! at = "sunrise"
+//! brightness = 90
+//!
+//! [[point]]
+//! at = "sunset+30m"
+//! brightness = 20
+//! transition = "1h"
+//! ```
+
+use crate::cli_error::CliError;
+use chrono::Local;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use std::{fs, io};
+use ws170120::{Location, Schedule, SchedulePoint};
+
+/// Default length of the transition towards each point.
+const DEFAULT_TRANSITION: Duration = Duration::from_secs(30 * 60);
+
+#[derive(clap::Args)]
+pub struct ScheduleArgs {
+    /// Schedule file to follow
+    #[arg(long)]
+    pub file: PathBuf,
+
+    /// How often the scheduled brightness is recomputed
+    #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    pub interval: Duration,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScheduleFile {
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    #[serde(default)]
+    curve: Option<String>,
+    #[serde(default)]
+    transition: Option<String>,
+    #[serde(rename = "point", default)]
+    points: Vec<PointFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PointFile {
+    at: String,
+    brightness: u8,
+    #[serde(default)]
+    transition: Option<String>,
+}
+
+fn parse_duration(s: Option<&str>, default: Duration) -> Result<Duration, String> {
+    match s {
+        Some(s) => humantime::parse_duration(s).map_err(|e| format!("invalid duration '{s}': {e}")),
+        None => Ok(default),
+    }
+}
+
+impl ScheduleFile {
+    /// Converts the file contents into a validated schedule.
+    fn into_schedule(self) -> Result<Schedule, String> {
+        let location = match (self.latitude, self.longitude) {
+            (Some(latitude), Some(longitude)) => Some(Location {
+                latitude,
+                longitude,
+            }),
+            (None, None) => None,
+            _ => return Err("latitude and longitude must be given together".to_string()),
+        };
+        let default_transition = parse_duration(self.transition.as_deref(), DEFAULT_TRANSITION)?;
+        let points = self
+            .points
+            .into_iter()
+            .map(|point| {
+                Ok(SchedulePoint {
+                    at: point.at.parse()?,
+                    brightness: point.brightness,
+                    transition: parse_duration(point.transition.as_deref(), default_transition)?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        let schedule = Schedule {
+            location,
+            curve: self.curve.as_deref().unwrap_or("linear").parse()?,
+            points,
+        };
+        schedule.validate()?;
+        Ok(schedule)
+    }
+}
+
+/// Reads and validates a schedule file.
+pub fn load(path: &Path) -> Result<Schedule, CliError> {
+    let invalid = |message: String| CliError::InvalidFile {
+        path: path.to_path_buf(),
+        message,
+    };
+    let contents = fs::read_to_string(path).map_err(|e: io::Error| invalid(e.to_string()))?;
+    let file: ScheduleFile = toml::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+    file.into_schedule().map_err(invalid)
+}
+
+/// Follows `schedule` forever, calling `apply` whenever the brightness changes.
+///
+/// Failures are reported and retried on the next tick, so a display that is
+/// unplugged for a while picks up the schedule again once it is back.
+pub fn run(
+    schedule: &Schedule,
+    interval: Duration,
+    verbose: u8,
+    mut apply: impl FnMut(u8) -> Result<(), CliError>,
+) -> ! {
+    let mut applied = None;
+    loop {
+        if let Some(brightness) = schedule.brightness_at(&Local::now()) {
+            if applied != Some(brightness) {
+                if verbose > 0 {
+                    println!("Scheduled brightness is {}%.", brightness);
+                }
+                applied = match apply(brightness) {
+                    Ok(()) => Some(brightness),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        None
+                    }
+                };
+            }
+        }
+        thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use ws170120::{Curve, TimeOfDay};
+
+    fn parse(contents: &str) -> Result<Schedule, String> {
+        toml::from_str::<ScheduleFile>(contents)
+            .map_err(|e| e.to_string())?
+            .into_schedule()
+    }
+
+    #[test]
+    fn parses_schedule_files() {
+        let schedule = parse(
+            r#"
+            latitude = 48.21
+            longitude = 16.37
+            transition = "10m"
+            curve = "perceptual"
+
+            [[point]]
+            at = "sunrise"
+            brightness = 90
+
+            [[point]]
+            at = "22:00"
+            brightness = 20
+            transition = "1h"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            schedule.location,
+            Some(Location {
+                latitude: 48.21,
+                longitude: 16.37
+            })
+        );
+        assert_eq!(schedule.curve, Curve::Perceptual);
+        assert_eq!(
+            schedule.points,
+            [
+                SchedulePoint {
+                    at: "sunrise".parse().unwrap(),
+                    brightness: 90,
+                    transition: Duration::from_secs(600),
+                },
+                SchedulePoint {
+                    at: "22:00".parse().unwrap(),
+                    brightness: 20,
+                    transition: Duration::from_secs(3600),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn fills_in_defaults() {
+        let schedule = parse("[[point]]\nat = \"07:00\"\nbrightness = 80\n").unwrap();
+        assert_eq!(schedule.location, None);
+        assert_eq!(schedule.curve, Curve::Linear);
+        assert!(matches!(schedule.points[0].at, TimeOfDay::Fixed(_)));
+        assert_eq!(schedule.points[0].transition, DEFAULT_TRANSITION);
+        assert_eq!(parse("").unwrap().points, []);
+    }
+
+    #[test]
+    fn rejects_invalid_schedule_files() {
+        for (contents, error) in [
+            ("latitude = 48.21", "latitude and longitude"),
+            ("longitude = 16.37", "latitude and longitude"),
+            ("transition = \"soon\"", "invalid duration 'soon'"),
+            ("curve = \"wobbly\"", "wobbly"),
+            ("brightness = 20", "unknown field `brightness`"),
+            (
+                "[[point]]\nat = \"noon\"\nbrightness = 50",
+                "invalid time 'noon'",
+            ),
+            (
+                "[[point]]\nat = \"07:00\"\nbrightness = 101",
+                "out of range",
+            ),
+            ("[[point]]\nat = \"07:00\"\nbrightness = 256", "brightness"),
+            ("[[point]]\nat = \"07:00\"", "missing field `brightness`"),
+            (
+                "[[point]]\nat = \"07:00\"\nbrightness = 50\ntransition = \"1 fortnight\"",
+                "invalid duration",
+            ),
+            (
+                "[[point]]\nat = \"07:00\"\nbrightness = 50\nlevel = 3",
+                "unknown field `level`",
+            ),
+            (
+                "[[point]]\nat = \"sunset\"\nbrightness = 20",
+                "needs a latitude and longitude",
+            ),
+        ] {
+            let message = parse(contents).unwrap_err();
+            assert!(message.contains(error), "{contents:?} gave {message:?}");
+        }
+    }
+
+    #[test]
+    fn names_the_file_in_errors() {
+        let dir = TestDir::new("scheduler");
+        let valid = dir.write("valid.toml", "[[point]]\nat = \"07:00\"\nbrightness = 80\n");
+        assert_eq!(load(&valid).unwrap().points.len(), 1);
+
+        let invalid = dir.write("invalid.toml", "[[point]]\nat = 7\n");
+        let missing = dir.join("missing.toml");
+        for path in [invalid, missing] {
+            match load(&path) {
+                Err(CliError::InvalidFile { path: reported, .. }) => assert_eq!(reported, path),
+                other => panic!("{path:?} gave {other:?}"),
+            }
+        }
+    }
+}
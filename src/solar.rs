@@ -0,0 +1,157 @@
+//! Sunrise and sunset times, using the sunrise equation.
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+/// Julian date of the Unix epoch.
+const UNIX_EPOCH_JD: f64 = 2_440_587.5;
+/// Julian date of the J2000.0 epoch.
+const J2000_JD: f64 = 2_451_545.0;
+/// Tilt of the earth's axis, in degrees.
+const OBLIQUITY: f64 = 23.4397;
+/// Solar altitude at sunrise and sunset, accounting for refraction and the
+/// sun's apparent radius, in degrees.
+const HORIZON: f64 = -0.833;
+
+/// A position on earth, in degrees. Longitude is positive east of Greenwich.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Sunrise and sunset on one day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SunTimes {
+    pub sunrise: DateTime<Utc>,
+    pub sunset: DateTime<Utc>,
+}
+
+impl Location {
+    /// Computes sunrise and sunset on `date`, as seen from this location.
+    ///
+    /// Returns `None` during polar day or polar night. Times are accurate to
+    /// within a few minutes, which is plenty for dimming a display.
+    pub fn sun_times(&self, date: NaiveDate) -> Option<SunTimes> {
+        let days_since_unix_epoch = (date - NaiveDate::default()).num_days() as f64;
+        let noon_jd = UNIX_EPOCH_JD + days_since_unix_epoch + 0.5;
+        let day = (noon_jd - J2000_JD).round() + 0.0008;
+
+        let mean_solar_time = day - self.longitude / 360.0;
+        let mean_anomaly = (357.5291 + 0.985_600_28 * mean_solar_time).rem_euclid(360.0);
+        let m = mean_anomaly.to_radians();
+        let center = 1.9148 * m.sin() + 0.02 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+        let ecliptic_longitude = (mean_anomaly + center + 180.0 + 102.9372)
+            .rem_euclid(360.0)
+            .to_radians();
+        let transit = J2000_JD + mean_solar_time + 0.0053 * m.sin()
+            - 0.0069 * (2.0 * ecliptic_longitude).sin();
+
+        let declination = (ecliptic_longitude.sin() * OBLIQUITY.to_radians().sin()).asin();
+        let latitude = self.latitude.to_radians();
+        let cos_hour_angle = (HORIZON.to_radians().sin() - latitude.sin() * declination.sin())
+            / (latitude.cos() * declination.cos());
+        if !(-1.0..=1.0).contains(&cos_hour_angle) {
+            return None;
+        }
+        let hour_angle = cos_hour_angle.acos().to_degrees();
+
+        Some(SunTimes {
+            sunrise: from_julian(transit - hour_angle / 360.0)?,
+            sunset: from_julian(transit + hour_angle / 360.0)?,
+        })
+    }
+}
+
+fn from_julian(jd: f64) -> Option<DateTime<Utc>> {
+    let millis = ((jd - UNIX_EPOCH_JD) * 86_400_000.0).round() as i64;
+    DateTime::from_timestamp_millis(millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDateTime, TimeDelta};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")
+            .unwrap()
+            .and_utc()
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    fn assert_close(actual: DateTime<Utc>, expected: DateTime<Utc>) {
+        assert!(
+            (actual - expected).abs() <= TimeDelta::minutes(3),
+            "{actual} is not close to {expected}"
+        );
+    }
+
+    #[test]
+    fn computes_sunrise_and_sunset() {
+        // Published times of the Royal Observatory and of timeanddate.com
+        let cases = [
+            // Greenwich on the March equinox
+            (
+                51.4779,
+                0.0,
+                "2024-03-20",
+                "2024-03-20 06:02",
+                "2024-03-20 18:14",
+            ),
+            // Vienna at the summer solstice
+            (
+                48.21,
+                16.37,
+                "2024-06-21",
+                "2024-06-21 02:54",
+                "2024-06-21 18:58",
+            ),
+            // Sydney in winter, south of the equator and east of Greenwich
+            (
+                -33.87,
+                151.21,
+                "2024-06-21",
+                "2024-06-20 20:59",
+                "2024-06-21 06:54",
+            ),
+            // New York in winter, west of Greenwich
+            (
+                40.71,
+                -74.01,
+                "2024-12-21",
+                "2024-12-21 12:16",
+                "2024-12-21 21:32",
+            ),
+        ];
+        for (latitude, longitude, day, sunrise, sunset) in cases {
+            let location = Location {
+                latitude,
+                longitude,
+            };
+            let sun = location.sun_times(date(day)).unwrap();
+            assert_close(sun.sunrise, at(sunrise));
+            assert_close(sun.sunset, at(sunset));
+        }
+    }
+
+    #[test]
+    fn has_no_times_during_polar_day_and_night() {
+        let tromso = Location {
+            latitude: 69.65,
+            longitude: 18.96,
+        };
+        assert_eq!(tromso.sun_times(date("2024-06-21")), None);
+        assert_eq!(tromso.sun_times(date("2024-12-21")), None);
+        assert!(tromso.sun_times(date("2024-03-20")).is_some());
+
+        let south_pole = Location {
+            latitude: -90.0,
+            longitude: 0.0,
+        };
+        assert_eq!(south_pole.sun_times(date("2024-12-21")), None);
+        assert_eq!(south_pole.sun_times(date("2024-06-21")), None);
+    }
+}
@@ -0,0 +1,150 @@
+//! Remembers the last brightness written, for devices that cannot be read back.
+
+use std::path::PathBuf;
+use std::{env, fs, io};
+
+const STATE_FILE_NAME: &str = "brightness";
+
+/// The directory holding the last brightness written to each display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    /// `None` if there is no state directory, e.g. without `HOME`.
+    dir: Option<PathBuf>,
+}
+
+impl State {
+    /// Uses `$XDG_STATE_HOME/ws170120-ctl`, falling back to `~/.local/state`
+    /// when `XDG_STATE_HOME` is not set.
+    pub fn from_env() -> Self {
+        let state_home = env::var_os("XDG_STATE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")));
+        match state_home {
+            Some(dir) => Self::in_dir(dir.join(env!("CARGO_PKG_NAME"))),
+            None => Self { dir: None },
+        }
+    }
+
+    /// Keeps the state files in `dir`, which is created when needed.
+    pub fn in_dir(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: Some(dir.into()),
+        }
+    }
+
+    /// Returns the file `brightness`, or `brightness-<serial>` for displays
+    /// with a serial number.
+    fn file(&self, serial: Option<&str>) -> Option<PathBuf> {
+        let file_name = match serial {
+            Some(serial) => {
+                let serial: String = serial
+                    .chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                    .collect();
+                format!("{STATE_FILE_NAME}-{serial}")
+            }
+            None => STATE_FILE_NAME.to_string(),
+        };
+        Some(self.dir.as_ref()?.join(file_name))
+    }
+
+    /// Loads the last brightness written, if any was recorded.
+    pub fn load_brightness(&self, serial: Option<&str>) -> Option<u8> {
+        let contents = fs::read_to_string(self.file(serial)?).ok()?;
+        contents.trim().parse().ok()
+    }
+
+    /// Records `brightness` as the last value written.
+    pub fn store_brightness(&self, serial: Option<&str>, brightness: u8) -> io::Result<()> {
+        let path = self
+            .file(serial)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, format!("{brightness}\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn remembers_each_display() {
+        let dir = TestDir::new("state-serials");
+        let state = State::in_dir(dir.path());
+        state.store_brightness(Some("0123"), 40).unwrap();
+        state.store_brightness(Some("4567"), 75).unwrap();
+        state.store_brightness(None, 10).unwrap();
+        assert_eq!(state.load_brightness(Some("0123")), Some(40));
+        assert_eq!(state.load_brightness(Some("4567")), Some(75));
+        assert_eq!(state.load_brightness(None), Some(10));
+
+        state.store_brightness(Some("0123"), 0).unwrap();
+        assert_eq!(state.load_brightness(Some("0123")), Some(0));
+        assert_eq!(
+            fs::read_to_string(dir.join("brightness-0123")).unwrap(),
+            "0\n"
+        );
+    }
+
+    #[test]
+    fn knows_nothing_of_other_displays() {
+        let dir = TestDir::new("state-missing");
+        let state = State::in_dir(dir.path());
+        assert_eq!(state.load_brightness(Some("0123")), None);
+        assert_eq!(state.load_brightness(None), None);
+        state.store_brightness(Some("0123"), 40).unwrap();
+        assert_eq!(state.load_brightness(Some("4567")), None);
+        assert_eq!(state.load_brightness(None), None);
+    }
+
+    #[test]
+    fn keeps_serials_inside_the_directory() {
+        let dir = TestDir::new("state-names");
+        let state = State::in_dir(dir.join("state"));
+        state.store_brightness(Some("../WS 01/x"), 40).unwrap();
+        assert!(dir.join("state/brightness-___WS_01_x").is_file());
+        assert_eq!(state.load_brightness(Some("../WS 01/x")), Some(40));
+    }
+
+    #[test]
+    fn ignores_corrupt_files() {
+        let dir = TestDir::new("state-corrupt");
+        let state = State::in_dir(dir.path());
+        for contents in ["", "bright\n", "-1\n", "256\n", "4 0\n"] {
+            dir.write("brightness-0123", contents);
+            assert_eq!(state.load_brightness(Some("0123")), None, "{contents:?}");
+        }
+        dir.write("brightness-0123", " 40 \n");
+        assert_eq!(state.load_brightness(Some("0123")), Some(40));
+    }
+
+    #[test]
+    fn creates_the_directory() {
+        let dir = TestDir::new("state-create");
+        let state = State::in_dir(dir.join("state/ws170120-ctl"));
+        assert_eq!(state.load_brightness(None), None);
+        assert!(!dir.join("state").exists());
+        state.store_brightness(None, 60).unwrap();
+        assert!(dir.join("state/ws170120-ctl/brightness").is_file());
+        assert_eq!(state.load_brightness(None), Some(60));
+    }
+
+    #[test]
+    fn fails_to_store_without_a_directory() {
+        let state = State { dir: None };
+        assert_eq!(state.load_brightness(Some("0123")), None);
+        assert_eq!(
+            state.store_brightness(Some("0123"), 40).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        let dir = TestDir::new("state-blocked");
+        let state = State::in_dir(dir.write("file", ""));
+        assert!(state.store_brightness(None, 40).is_err());
+    }
+}
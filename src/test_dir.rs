@@ -0,0 +1,42 @@
+//! Temporary directories for the tests.
+
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+
+/// An empty directory below the system's temporary directory, removed when
+/// dropped.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    /// Creates the directory `ws170120-<name>-<pid>`, so `name` must differ
+    /// between tests that run at the same time.
+    pub fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("ws170120-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TestDir(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+
+    /// Writes `contents` to `path` below the directory, creating the
+    /// directories on the way, and returns the full path.
+    pub fn write(&self, path: impl AsRef<Path>, contents: &str) -> PathBuf {
+        let path = self.0.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,1133 @@
+//! Decoding of the touch screen's multi-touch reports.
+//!
+//! The touch controller is a separate HID interface of the display. Its input
+//! reports are laid out as declared in its report descriptor, so a
+//! [`TouchLayout`] is built from the decoded [`ReportDescriptor`] first. A
+//! [`TouchTracker`] then turns reports into contact events.
+
+use crate::descriptor::{Field, Node, ReportDescriptor, ReportKind, Usage};
+use crate::{DisplayInfo, Error, Result, Selector, WS170120_VENDOR_ID};
+use hidapi::{HidApi, HidDevice, MAX_REPORT_DESCRIPTOR_SIZE};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::CString;
+use std::fmt;
+use std::time::Duration;
+
+const DIGITIZERS: u16 = 0x0d;
+/// Upper limit for the number of simultaneous contacts.
+const MAX_CONTACTS: usize = 32;
+const GENERIC_DESKTOP: u16 = 0x01;
+const TOUCH_SCREEN: Usage = Usage {
+    page: DIGITIZERS,
+    id: 0x04,
+};
+const FINGER: Usage = Usage {
+    page: DIGITIZERS,
+    id: 0x22,
+};
+const TIP_SWITCH: Usage = Usage {
+    page: DIGITIZERS,
+    id: 0x42,
+};
+const CONFIDENCE: Usage = Usage {
+    page: DIGITIZERS,
+    id: 0x47,
+};
+const WIDTH: Usage = Usage {
+    page: DIGITIZERS,
+    id: 0x48,
+};
+const HEIGHT: Usage = Usage {
+    page: DIGITIZERS,
+    id: 0x49,
+};
+const CONTACT_IDENTIFIER: Usage = Usage {
+    page: DIGITIZERS,
+    id: 0x51,
+};
+const CONTACT_COUNT: Usage = Usage {
+    page: DIGITIZERS,
+    id: 0x54,
+};
+const X: Usage = Usage {
+    page: GENERIC_DESKTOP,
+    id: 0x30,
+};
+const Y: Usage = Usage {
+    page: GENERIC_DESKTOP,
+    id: 0x31,
+};
+
+/// Where a value is found in a report, after the report ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Value {
+    offset: u32,
+    size: u32,
+    signed: bool,
+}
+
+impl Value {
+    fn read(&self, data: &[u8]) -> Option<i64> {
+        if self.size == 0 || self.size > 32 {
+            return None;
+        }
+        let mut raw = 0u64;
+        for bit in 0..self.size {
+            let position = self.offset + bit;
+            let byte = *data.get(usize::try_from(position / 8).ok()?)?;
+            if byte >> (position % 8) & 1 != 0 {
+                raw |= 1 << bit;
+            }
+        }
+        if self.signed && raw >> (self.size - 1) & 1 != 0 {
+            Some(raw as i64 - (1 << self.size))
+        } else {
+            Some(raw as i64)
+        }
+    }
+}
+
+/// The values of one contact slot of a report.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Slot {
+    tip: Option<Value>,
+    id: Option<Value>,
+    x: Option<Value>,
+    y: Option<Value>,
+    confidence: Option<Value>,
+    width: Option<Value>,
+    height: Option<Value>,
+}
+
+/// How contacts are laid out in the touch screen's input reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TouchLayout {
+    /// The ID of the touch report, `None` if the device uses no report IDs.
+    pub report_id: Option<u8>,
+    /// Logical range of X coordinates.
+    pub x_range: (i32, i32),
+    /// Logical range of Y coordinates.
+    pub y_range: (i32, i32),
+    slots: Vec<Slot>,
+    contact_count: Option<Value>,
+    max_contacts: usize,
+}
+
+/// Collects the value positions while walking the descriptor.
+#[derive(Default)]
+struct Builder {
+    /// Bits used so far by each input report.
+    offsets: HashMap<Option<u8>, u32>,
+    report_id: Option<Option<u8>>,
+    slots: Vec<Slot>,
+    contact_count: Option<Value>,
+    contact_count_maximum: Option<i32>,
+    x_range: Option<(i32, i32)>,
+    y_range: Option<(i32, i32)>,
+}
+
+/// Returns the usage of each value of a variable field.
+fn value_usages(field: &Field) -> Vec<Option<Usage>> {
+    let mut usages: Vec<Usage> = field.usages.clone();
+    if let Some((minimum, maximum)) = field.usage_range {
+        usages.extend((minimum.id..=maximum.id).map(|id| Usage {
+            page: minimum.page,
+            id,
+        }));
+    }
+    // The last usage applies to any remaining values
+    (0..field.report_count as usize)
+        .map(|index| usages.get(index).or(usages.last()).copied())
+        .collect()
+}
+
+impl Builder {
+    fn walk(&mut self, nodes: &[Node], in_touch_screen: bool, slot: Option<usize>) {
+        for node in nodes {
+            match node {
+                Node::Collection(collection) => {
+                    let usage = collection.usage;
+                    if in_touch_screen && usage == Some(FINGER) {
+                        self.slots.push(Slot::default());
+                        let index = self.slots.len() - 1;
+                        self.walk(&collection.children, true, Some(index));
+                    } else {
+                        let touch_screen = in_touch_screen || usage == Some(TOUCH_SCREEN);
+                        self.walk(&collection.children, touch_screen, slot);
+                    }
+                }
+                Node::Field(field) if field.kind == ReportKind::Input => {
+                    self.field(field, in_touch_screen, slot);
+                }
+                Node::Field(_) => {}
+            }
+        }
+    }
+
+    fn field(&mut self, field: &Field, in_touch_screen: bool, slot: Option<usize>) {
+        let start = *self.offsets.entry(field.report_id).or_default();
+        self.offsets.insert(field.report_id, start + field.bits());
+        if !in_touch_screen || field.is_constant() || !field.is_variable() {
+            return;
+        }
+        if *self.report_id.get_or_insert(field.report_id) != field.report_id {
+            return;
+        }
+
+        for (index, usage) in value_usages(field).into_iter().enumerate() {
+            let Some(usage) = usage else {
+                continue;
+            };
+            let value = Value {
+                offset: start + index as u32 * field.report_size,
+                size: field.report_size,
+                signed: field.logical_minimum < 0,
+            };
+            let range = (field.logical_minimum, field.logical_maximum);
+            if usage == CONTACT_COUNT {
+                self.contact_count = Some(value);
+                self.contact_count_maximum = Some(field.logical_maximum);
+                continue;
+            }
+            // Single touch devices declare the values without a finger collection
+            let index = match slot {
+                Some(index) => index,
+                None => {
+                    if self.slots.is_empty() {
+                        self.slots.push(Slot::default());
+                    }
+                    0
+                }
+            };
+            let slot = &mut self.slots[index];
+            match usage {
+                TIP_SWITCH => slot.tip = Some(value),
+                CONTACT_IDENTIFIER => slot.id = Some(value),
+                CONFIDENCE => slot.confidence = Some(value),
+                WIDTH => slot.width = Some(value),
+                HEIGHT => slot.height = Some(value),
+                X => {
+                    slot.x = Some(value);
+                    self.x_range.get_or_insert(range);
+                }
+                Y => {
+                    slot.y = Some(value);
+                    self.y_range.get_or_insert(range);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl TouchLayout {
+    /// Finds the touch screen report in `descriptor`. Returns `None` if the
+    /// descriptor declares no touch screen with X and Y coordinates.
+    pub fn from_descriptor(descriptor: &ReportDescriptor) -> Option<Self> {
+        let mut builder = Builder::default();
+        builder.walk(&descriptor.nodes, false, None);
+        let slots: Vec<Slot> = builder
+            .slots
+            .into_iter()
+            .filter(|slot| slot.x.is_some() && slot.y.is_some())
+            .collect();
+        if slots.is_empty() {
+            return None;
+        }
+        // The declared maximum of the contact count is often just the
+        // field's range, so don't trust it beyond a sensible number
+        let max_contacts = builder
+            .contact_count_maximum
+            .and_then(|maximum| usize::try_from(maximum).ok())
+            .filter(|maximum| *maximum <= MAX_CONTACTS)
+            .unwrap_or(0)
+            .max(slots.len());
+        Some(TouchLayout {
+            report_id: builder.report_id.flatten(),
+            x_range: builder.x_range?,
+            y_range: builder.y_range?,
+            slots,
+            contact_count: builder.contact_count,
+            max_contacts,
+        })
+    }
+
+    /// The number of contacts each report can carry.
+    pub fn slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// The number of contacts the device can track at the same time, which
+    /// may be more than [`slots`](Self::slots) when frames span several
+    /// reports.
+    pub fn max_contacts(&self) -> usize {
+        self.max_contacts
+    }
+
+    /// Decodes one input report, as read from the device including the
+    /// report ID. Returns `None` if it is not a touch report.
+    pub fn decode(&self, report: &[u8]) -> Option<TouchReport> {
+        let data = match self.report_id {
+            Some(id) => report.strip_prefix(&[id])?,
+            None => report,
+        };
+        let contact_count = match self.contact_count {
+            Some(value) => Some(usize::try_from(value.read(data)?).unwrap_or(0)),
+            None => None,
+        };
+        let contacts = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                Some(Contact {
+                    id: match slot.id {
+                        Some(id) => u32::try_from(id.read(data)?).ok()?,
+                        None => index as u32,
+                    },
+                    x: slot.x?.read(data)? as i32,
+                    y: slot.y?.read(data)? as i32,
+                    // Without a tip switch, reported contacts are touching
+                    tip: match slot.tip {
+                        Some(tip) => tip.read(data)? != 0,
+                        None => true,
+                    },
+                    confidence: match slot.confidence {
+                        Some(confidence) => confidence.read(data)? != 0,
+                        None => true,
+                    },
+                    width: match slot.width {
+                        Some(width) => Some(width.read(data)? as i32),
+                        None => None,
+                    },
+                    height: match slot.height {
+                        Some(height) => Some(height.read(data)? as i32),
+                        None => None,
+                    },
+                })
+            })
+            .collect();
+        Some(TouchReport {
+            contact_count,
+            contacts,
+        })
+    }
+}
+
+/// One contact with the touch screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Contact {
+    /// Identifies the contact while it lasts.
+    pub id: u32,
+    pub x: i32,
+    pub y: i32,
+    /// Whether the contact is touching the screen.
+    pub tip: bool,
+    /// Whether the device considers the contact intentional rather than,
+    /// say, a palm. `true` if the device doesn't tell.
+    pub confidence: bool,
+    /// Size of the contact area in the units of the descriptor, if reported.
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+}
+
+/// The contents of one touch report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TouchReport {
+    /// The number of contacts in this frame, if the device reports it. Only
+    /// the first report of a frame that spans several reports carries it;
+    /// the others report zero.
+    pub contact_count: Option<usize>,
+    /// The values of every slot, including unused ones.
+    pub contacts: Vec<Contact>,
+}
+
+/// What happened to a contact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TouchPhase {
+    Down,
+    Move,
+    Up,
+}
+
+impl fmt::Display for TouchPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            TouchPhase::Down => "down",
+            TouchPhase::Move => "move",
+            TouchPhase::Up => "up",
+        })
+    }
+}
+
+/// A change of a contact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TouchEvent {
+    pub phase: TouchPhase,
+    /// The contact's position; for [`TouchPhase::Up`] the last known one.
+    pub contact: Contact,
+}
+
+/// An affine transform of touch coordinates, mapping a contact at `x`, `y`
+/// to
+///
+/// ```text
+/// x' = a·x + b·y + c
+/// y' = d·x + e·y + f
+/// ```
+///
+/// with the coefficients `[a, b, c, d, e, f]`. This covers rotated, flipped,
+/// scaled and shifted panels alike.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform(pub [f64; 6]);
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    /// The transform leaving coordinates as they are.
+    pub const IDENTITY: Transform = Transform([1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+
+    /// Returns the transform mapping the `measured` points onto the `target`
+    /// points of the same index as closely as possible, by least squares.
+    ///
+    /// Returns `None` if there are fewer than three pairs of points, or if the
+    /// measured points (nearly) lie on one line.
+    pub fn fit(measured: &[(f64, f64)], target: &[(f64, f64)]) -> Option<Self> {
+        let n = measured.len().min(target.len());
+        if n < 3 {
+            return None;
+        }
+        let (measured, target) = (&measured[..n], &target[..n]);
+        let mean = |points: &[(f64, f64)]| {
+            let (x, y) = points
+                .iter()
+                .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+            (x / n as f64, y / n as f64)
+        };
+        let (mx, my) = mean(measured);
+        let (tx, ty) = mean(target);
+
+        // Solve the normal equations around the means, where the offsets drop
+        // out and only a 2x2 system is left for each output coordinate
+        let (mut sxx, mut sxy, mut syy) = (0.0, 0.0, 0.0);
+        let (mut sxu, mut syu, mut sxv, mut syv) = (0.0, 0.0, 0.0, 0.0);
+        for ((x, y), (u, v)) in measured.iter().zip(target) {
+            let (x, y, u, v) = (x - mx, y - my, u - tx, v - ty);
+            sxx += x * x;
+            sxy += x * y;
+            syy += y * y;
+            sxu += x * u;
+            syu += y * u;
+            sxv += x * v;
+            syv += y * v;
+        }
+        let det = sxx * syy - sxy * sxy;
+        if det.is_nan() || det <= 1e-6 * sxx * syy {
+            return None;
+        }
+        let a = (sxu * syy - syu * sxy) / det;
+        let b = (syu * sxx - sxu * sxy) / det;
+        let d = (sxv * syy - syv * sxy) / det;
+        let e = (syv * sxx - sxv * sxy) / det;
+        Some(Transform([
+            a,
+            b,
+            tx - a * mx - b * my,
+            d,
+            e,
+            ty - d * mx - e * my,
+        ]))
+    }
+
+    /// Transforms the point `x`, `y`.
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        let [a, b, c, d, e, f] = self.0;
+        (a * x + b * y + c, d * x + e * y + f)
+    }
+
+    /// Transforms the position of `contact`, rounding to whole units.
+    pub fn apply_contact(&self, contact: Contact) -> Contact {
+        let (x, y) = self.apply(contact.x.into(), contact.y.into());
+        Contact {
+            x: x.round() as i32,
+            y: y.round() as i32,
+            ..contact
+        }
+    }
+}
+
+/// Turns touch reports into contact events.
+///
+/// Devices with more contacts than slots per report split a frame over
+/// several reports ("hybrid mode"), so events are only emitted once a frame
+/// is complete. Events carry coordinates as mapped by the tracker's
+/// [`Transform`], the identity unless set with [`TouchTracker::set_transform`].
+#[derive(Debug, Clone)]
+pub struct TouchTracker {
+    layout: TouchLayout,
+    transform: Transform,
+    pending: Vec<Contact>,
+    expected: usize,
+    active: BTreeMap<u32, Contact>,
+}
+
+impl TouchTracker {
+    pub fn new(layout: TouchLayout) -> Self {
+        Self {
+            layout,
+            transform: Transform::IDENTITY,
+            pending: Vec::new(),
+            expected: 0,
+            active: BTreeMap::new(),
+        }
+    }
+
+    pub fn layout(&self) -> &TouchLayout {
+        &self.layout
+    }
+
+    /// Maps the coordinates of all following events with `transform`.
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
+    /// The contacts currently touching the screen, by ID.
+    pub fn active(&self) -> &BTreeMap<u32, Contact> {
+        &self.active
+    }
+
+    /// Processes one report as read from the device, returning the events of
+    /// the frame it completes, if any.
+    pub fn feed(&mut self, report: &[u8]) -> Vec<TouchEvent> {
+        let Some(decoded) = self.layout.decode(report) else {
+            return Vec::new();
+        };
+        match decoded.contact_count {
+            // Without a count, every report is a frame of its touching slots
+            None => {
+                let frame = decoded.contacts.into_iter().filter(|c| c.tip).collect();
+                return self.finish(frame);
+            }
+            Some(0) if self.pending.is_empty() => return self.finish(Vec::new()),
+            Some(0) => {}
+            Some(count) => {
+                // A new frame starts, dropping an incomplete previous one
+                self.pending.clear();
+                self.expected = count;
+            }
+        }
+        let missing = self.expected.saturating_sub(self.pending.len());
+        self.pending
+            .extend(decoded.contacts.into_iter().take(missing));
+        if self.pending.len() < self.expected {
+            return Vec::new();
+        }
+        let frame = std::mem::take(&mut self.pending);
+        self.finish(frame)
+    }
+
+    fn finish(&mut self, frame: Vec<Contact>) -> Vec<TouchEvent> {
+        let frame: Vec<Contact> = frame
+            .into_iter()
+            .map(|contact| self.transform.apply_contact(contact))
+            .collect();
+        let mut events = Vec::new();
+        for contact in &frame {
+            let previous = self.active.get(&contact.id).copied();
+            match (contact.tip, previous) {
+                (true, None) => events.push(TouchEvent {
+                    phase: TouchPhase::Down,
+                    contact: *contact,
+                }),
+                (true, Some(previous)) if previous != *contact => events.push(TouchEvent {
+                    phase: TouchPhase::Move,
+                    contact: *contact,
+                }),
+                (true, Some(_)) => {}
+                (false, Some(_)) => events.push(TouchEvent {
+                    phase: TouchPhase::Up,
+                    contact: *contact,
+                }),
+                (false, None) => {}
+            }
+        }
+        // Contacts missing from the frame have been lifted
+        for (id, contact) in &self.active {
+            if !frame.iter().any(|c| c.id == *id) {
+                events.push(TouchEvent {
+                    phase: TouchPhase::Up,
+                    contact: Contact {
+                        tip: false,
+                        ..*contact
+                    },
+                });
+            }
+        }
+        self.active = frame
+            .into_iter()
+            .filter(|contact| contact.tip)
+            .map(|contact| (contact.id, contact))
+            .collect();
+        events
+    }
+}
+
+/// Drops the events of contacts that look like a palm or the side of a hand
+/// resting on the screen.
+///
+/// A contact counts as a palm if the device marks it as not confident, or if
+/// it is wider or higher than a limit. Once a contact is a palm, it is
+/// ignored until it is lifted; a contact turning into one is reported as
+/// lifted.
+#[derive(Debug, Clone, Default)]
+pub struct PalmFilter {
+    max_size: Option<i32>,
+    palms: BTreeSet<u32>,
+}
+
+impl PalmFilter {
+    /// Creates a filter treating contacts larger than `max_size` in either
+    /// direction as palms, in addition to those the device marks as such.
+    pub fn new(max_size: Option<i32>) -> Self {
+        Self {
+            max_size,
+            palms: BTreeSet::new(),
+        }
+    }
+
+    fn is_palm(&self, contact: &Contact) -> bool {
+        let too_large = |size: Option<i32>| match (size, self.max_size) {
+            (Some(size), Some(max_size)) => size > max_size,
+            _ => false,
+        };
+        !contact.confidence || too_large(contact.width) || too_large(contact.height)
+    }
+
+    /// Returns `events` without those of palms.
+    pub fn filter(&mut self, events: Vec<TouchEvent>) -> Vec<TouchEvent> {
+        let mut kept = Vec::with_capacity(events.len());
+        for event in events {
+            let id = event.contact.id;
+            if self.palms.contains(&id) {
+                if event.phase == TouchPhase::Up {
+                    self.palms.remove(&id);
+                }
+                continue;
+            }
+            if event.phase == TouchPhase::Up || !self.is_palm(&event.contact) {
+                kept.push(event);
+                continue;
+            }
+            self.palms.insert(id);
+            if event.phase == TouchPhase::Move {
+                kept.push(TouchEvent {
+                    phase: TouchPhase::Up,
+                    contact: Contact {
+                        tip: false,
+                        ..event.contact
+                    },
+                });
+            }
+        }
+        kept
+    }
+}
+
+/// Size of the buffer touch reports are read into.
+const REPORT_BUFFER_SIZE: usize = 256;
+
+/// The opened touch interface of a display.
+pub struct Touchscreen {
+    info: DisplayInfo,
+    device: HidDevice,
+    layout: TouchLayout,
+}
+
+impl Touchscreen {
+    /// Opens the first touch screen interface among the devices picked by
+    /// `selector`. The touch controller shares the display's vendor ID, so
+    /// every interface with that ID is considered.
+    pub fn open(api: &HidApi, selector: &Selector) -> Result<Self> {
+        let mut devices: Vec<_> = api
+            .device_list()
+            .filter(|info| info.vendor_id() == WS170120_VENDOR_ID)
+            .collect();
+        // Try interfaces that announce the Digitizers usage page first
+        devices.sort_by_key(|info| info.usage_page() != DIGITIZERS);
+        let candidates: Vec<DisplayInfo> = devices.into_iter().map(DisplayInfo::from).collect();
+        let candidates = match selector {
+            Selector::First | Selector::All => candidates,
+            _ => selector.select(candidates)?,
+        };
+
+        let mut error = None;
+        for info in candidates {
+            match Self::open_interface(api, info) {
+                Ok(Some(touchscreen)) => return Ok(touchscreen),
+                Ok(None) => {}
+                Err(e) => error = Some(e),
+            }
+        }
+        Err(error.unwrap_or(Error::NoTouchInterface))
+    }
+
+    /// Opens the interface `info` as touch screen. Returns `None` if its
+    /// report descriptor has no touch report.
+    pub fn open_interface(api: &HidApi, info: DisplayInfo) -> Result<Option<Self>> {
+        let path = CString::new(info.path.as_str()).map_err(|_| Error::NotConnected)?;
+        let device = api
+            .open_path(&path)
+            .map_err(|e| Error::from_device("Failed to open touch interface", e))?;
+        let mut buf = vec![0; MAX_REPORT_DESCRIPTOR_SIZE];
+        let read = device
+            .get_report_descriptor(&mut buf)
+            .map_err(|e| Error::from_device("Failed to read report descriptor", e))?;
+        let descriptor = ReportDescriptor::parse(&buf[..read])?;
+        Ok(
+            TouchLayout::from_descriptor(&descriptor).map(|layout| Touchscreen {
+                info,
+                device,
+                layout,
+            }),
+        )
+    }
+
+    /// The interface that was opened.
+    pub fn info(&self) -> &DisplayInfo {
+        &self.info
+    }
+
+    pub fn layout(&self) -> &TouchLayout {
+        &self.layout
+    }
+
+    /// Waits up to `timeout` for the next input report, or indefinitely if
+    /// `timeout` is `None`. Returns `None` if no report arrived in time.
+    pub fn read_report(&self, timeout: Option<Duration>) -> Result<Option<Vec<u8>>> {
+        let millis = match timeout {
+            Some(timeout) => i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX),
+            None => -1,
+        };
+        let mut buf = vec![0; REPORT_BUFFER_SIZE];
+        let read = self
+            .device
+            .read_timeout(&mut buf, millis)
+            .map_err(|e| Error::from_device("Failed to read touch report", e))?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buf.truncate(read);
+        Ok(Some(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        finger, touch_report, Finger, Recording, CONTROL_DESCRIPTOR, MOUSE, TOUCH_DESCRIPTOR,
+        TWO_CONTACTS,
+    };
+
+    fn layout() -> TouchLayout {
+        let descriptor = ReportDescriptor::parse(TOUCH_DESCRIPTOR).unwrap();
+        TouchLayout::from_descriptor(&descriptor).unwrap()
+    }
+
+    fn contact(id: u32, x: i32, y: i32) -> Contact {
+        Contact {
+            id,
+            x,
+            y,
+            tip: true,
+            confidence: true,
+            width: None,
+            height: None,
+        }
+    }
+
+    fn lifted(finger: Finger) -> Finger {
+        Finger {
+            tip: false,
+            ..finger
+        }
+    }
+
+    /// Returns the phase and contact ID of each event.
+    fn phases(events: &[TouchEvent]) -> Vec<(TouchPhase, u32)> {
+        events
+            .iter()
+            .map(|event| (event.phase, event.contact.id))
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_touch_report() {
+        let layout = layout();
+        assert_eq!(layout.report_id, Some(2));
+        assert_eq!(layout.x_range, (0, 4095));
+        assert_eq!(layout.y_range, (0, 4095));
+        assert_eq!(layout.slots(), 2);
+        assert_eq!(layout.max_contacts(), 5);
+
+        let control = ReportDescriptor::parse(CONTROL_DESCRIPTOR).unwrap();
+        assert_eq!(TouchLayout::from_descriptor(&control), None);
+    }
+
+    #[test]
+    fn decodes_contacts() {
+        let report = layout().decode(&TWO_CONTACTS).unwrap();
+        assert_eq!(report.contact_count, Some(2));
+        assert_eq!(
+            report.contacts,
+            [contact(3, 564, 1400), contact(4, 4095, 0)]
+        );
+    }
+
+    #[test]
+    fn decodes_flags_and_unused_slots() {
+        let unsure = Finger {
+            confident: false,
+            ..finger(7, 10, 20)
+        };
+        let report = layout().decode(&touch_report(&[unsure], 1)).unwrap();
+        assert_eq!(report.contact_count, Some(1));
+        assert_eq!(
+            report.contacts[0],
+            Contact {
+                confidence: false,
+                ..contact(7, 10, 20)
+            }
+        );
+        // The unused slot is decoded as a contact that doesn't touch
+        assert_eq!(
+            report.contacts[1],
+            Contact {
+                tip: false,
+                confidence: false,
+                ..contact(0, 0, 0)
+            }
+        );
+
+        let report = layout()
+            .decode(&touch_report(&[lifted(finger(7, 10, 20))], 1))
+            .unwrap();
+        assert!(!report.contacts[0].tip);
+    }
+
+    #[test]
+    fn ignores_other_reports() {
+        let layout = layout();
+        assert_eq!(layout.decode(&MOUSE), None);
+        assert_eq!(layout.decode(&[]), None);
+        // Too short to hold the contact count
+        assert_eq!(layout.decode(&TWO_CONTACTS[..8]), None);
+    }
+
+    #[test]
+    fn tracks_down_move_and_up() {
+        let mut tracker = TouchTracker::new(layout());
+        let events = tracker.feed(&touch_report(&[finger(3, 100, 200)], 1));
+        assert_eq!(events[0].phase, TouchPhase::Down);
+        assert_eq!(events[0].contact, contact(3, 100, 200));
+        assert_eq!(events.len(), 1);
+
+        let events = tracker.feed(&touch_report(&[finger(3, 110, 200)], 1));
+        assert_eq!(phases(&events), [(TouchPhase::Move, 3)]);
+        assert_eq!(events[0].contact, contact(3, 110, 200));
+
+        // Nothing changed
+        assert!(tracker
+            .feed(&touch_report(&[finger(3, 110, 200)], 1))
+            .is_empty());
+
+        let events = tracker.feed(&touch_report(&[finger(3, 110, 200), finger(4, 900, 50)], 2));
+        assert_eq!(phases(&events), [(TouchPhase::Down, 4)]);
+        assert_eq!(tracker.active().keys().copied().collect::<Vec<_>>(), [3, 4]);
+
+        // The device reports the lifted contact once with the tip switch off
+        let events = tracker.feed(&touch_report(
+            &[lifted(finger(3, 112, 201)), finger(4, 900, 50)],
+            2,
+        ));
+        assert_eq!(phases(&events), [(TouchPhase::Up, 3)]);
+        assert!(!events[0].contact.tip);
+        assert_eq!(tracker.active().keys().copied().collect::<Vec<_>>(), [4]);
+
+        // A contact missing from the frame has been lifted as well
+        let events = tracker.feed(&touch_report(&[], 0));
+        assert_eq!(phases(&events), [(TouchPhase::Up, 4)]);
+        assert_eq!(
+            events[0].contact,
+            Contact {
+                tip: false,
+                ..contact(4, 900, 50)
+            }
+        );
+        assert!(tracker.active().is_empty());
+    }
+
+    #[test]
+    fn joins_frames_spanning_several_reports() {
+        let mut tracker = TouchTracker::new(layout());
+        // Three contacts: the first report carries the count, the second
+        // reports zero and leaves its last slot unused
+        assert!(tracker
+            .feed(&touch_report(&[finger(1, 10, 10), finger(2, 20, 20)], 3))
+            .is_empty());
+        let events = tracker.feed(&touch_report(&[finger(5, 50, 50)], 0));
+        assert_eq!(
+            phases(&events),
+            [
+                (TouchPhase::Down, 1),
+                (TouchPhase::Down, 2),
+                (TouchPhase::Down, 5)
+            ]
+        );
+        assert_eq!(tracker.active().len(), 3);
+
+        // A frame that is cut short is dropped when the next one starts
+        assert!(tracker
+            .feed(&touch_report(&[finger(1, 11, 10), finger(2, 20, 20)], 3))
+            .is_empty());
+        let events = tracker.feed(&touch_report(&[finger(1, 12, 10)], 1));
+        assert_eq!(
+            phases(&events),
+            [
+                (TouchPhase::Move, 1),
+                (TouchPhase::Up, 2),
+                (TouchPhase::Up, 5)
+            ]
+        );
+        assert_eq!(events[0].contact.x, 12);
+    }
+
+    #[test]
+    fn ignores_reports_of_other_collections() {
+        let mut tracker = TouchTracker::new(layout());
+        tracker.feed(&touch_report(&[finger(3, 100, 200)], 1));
+        assert!(tracker.feed(&MOUSE).is_empty());
+        assert_eq!(tracker.active().len(), 1);
+    }
+
+    #[test]
+    fn transforms_event_coordinates() {
+        let mut tracker = TouchTracker::new(layout());
+        // Flips X across the logical range
+        tracker.set_transform(Transform([-1.0, 0.0, 4095.0, 0.0, 1.0, 0.0]));
+        let events = tracker.feed(&TWO_CONTACTS);
+        let positions: Vec<_> = events
+            .iter()
+            .map(|event| (event.contact.x, event.contact.y))
+            .collect();
+        assert_eq!(positions, [(3531, 1400), (0, 0)]);
+    }
+
+    #[test]
+    fn replays_recordings() {
+        for (path, recording) in Recording::all() {
+            let descriptor = ReportDescriptor::parse(&recording.descriptor)
+                .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+            // Only the touch interface has a touch report
+            let Some(layout) = TouchLayout::from_descriptor(&descriptor) else {
+                continue;
+            };
+            let (x_range, y_range) = (layout.x_range, layout.y_range);
+            let mut tracker = TouchTracker::new(layout);
+            let mut events = 0;
+            for report in &recording.reports {
+                for event in tracker.feed(report) {
+                    let Contact { x, y, .. } = event.contact;
+                    assert!(
+                        (x_range.0..=x_range.1).contains(&x)
+                            && (y_range.0..=y_range.1).contains(&y),
+                        "{}: {event:?} is out of range",
+                        path.display()
+                    );
+                    events += 1;
+                }
+            }
+            assert!(events > 0, "{} has no touches", path.display());
+            assert!(
+                tracker.active().is_empty(),
+                "{} ends with contacts down",
+                path.display()
+            );
+        }
+    }
+
+    fn assert_close(actual: Transform, expected: Transform) {
+        for (a, e) in actual.0.iter().zip(expected.0) {
+            assert!((a - e).abs() < 1e-9, "{actual:?} is not {expected:?}");
+        }
+    }
+
+    #[test]
+    fn fits_a_known_transform() {
+        // Rotated by 90 degrees, scaled from 0-4095 to 0-1023 and 0-599,
+        // and shifted
+        let expected = Transform([0.0, 0.25, 10.0, -0.15, 0.0, 620.0]);
+        let measured = [
+            (0.0, 0.0),
+            (4095.0, 0.0),
+            (4095.0, 4095.0),
+            (0.0, 4095.0),
+            (1234.0, 567.0),
+        ];
+        let target: Vec<_> = measured
+            .iter()
+            .map(|&(x, y)| expected.apply(x, y))
+            .collect();
+        assert_close(Transform::fit(&measured, &target).unwrap(), expected);
+        // Three points determine the transform
+        assert_close(
+            Transform::fit(&measured[1..4], &target[1..4]).unwrap(),
+            expected,
+        );
+        assert_close(
+            Transform::fit(&measured, &measured).unwrap(),
+            Transform::IDENTITY,
+        );
+    }
+
+    #[test]
+    fn fits_by_least_squares() {
+        let square = [(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)];
+        let measured = [(-3.0, 1.0), (98.0, 2.0), (103.0, 97.0), (1.0, 104.0)];
+        let transform = Transform::fit(&measured, &square).unwrap();
+        // The residuals of a least squares fit are orthogonal to each input,
+        // here 1, x and y
+        let mut sums = [0.0; 6];
+        for (&(x, y), (u, v)) in measured.iter().zip(square) {
+            let (tx, ty) = transform.apply(x, y);
+            let (du, dv) = (tx - u, ty - v);
+            for (sum, term) in sums
+                .iter_mut()
+                .zip([du, du * x, du * y, dv, dv * x, dv * y])
+            {
+                *sum += term;
+            }
+        }
+        assert!(sums.iter().all(|sum| sum.abs() < 1e-6), "{sums:?}");
+        // With residuals left over
+        let (x, y) = transform.apply(measured[0].0, measured[0].1);
+        assert!(x.hypot(y) > 0.1);
+        // Surplus points of either side are ignored
+        assert_eq!(
+            Transform::fit(&square, &square[..3]),
+            Transform::fit(&square[..3], &square)
+        );
+    }
+
+    #[test]
+    fn needs_three_points_off_a_line() {
+        let square = [(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)];
+        assert_eq!(Transform::fit(&[], &[]), None);
+        assert_eq!(Transform::fit(&square[..2], &square[..2]), None);
+        assert_eq!(Transform::fit(&square, &square[..2]), None);
+        let line = [(0.0, 0.0), (10.0, 10.0), (20.0, 20.0), (30.0, 30.0)];
+        assert_eq!(Transform::fit(&line, &square), None);
+        let nearly = [(0.0, 0.0), (10.0, 10.0), (20.0, 20.0001), (30.0, 30.0)];
+        assert_eq!(Transform::fit(&nearly, &square), None);
+        let same = [(50.0, 50.0); 4];
+        assert_eq!(Transform::fit(&same, &square), None);
+        let nan = [(f64::NAN, 0.0), (100.0, 0.0), (100.0, 100.0)];
+        assert_eq!(Transform::fit(&nan, &square), None);
+    }
+
+    fn touch(phase: TouchPhase, contact: Contact) -> TouchEvent {
+        TouchEvent { phase, contact }
+    }
+
+    fn sized(id: u32, width: i32, height: i32) -> Contact {
+        Contact {
+            width: Some(width),
+            height: Some(height),
+            ..contact(id, 100, 100)
+        }
+    }
+
+    #[test]
+    fn drops_large_contacts() {
+        let mut filter = PalmFilter::new(Some(100));
+        let events = filter.filter(vec![
+            touch(TouchPhase::Down, sized(1, 40, 50)),
+            touch(TouchPhase::Down, sized(2, 150, 50)),
+            touch(TouchPhase::Down, sized(3, 50, 101)),
+        ]);
+        assert_eq!(phases(&events), [(TouchPhase::Down, 1)]);
+
+        // A palm stays ignored until it is lifted, even if it shrinks
+        let events = filter.filter(vec![
+            touch(TouchPhase::Move, sized(2, 60, 50)),
+            touch(TouchPhase::Up, sized(3, 50, 101)),
+        ]);
+        assert!(events.is_empty());
+        let events = filter.filter(vec![touch(TouchPhase::Down, sized(3, 50, 50))]);
+        assert_eq!(phases(&events), [(TouchPhase::Down, 3)]);
+
+        // A contact growing into a palm is lifted
+        let events = filter.filter(vec![touch(TouchPhase::Move, sized(1, 120, 50))]);
+        assert_eq!(phases(&events), [(TouchPhase::Up, 1)]);
+        assert!(!events[0].contact.tip);
+        assert!(filter
+            .filter(vec![touch(TouchPhase::Up, sized(1, 120, 50))])
+            .is_empty());
+    }
+
+    #[test]
+    fn keeps_contacts_without_a_size() {
+        let mut filter = PalmFilter::new(Some(100));
+        let events = filter.filter(vec![touch(TouchPhase::Down, contact(1, 10, 10))]);
+        assert_eq!(phases(&events), [(TouchPhase::Down, 1)]);
+
+        // Without a limit, the size doesn't matter
+        let mut filter = PalmFilter::new(None);
+        let events = filter.filter(vec![touch(TouchPhase::Down, sized(2, 4000, 4000))]);
+        assert_eq!(phases(&events), [(TouchPhase::Down, 2)]);
+    }
+
+    #[test]
+    fn honors_the_confidence_bit() {
+        let unsure = Contact {
+            confidence: false,
+            ..contact(1, 10, 10)
+        };
+        for mut filter in [PalmFilter::new(None), PalmFilter::new(Some(100))] {
+            assert!(filter
+                .filter(vec![touch(TouchPhase::Down, unsure)])
+                .is_empty());
+            assert!(filter
+                .filter(vec![touch(TouchPhase::Up, unsure)])
+                .is_empty());
+        }
+
+        // Losing the confidence while down lifts the contact
+        let mut filter = PalmFilter::default();
+        filter.filter(vec![touch(TouchPhase::Down, contact(2, 10, 10))]);
+        let events = filter.filter(vec![touch(
+            TouchPhase::Move,
+            Contact {
+                confidence: false,
+                ..contact(2, 12, 10)
+            },
+        )]);
+        assert_eq!(phases(&events), [(TouchPhase::Up, 2)]);
+    }
+
+    #[test]
+    fn decoded_confidence_reaches_the_filter() {
+        let mut tracker = TouchTracker::new(layout());
+        let mut filter = PalmFilter::new(None);
+        let unsure = Finger {
+            confident: false,
+            ..finger(4, 900, 50)
+        };
+        let events = filter.filter(tracker.feed(&touch_report(&[finger(3, 100, 200), unsure], 2)));
+        assert_eq!(phases(&events), [(TouchPhase::Down, 3)]);
+    }
+}
@@ -0,0 +1,393 @@
+//! Reads the display's touch screen.
+
+use crate::cli_error::CliError;
+use crate::config::{self, Config};
+use crate::output::{self, Format};
+use crate::raw;
+use clap::Subcommand;
+use hidapi::HidApi;
+use std::path::Path;
+#[cfg(all(target_os = "linux", feature = "uinput"))]
+use ws170120::touch::PalmFilter;
+use ws170120::touch::{TouchEvent, TouchPhase, TouchTracker, Touchscreen, Transform};
+use ws170120::{Error, Selector};
+
+#[derive(Subcommand)]
+pub enum TouchCommand {
+    /// Print touch events as they happen
+    Monitor {
+        /// Also dump every report received
+        #[arg(long)]
+        raw: bool,
+
+        /// Print the coordinates as reported, ignoring the calibration
+        #[arg(long)]
+        uncalibrated: bool,
+    },
+    /// Match the touch coordinates to the picture by touching reference
+    /// points, and save the result to the configuration file
+    Calibrate {
+        /// Size of the picture in pixels, e.g. 1024x600 [default: the
+        /// coordinate range of the touch screen]
+        #[arg(long, value_parser = parse_size)]
+        size: Option<(u32, u32)>,
+
+        /// Touch this point instead of the corners of the picture, e.g.
+        /// 100,100; give at least three
+        #[arg(long = "target", value_name = "X,Y", value_parser = parse_point)]
+        targets: Vec<(f64, f64)>,
+
+        /// Print the transform without saving it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Feed the touches into Linux as a virtual multi-touch device
+    #[cfg(all(target_os = "linux", feature = "uinput"))]
+    Bridge {
+        /// Name of the virtual device
+        #[arg(long, default_value = crate::uinput::DEFAULT_NAME)]
+        name: String,
+
+        /// Pass the coordinates on as reported, ignoring the calibration
+        #[arg(long)]
+        uncalibrated: bool,
+
+        /// Drop contacts wider or higher than this, in the units of the touch
+        /// screen, as palms
+        #[arg(long, value_name = "SIZE")]
+        palm_size: Option<u16>,
+
+        /// Also pass on contacts the touch screen marks as palms
+        #[arg(long, conflicts_with = "palm_size")]
+        keep_palms: bool,
+    },
+}
+
+pub fn run(
+    command: &TouchCommand,
+    selector: &Selector,
+    config: &Config,
+    config_path: Option<&Path>,
+    format: Format,
+    verbose: u8,
+) -> Result<(), CliError> {
+    match command {
+        TouchCommand::Monitor { raw, uncalibrated } => monitor(
+            selector,
+            transform(config, *uncalibrated),
+            *raw,
+            format,
+            verbose,
+        ),
+        TouchCommand::Calibrate {
+            size,
+            targets,
+            dry_run,
+        } => {
+            if !targets.is_empty() && targets.len() < 3 {
+                return Err(CliError::InvalidArgument(
+                    "Calibration needs at least three targets.".to_string(),
+                ));
+            }
+            let touchscreen = open(selector, verbose)?;
+            let (targets, names) = if targets.is_empty() {
+                {
+                    let layout = touchscreen.layout();
+                    corners(layout.x_range, layout.y_range, *size)
+                }
+            } else {
+                let names = targets
+                    .iter()
+                    .map(|(x, y)| format!("point {x},{y}"))
+                    .collect();
+                (targets.clone(), names)
+            };
+            let (transform, deviation) = calibrate(&touchscreen, &targets, &names)?;
+            let saved = if *dry_run {
+                None
+            } else {
+                Some(config::save_touch_transform(config_path, &transform)?)
+            };
+            match format {
+                Format::Text => {
+                    let values: Vec<String> =
+                        transform.0.iter().map(|v| format!("{v:.6}")).collect();
+                    println!("transform = [{}]", values.join(", "));
+                    println!("Largest deviation from a target: {deviation:.1}");
+                    if let Some(path) = saved {
+                        println!("Saved to {}.", path.display());
+                    }
+                }
+                Format::Json => output::print_calibration(&transform, deviation, saved.as_deref()),
+            }
+            Ok(())
+        }
+        #[cfg(all(target_os = "linux", feature = "uinput"))]
+        TouchCommand::Bridge {
+            name,
+            uncalibrated,
+            palm_size,
+            keep_palms,
+        } => {
+            let palm_filter = (!keep_palms).then(|| PalmFilter::new(palm_size.map(i32::from)));
+            crate::uinput::run(
+                &open(selector, verbose)?,
+                name,
+                transform(config, *uncalibrated),
+                palm_filter,
+                verbose,
+            )
+        }
+    }
+}
+
+/// The transform from the configuration, unless `uncalibrated`.
+fn transform(config: &Config, uncalibrated: bool) -> Transform {
+    match config.touch_transform {
+        Some(transform) if !uncalibrated => transform,
+        _ => Transform::IDENTITY,
+    }
+}
+
+fn open(selector: &Selector, verbose: u8) -> Result<Touchscreen, CliError> {
+    let api = HidApi::new().map_err(Error::HidInit)?;
+    let touchscreen = Touchscreen::open(&api, selector)?;
+    let layout = touchscreen.layout();
+    if verbose > 0 {
+        println!(
+            "Reading touch reports from {}: report {}, {} contacts per report, x {}..{}, y {}..{}.",
+            touchscreen.info().path,
+            layout
+                .report_id
+                .map_or("without ID".to_string(), |id| format!("{id:02x}")),
+            layout.slots(),
+            layout.x_range.0,
+            layout.x_range.1,
+            layout.y_range.0,
+            layout.y_range.1
+        );
+    }
+    Ok(touchscreen)
+}
+
+fn monitor(
+    selector: &Selector,
+    transform: Transform,
+    raw: bool,
+    format: Format,
+    verbose: u8,
+) -> Result<(), CliError> {
+    let touchscreen = open(selector, verbose)?;
+    let mut tracker = TouchTracker::new(touchscreen.layout().clone());
+    tracker.set_transform(transform);
+    loop {
+        let Some(report) = touchscreen.read_report(None)? else {
+            continue;
+        };
+        if raw {
+            print!("{}", raw::hexdump(&report));
+        }
+        for event in tracker.feed(&report) {
+            print_event(&event, format);
+        }
+    }
+}
+
+fn print_event(event: &TouchEvent, format: Format) {
+    match format {
+        Format::Text => println!(
+            "{:<4} id={} x={} y={}",
+            event.phase, event.contact.id, event.contact.x, event.contact.y
+        ),
+        Format::Json => output::print_touch_event(event),
+    }
+}
+
+/// Returns the four corners of the picture, clockwise from the top left, and
+/// their names. Without a `size`, the picture is taken to cover the touch
+/// screen's coordinate ranges.
+fn corners(
+    x_range: (i32, i32),
+    y_range: (i32, i32),
+    size: Option<(u32, u32)>,
+) -> (Vec<(f64, f64)>, Vec<String>) {
+    let ((left, right), (top, bottom)) = match size {
+        Some((width, height)) => ((0.0, f64::from(width - 1)), (0.0, f64::from(height - 1))),
+        None => (
+            (x_range.0.into(), x_range.1.into()),
+            (y_range.0.into(), y_range.1.into()),
+        ),
+    };
+    let targets = vec![(left, top), (right, top), (right, bottom), (left, bottom)];
+    let names = ["top left", "top right", "bottom right", "bottom left"]
+        .iter()
+        .map(|name| format!("{name} corner"))
+        .collect();
+    (targets, names)
+}
+
+/// Asks the user to touch each target in turn and fits the transform onto
+/// them.
+fn calibrate(
+    touchscreen: &Touchscreen,
+    targets: &[(f64, f64)],
+    names: &[String],
+) -> Result<(Transform, f64), CliError> {
+    let mut tracker = TouchTracker::new(touchscreen.layout().clone());
+    let mut measured = Vec::new();
+    for ((x, y), name) in targets.iter().zip(names) {
+        eprintln!("Touch the {name} ({x}, {y}) with one finger, then lift it.");
+        let point = touch(touchscreen, &mut tracker)?;
+        eprintln!("  touched at {:.0}, {:.0}", point.0, point.1);
+        measured.push(point);
+    }
+    fit(&measured, targets)
+}
+
+/// Fits the transform mapping the `measured` touches onto the `targets`.
+/// Returns it with the largest distance of a transformed touch from its
+/// target.
+fn fit(measured: &[(f64, f64)], targets: &[(f64, f64)]) -> Result<(Transform, f64), CliError> {
+    let transform = Transform::fit(measured, targets).ok_or_else(|| {
+        CliError::InvalidArgument(
+            "The touched points lie on a line, so no transform fits them. Try again, touching each target exactly.".to_string(),
+        )
+    })?;
+    let deviation = measured
+        .iter()
+        .zip(targets)
+        .map(|(&(x, y), (tx, ty))| {
+            let (x, y) = transform.apply(x, y);
+            (x - tx).hypot(y - ty)
+        })
+        .fold(0.0, f64::max);
+    Ok((transform, deviation))
+}
+
+/// Waits for a single touch and returns its average position.
+fn touch(touchscreen: &Touchscreen, tracker: &mut TouchTracker) -> Result<(f64, f64), CliError> {
+    let mut contact = None;
+    let mut sum = (0.0, 0.0);
+    let mut samples = 0.0;
+    loop {
+        let Some(report) = touchscreen.read_report(None)? else {
+            continue;
+        };
+        for event in tracker.feed(&report) {
+            if *contact.get_or_insert(event.contact.id) != event.contact.id {
+                continue;
+            }
+            sum.0 += f64::from(event.contact.x);
+            sum.1 += f64::from(event.contact.y);
+            samples += 1.0;
+            if event.phase == TouchPhase::Up {
+                return Ok((sum.0 / samples, sum.1 / samples));
+            }
+        }
+    }
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("'{s}' is not a size like 1024x600");
+    let (width, height) = s.split_once(['x', 'X']).ok_or_else(invalid)?;
+    match (width.trim().parse(), height.trim().parse()) {
+        (Ok(width), Ok(height)) if width > 1 && height > 1 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_point(s: &str) -> Result<(f64, f64), String> {
+    let invalid = || format!("'{s}' is not a point like 100,100");
+    let (x, y) = s.split_once(',').ok_or_else(invalid)?;
+    match (x.trim().parse::<f64>(), y.trim().parse::<f64>()) {
+        (Ok(x), Ok(y)) if x.is_finite() && y.is_finite() => Ok((x, y)),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1024x600"), Ok((1024, 600)));
+        assert_eq!(parse_size("800X480"), Ok((800, 480)));
+        assert_eq!(parse_size(" 1280 x 800 "), Ok((1280, 800)));
+        for invalid in [
+            "",
+            "1024",
+            "1024x",
+            "x600",
+            "1024x600x2",
+            "1x600",
+            "-1024x600",
+            "big",
+        ] {
+            assert!(parse_size(invalid).is_err(), "size {invalid:?}");
+        }
+    }
+
+    #[test]
+    fn parses_points() {
+        assert_eq!(parse_point("100,100"), Ok((100.0, 100.0)));
+        assert_eq!(parse_point(" -2.5 , 7 "), Ok((-2.5, 7.0)));
+        for invalid in ["", "100", "100,", ",100", "1,2,3", "a,b", "inf,0", "0,NaN"] {
+            assert!(parse_point(invalid).is_err(), "point {invalid:?}");
+        }
+    }
+
+    #[test]
+    fn targets_the_corners() {
+        let (targets, names) = corners((0, 4095), (0, 4095), Some((1024, 600)));
+        assert_eq!(
+            targets,
+            [(0.0, 0.0), (1023.0, 0.0), (1023.0, 599.0), (0.0, 599.0)]
+        );
+        assert_eq!(
+            names,
+            [
+                "top left corner",
+                "top right corner",
+                "bottom right corner",
+                "bottom left corner"
+            ]
+        );
+
+        let (targets, _) = corners((10, 4000), (-5, 3000), None);
+        assert_eq!(
+            targets,
+            [
+                (10.0, -5.0),
+                (4000.0, -5.0),
+                (4000.0, 3000.0),
+                (10.0, 3000.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_largest_deviation() {
+        let targets = [(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)];
+        let (transform, deviation) = fit(&targets, &targets).unwrap();
+        assert_eq!(transform.apply(50.0, 50.0), (50.0, 50.0));
+        assert!(deviation < 1e-9, "{deviation}");
+
+        // No affine transform maps a square onto a square with one corner
+        // moved by 4 units; the best one misses every corner by 1 unit
+        let mut moved = targets;
+        moved[2] = (104.0, 100.0);
+        let (_, deviation) = fit(&targets, &moved).unwrap();
+        assert!((deviation - 1.0).abs() < 1e-9, "{deviation}");
+    }
+
+    #[test]
+    fn rejects_touches_on_a_line() {
+        let targets = [(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)];
+        let measured = [(0.0, 0.0), (10.0, 10.0), (20.0, 20.0), (30.0, 30.0)];
+        assert!(matches!(
+            fit(&measured, &targets),
+            Err(CliError::InvalidArgument(_))
+        ));
+    }
+}
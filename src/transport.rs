@@ -0,0 +1,61 @@
+use hidapi::{HidDevice, HidResult};
+use std::rc::Rc;
+
+/// The report-level operations the display protocol needs from a device.
+///
+/// [`HidDevice`] implements this for real hardware, while
+/// [`MockTransport`](crate::mock::MockTransport) records reports in memory.
+pub trait Transport {
+    /// Sends an output report, returning the number of bytes written.
+    fn write_output_report(&self, data: &[u8]) -> HidResult<usize>;
+
+    /// Sends a feature report. The first byte is the report ID.
+    fn send_feature_report(&self, data: &[u8]) -> HidResult<()>;
+
+    /// Reads the feature report whose ID is in `buf[0]` into `buf`.
+    fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize>;
+}
+
+impl Transport for HidDevice {
+    fn write_output_report(&self, data: &[u8]) -> HidResult<usize> {
+        self.write(data)
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> HidResult<()> {
+        HidDevice::send_feature_report(self, data)
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize> {
+        HidDevice::get_feature_report(self, buf)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for &T {
+    fn write_output_report(&self, data: &[u8]) -> HidResult<usize> {
+        (**self).write_output_report(data)
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> HidResult<()> {
+        (**self).send_feature_report(data)
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize> {
+        (**self).get_feature_report(buf)
+    }
+}
+
+/// Lets a test keep a handle on a [`MockTransport`](crate::mock::MockTransport)
+/// while a [`Display`](crate::Display) owns it.
+impl<T: Transport + ?Sized> Transport for Rc<T> {
+    fn write_output_report(&self, data: &[u8]) -> HidResult<usize> {
+        (**self).write_output_report(data)
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> HidResult<()> {
+        (**self).send_feature_report(data)
+    }
+
+    fn get_feature_report(&self, buf: &mut [u8]) -> HidResult<usize> {
+        (**self).get_feature_report(buf)
+    }
+}
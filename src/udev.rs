@@ -0,0 +1,224 @@
+//! Generates and installs udev rules granting non-root access to the display.
+
+use crate::cli_error::CliError;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use ws170120::{WS170120_PRODUCT_ID, WS170120_VENDOR_ID};
+
+#[derive(clap::Args)]
+pub struct UdevArgs {
+    /// Rules file to write
+    #[arg(long, default_value = "/etc/udev/rules.d/70-ws170120.rules")]
+    pub file: PathBuf,
+
+    /// Group owning the device nodes, e.g. plugdev
+    #[arg(long, value_parser = parse_group)]
+    pub group: Option<String>,
+
+    /// Permissions of the device nodes
+    #[arg(long, default_value = "0660", value_parser = parse_mode)]
+    pub mode: String,
+
+    /// Don't tag the device for access by the user logged in at the seat
+    #[arg(long)]
+    pub no_uaccess: bool,
+
+    /// Print the rules instead of installing them
+    #[arg(long, conflicts_with = "dry_run")]
+    pub print: bool,
+
+    /// Show what would be written and run, without changing anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+fn parse_mode(s: &str) -> Result<String, String> {
+    if (3..=4).contains(&s.len()) && s.chars().all(|c| ('0'..='7').contains(&c)) {
+        Ok(s.to_string())
+    } else {
+        Err(format!("{s} is not an octal mode like 0660"))
+    }
+}
+
+fn parse_group(s: &str) -> Result<String, String> {
+    if !s.is_empty()
+        && !s.starts_with('-')
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c))
+    {
+        Ok(s.to_string())
+    } else {
+        Err(format!("{s} is not a valid group name"))
+    }
+}
+
+/// Returns the rules file contents for `args`.
+pub fn rules(args: &UdevArgs) -> String {
+    let mut access = format!(r#"MODE="{}""#, args.mode);
+    if let Some(group) = &args.group {
+        access.push_str(&format!(r#", GROUP="{group}""#));
+    }
+    if !args.no_uaccess {
+        access.push_str(r#", TAG+="uaccess""#);
+    }
+    let vendor = format!("{WS170120_VENDOR_ID:04x}");
+    let product = format!("{WS170120_PRODUCT_ID:04x}");
+    format!(
+        "# Waveshare WS170120 display, written by {name} install-udev-rules\n\
+         SUBSYSTEM==\"usb\", ATTR{{idVendor}}==\"{vendor}\", ATTR{{idProduct}}==\"{product}\", {access}\n\
+         KERNEL==\"hidraw*\", SUBSYSTEM==\"hidraw\", ATTRS{{idVendor}}==\"{vendor}\", ATTRS{{idProduct}}==\"{product}\", {access}\n",
+        name = env!("CARGO_PKG_NAME"),
+    )
+}
+
+/// Commands that make udev apply the new rules to attached displays.
+fn reload_commands() -> [Vec<&'static str>; 2] {
+    [
+        vec!["udevadm", "control", "--reload-rules"],
+        vec![
+            "udevadm",
+            "trigger",
+            "--action=add",
+            "--subsystem-match=usb",
+            "--subsystem-match=hidraw",
+        ],
+    ]
+}
+
+pub fn run(args: &UdevArgs, verbose: u8) -> Result<(), CliError> {
+    let rules = rules(args);
+    if args.print {
+        print!("{rules}");
+        return Ok(());
+    }
+    if args.dry_run {
+        println!("Would write {}:", args.file.display());
+        print!("{rules}");
+        for command in reload_commands() {
+            println!("Would run: {}", command.join(" "));
+        }
+        return Ok(());
+    }
+
+    install(&args.file, &rules)?;
+    if verbose > 0 {
+        println!("Wrote {}.", args.file.display());
+    }
+    for command in reload_commands() {
+        let status = Command::new(command[0]).args(&command[1..]).status();
+        match status {
+            Ok(status) if status.success() => {}
+            // The rules still apply from the next time the display is plugged in
+            Ok(status) => eprintln!("{} exited with {}", command.join(" "), status),
+            Err(e) => eprintln!("Running {} failed: {}", command.join(" "), e),
+        }
+    }
+    println!("Installed udev rules, replug the display if it is still not accessible.");
+    Ok(())
+}
+
+fn install(file: &Path, rules: &str) -> Result<(), CliError> {
+    let failed = |source| CliError::Install {
+        path: file.to_path_buf(),
+        source,
+    };
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir).map_err(failed)?;
+    }
+    fs::write(file, rules).map_err(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn args(group: Option<&str>, mode: &str, no_uaccess: bool) -> UdevArgs {
+        UdevArgs {
+            file: PathBuf::from("/etc/udev/rules.d/70-ws170120.rules"),
+            group: group.map(str::to_string),
+            mode: mode.to_string(),
+            no_uaccess,
+            print: false,
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn writes_the_rules() {
+        assert_eq!(
+            rules(&args(Some("plugdev"), "0660", false)),
+            "# Waveshare WS170120 display, written by ws170120-ctl install-udev-rules\n\
+             SUBSYSTEM==\"usb\", ATTR{idVendor}==\"0eef\", ATTR{idProduct}==\"0005\", \
+             MODE=\"0660\", GROUP=\"plugdev\", TAG+=\"uaccess\"\n\
+             KERNEL==\"hidraw*\", SUBSYSTEM==\"hidraw\", ATTRS{idVendor}==\"0eef\", ATTRS{idProduct}==\"0005\", \
+             MODE=\"0660\", GROUP=\"plugdev\", TAG+=\"uaccess\"\n"
+        );
+    }
+
+    #[test]
+    fn leaves_out_what_is_not_asked_for() {
+        let rules = rules(&args(None, "0600", true));
+        assert_eq!(rules.lines().count(), 3);
+        for line in rules.lines().skip(1) {
+            assert!(line.ends_with(r#", MODE="0600""#), "{line}");
+        }
+        assert!(!rules.contains("GROUP"));
+        assert!(!rules.contains("uaccess"));
+
+        let rules = self::rules(&args(None, "666", false));
+        for line in rules.lines().skip(1) {
+            assert!(line.ends_with(r#", MODE="666", TAG+="uaccess""#), "{line}");
+        }
+    }
+
+    #[test]
+    fn checks_modes() {
+        for mode in ["0660", "0666", "660", "0600"] {
+            assert_eq!(parse_mode(mode), Ok(mode.to_string()));
+        }
+        for mode in [
+            "", "66", "0999", "0668", "abc", "06600", "0x660", " 660", "-660",
+        ] {
+            assert!(parse_mode(mode).is_err(), "mode {mode:?}");
+        }
+    }
+
+    #[test]
+    fn checks_group_names() {
+        for group in ["plugdev", "video", "_ws170120", "input-2", "my.group"] {
+            assert_eq!(parse_group(group), Ok(group.to_string()));
+        }
+        for group in [
+            "",
+            "plug dev",
+            "plugdev\"",
+            "plug\"dev",
+            "'plugdev'",
+            "plugdev,",
+            "a=b",
+            "-plugdev",
+            "grüppe",
+            "plugdev\n",
+        ] {
+            assert!(parse_group(group).is_err(), "group {group:?}");
+        }
+    }
+
+    #[test]
+    fn installs_the_rules_file() {
+        let dir = TestDir::new("udev");
+        let file = dir.join("rules.d/70-ws170120.rules");
+        install(&file, "rules\n").unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "rules\n");
+        install(&file, "new rules\n").unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "new rules\n");
+
+        let blocked = dir.write("file", "");
+        assert!(matches!(
+            install(&blocked.join("70-ws170120.rules"), "rules\n"),
+            Err(CliError::Install { .. })
+        ));
+    }
+}
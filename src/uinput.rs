@@ -0,0 +1,429 @@
+//! Feeds the touch screen into Linux as a virtual multi-touch device.
+//!
+//! The contacts decoded from the touch reports are transformed, filtered for
+//! palms and emitted through `/dev/uinput` using the type B multi-touch
+//! protocol, i.e. as `ABS_MT_SLOT` / `ABS_MT_TRACKING_ID` events, along with
+//! `BTN_TOUCH` and `ABS_X` / `ABS_Y` for single touch clients.
+//!
+//! The kernel's own driver, usually hid-multitouch, still reports the touches
+//! through the input devices it created for the touch interface. These are
+//! grabbed while the bridge runs, so that the raw touches only reach the
+//! virtual device's clients once, and calibrated.
+
+use crate::cli_error::CliError;
+use evdev::uinput::VirtualDevice;
+use evdev::{
+    AbsInfo, AbsoluteAxisCode, AttributeSet, BusType, Device, EventType, InputEvent, InputId,
+    KeyCode, PropType, UinputAbsSetup,
+};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use ws170120::touch::{
+    PalmFilter, TouchEvent, TouchLayout, TouchPhase, TouchTracker, Touchscreen, Transform,
+};
+use ws170120::{WS170120_PRODUCT_ID, WS170120_VENDOR_ID};
+
+/// Name of the virtual device unless given with `--name`.
+pub const DEFAULT_NAME: &str = "WS170120 Touchscreen";
+
+/// Largest tracking ID before wrapping around, as libinput expects.
+const MAX_TRACKING_ID: i32 = 0xffff;
+
+/// Where sysfs lists hidraw nodes along with the HID device behind them.
+const SYSFS_HIDRAW: &str = "/sys/class/hidraw";
+
+/// Returns the event nodes the kernel created for the HID device behind the
+/// hidraw node `hidraw`, looking it up in `class_dir`, normally
+/// [`SYSFS_HIDRAW`]. Paths that are no hidraw node have none.
+fn kernel_event_nodes(class_dir: &Path, hidraw: &str) -> io::Result<Vec<PathBuf>> {
+    let Some(name) = Path::new(hidraw)
+        .file_name()
+        .filter(|name| name.to_string_lossy().starts_with("hidraw"))
+    else {
+        return Ok(Vec::new());
+    };
+    let inputs = class_dir.join(name).join("device").join("input");
+    if !inputs.is_dir() {
+        // No driver created input devices, e.g. hid-multitouch is unbound
+        return Ok(Vec::new());
+    }
+    let mut nodes = Vec::new();
+    for input in fs::read_dir(inputs)? {
+        for entry in fs::read_dir(input?.path())? {
+            let name = entry?.file_name();
+            if name.to_string_lossy().starts_with("event") {
+                nodes.push(Path::new("/dev/input").join(name));
+            }
+        }
+    }
+    nodes.sort();
+    Ok(nodes)
+}
+
+/// Grabs the kernel's input devices for the touch interface at `hidraw`, so
+/// that their events reach no one else. The grab lasts until the returned
+/// devices are dropped.
+///
+/// Devices that can't be grabbed are reported and left as they are.
+fn grab_kernel_devices(hidraw: &str, verbose: u8) -> Vec<Device> {
+    let nodes = match kernel_event_nodes(Path::new(SYSFS_HIDRAW), hidraw) {
+        Ok(nodes) => nodes,
+        Err(e) => {
+            eprintln!(
+                "Finding the kernel's input devices for {} failed: {}. Touches may be reported twice.",
+                hidraw, e
+            );
+            return Vec::new();
+        }
+    };
+    let mut grabbed = Vec::new();
+    for node in nodes {
+        match Device::open(&node).and_then(|mut device| device.grab().map(|()| device)) {
+            Ok(device) => {
+                if verbose > 0 {
+                    println!("Grabbed the kernel's input device {}.", node.display());
+                }
+                grabbed.push(device);
+            }
+            Err(e) => eprintln!(
+                "Grabbing the kernel's input device {} failed: {}. Touches are reported twice unless it is ignored, see the README.",
+                node.display(),
+                e
+            ),
+        }
+    }
+    grabbed
+}
+
+/// Returns the range of coordinates `layout` covers after `transform`.
+fn ranges(layout: &TouchLayout, transform: &Transform) -> ((i32, i32), (i32, i32)) {
+    let (x0, x1) = (f64::from(layout.x_range.0), f64::from(layout.x_range.1));
+    let (y0, y1) = (f64::from(layout.y_range.0), f64::from(layout.y_range.1));
+    let corners = [(x0, y0), (x1, y0), (x1, y1), (x0, y1)].map(|(x, y)| transform.apply(x, y));
+    let range = |values: [f64; 4]| {
+        let minimum = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let maximum = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        (minimum.round() as i32, maximum.round() as i32)
+    };
+    (
+        range(corners.map(|(x, _)| x)),
+        range(corners.map(|(_, y)| y)),
+    )
+}
+
+/// The virtual device and the state of its slots.
+struct Bridge {
+    device: VirtualDevice,
+    /// The contact ID using each slot.
+    slots: Vec<Option<u32>>,
+    next_tracking_id: i32,
+    touching: bool,
+}
+
+impl Bridge {
+    /// Creates a device with `slots` contacts whose coordinates cover
+    /// `ranges`, as returned by [`ranges`].
+    fn create(name: &str, ranges: ((i32, i32), (i32, i32)), slots: usize) -> io::Result<Self> {
+        let ((x_min, x_max), (y_min, y_max)) = ranges;
+        let axis = |code, minimum, maximum| {
+            UinputAbsSetup::new(code, AbsInfo::new(0, minimum, maximum, 0, 0, 0))
+        };
+        let keys: AttributeSet<KeyCode> = [KeyCode::BTN_TOUCH, KeyCode::BTN_TOOL_FINGER]
+            .into_iter()
+            .collect();
+        let properties: AttributeSet<PropType> = [PropType::DIRECT].into_iter().collect();
+        let device = VirtualDevice::builder()?
+            .name(name)
+            .input_id(InputId::new(
+                BusType::BUS_VIRTUAL,
+                WS170120_VENDOR_ID,
+                WS170120_PRODUCT_ID,
+                1,
+            ))
+            .with_keys(&keys)?
+            .with_properties(&properties)?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_X, x_min, x_max))?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_Y, y_min, y_max))?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_SLOT, 0, slots as i32 - 1))?
+            .with_absolute_axis(&axis(
+                AbsoluteAxisCode::ABS_MT_TRACKING_ID,
+                0,
+                MAX_TRACKING_ID,
+            ))?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_POSITION_X, x_min, x_max))?
+            .with_absolute_axis(&axis(AbsoluteAxisCode::ABS_MT_POSITION_Y, y_min, y_max))?
+            .build()?;
+        Ok(Self {
+            device,
+            slots: vec![None; slots],
+            next_tracking_id: 0,
+            touching: false,
+        })
+    }
+
+    /// Emits the changes of one frame, followed by a `SYN_REPORT`.
+    fn emit(&mut self, changes: &[TouchEvent]) -> io::Result<()> {
+        let mut events = Vec::new();
+        let abs =
+            |code: AbsoluteAxisCode, value| InputEvent::new(EventType::ABSOLUTE.0, code.0, value);
+        for TouchEvent { phase, contact } in changes {
+            let slot = match phase {
+                TouchPhase::Down => self.slots.iter().position(Option::is_none),
+                TouchPhase::Move | TouchPhase::Up => {
+                    self.slots.iter().position(|slot| *slot == Some(contact.id))
+                }
+            };
+            // Contacts beyond the number of slots are dropped
+            let Some(slot) = slot else {
+                continue;
+            };
+            events.push(abs(AbsoluteAxisCode::ABS_MT_SLOT, slot as i32));
+            match phase {
+                TouchPhase::Down => {
+                    self.slots[slot] = Some(contact.id);
+                    events.push(abs(
+                        AbsoluteAxisCode::ABS_MT_TRACKING_ID,
+                        self.next_tracking_id,
+                    ));
+                    self.next_tracking_id = (self.next_tracking_id + 1) % (MAX_TRACKING_ID + 1);
+                }
+                TouchPhase::Move => {}
+                TouchPhase::Up => {
+                    self.slots[slot] = None;
+                    events.push(abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, -1));
+                    continue;
+                }
+            }
+            events.push(abs(AbsoluteAxisCode::ABS_MT_POSITION_X, contact.x));
+            events.push(abs(AbsoluteAxisCode::ABS_MT_POSITION_Y, contact.y));
+        }
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let touching = self.slots.iter().any(Option::is_some);
+        if touching != self.touching {
+            self.touching = touching;
+            for key in [KeyCode::BTN_TOUCH, KeyCode::BTN_TOOL_FINGER] {
+                events.push(InputEvent::new(EventType::KEY.0, key.0, touching.into()));
+            }
+        }
+        // Single touch clients follow the first contact that is still down
+        let first = self.slots.iter().flatten().next();
+        if let Some(event) = changes
+            .iter()
+            .find(|event| event.phase != TouchPhase::Up && Some(&event.contact.id) == first)
+        {
+            events.push(abs(AbsoluteAxisCode::ABS_X, event.contact.x));
+            events.push(abs(AbsoluteAxisCode::ABS_Y, event.contact.y));
+        }
+        self.device.emit(&events)
+    }
+}
+
+/// Creates the virtual device and forwards the touches of `touchscreen` to
+/// it until reading fails.
+pub fn run(
+    touchscreen: &Touchscreen,
+    name: &str,
+    transform: Transform,
+    palm_filter: Option<PalmFilter>,
+    verbose: u8,
+) -> Result<(), CliError> {
+    let layout = touchscreen.layout();
+    let mut bridge = Bridge::create(name, ranges(layout, &transform), layout.max_contacts())
+        .map_err(CliError::Uinput)?;
+    if verbose > 0 {
+        let nodes: Vec<String> = bridge
+            .device
+            .enumerate_dev_nodes_blocking()
+            .map_err(CliError::Uinput)?
+            .flatten()
+            .map(|path| path.display().to_string())
+            .collect();
+        println!(
+            "Created virtual touch screen '{name}' with {} slots at {}.",
+            bridge.slots.len(),
+            nodes.join(", ")
+        );
+    }
+
+    // Released when the bridge stops
+    let _grabbed = grab_kernel_devices(&touchscreen.info().path, verbose);
+
+    let mut tracker = TouchTracker::new(layout.clone());
+    tracker.set_transform(transform);
+    let mut palm_filter = palm_filter;
+    loop {
+        let Some(report) = touchscreen.read_report(None)? else {
+            continue;
+        };
+        let mut events = tracker.feed(&report);
+        if let Some(filter) = &mut palm_filter {
+            events = filter.filter(events);
+        }
+        bridge.emit(&events).map_err(CliError::Uinput)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use std::collections::BTreeMap;
+    use std::thread;
+    use std::time::Duration;
+    use ws170120::touch::Contact;
+
+    fn event(phase: TouchPhase, id: u32, x: i32, y: i32) -> TouchEvent {
+        TouchEvent {
+            phase,
+            contact: Contact {
+                id,
+                x,
+                y,
+                tip: phase != TouchPhase::Up,
+                confidence: true,
+                width: None,
+                height: None,
+            },
+        }
+    }
+
+    #[test]
+    fn finds_the_kernel_event_nodes() {
+        // Laid out like /sys/class/hidraw
+        let sysfs = TestDir::new("uinput-nodes");
+        for entry in [
+            "hidraw3/device/input/input18/event6",
+            "hidraw3/device/input/input18/mouse1",
+            "hidraw3/device/input/input17/event5",
+            "hidraw4/device/input/input20/event9",
+            "hidraw5/device",
+        ] {
+            fs::create_dir_all(sysfs.join(entry)).unwrap();
+        }
+        let nodes = |hidraw| kernel_event_nodes(sysfs.path(), hidraw).unwrap();
+        assert_eq!(
+            nodes("/dev/hidraw3"),
+            [
+                PathBuf::from("/dev/input/event5"),
+                PathBuf::from("/dev/input/event6")
+            ]
+        );
+        // Unbound from its driver
+        assert!(nodes("/dev/hidraw5").is_empty());
+        // Not a hidraw node, e.g. a path of the libusb backend
+        assert!(nodes("1-2:1.0").is_empty());
+    }
+
+    /// The state of the virtual device as a multi-touch client tracks it.
+    #[derive(Debug, Default)]
+    struct Client {
+        slot: i32,
+        /// Tracking ID and position of each slot in use.
+        slots: BTreeMap<i32, (i32, i32, i32)>,
+        touching: bool,
+        position: (i32, i32),
+    }
+
+    impl Client {
+        /// Reads the events of the frames emitted so far.
+        fn read(&mut self, device: &mut Device) {
+            for event in device.fetch_events().unwrap() {
+                let value = event.value();
+                if event.event_type() == EventType::KEY {
+                    if event.code() == KeyCode::BTN_TOUCH.0 {
+                        self.touching = value != 0;
+                    }
+                    continue;
+                }
+                if event.event_type() != EventType::ABSOLUTE {
+                    continue;
+                }
+                let slot = self.slots.entry(self.slot).or_insert((-1, 0, 0));
+                match AbsoluteAxisCode(event.code()) {
+                    AbsoluteAxisCode::ABS_MT_SLOT => self.slot = value,
+                    AbsoluteAxisCode::ABS_MT_TRACKING_ID => slot.0 = value,
+                    AbsoluteAxisCode::ABS_MT_POSITION_X => slot.1 = value,
+                    AbsoluteAxisCode::ABS_MT_POSITION_Y => slot.2 = value,
+                    AbsoluteAxisCode::ABS_X => self.position.0 = value,
+                    AbsoluteAxisCode::ABS_Y => self.position.1 = value,
+                    _ => {}
+                }
+                self.slots
+                    .retain(|_, (tracking_id, _, _)| *tracking_id != -1);
+            }
+        }
+    }
+
+    /// Creates a bridge with two slots and opens its event node.
+    fn virtual_device() -> (Bridge, Device) {
+        let mut bridge = Bridge::create("WS170120 Test", ((0, 4095), (0, 4095)), 2)
+            .expect("creating the virtual device needs write access to /dev/uinput");
+        let node = bridge
+            .device
+            .enumerate_dev_nodes_blocking()
+            .unwrap()
+            .flatten()
+            .next()
+            .expect("the virtual device has an event node");
+        // The node appears shortly after the device
+        for _ in 0..50 {
+            if let Ok(device) = Device::open(&node) {
+                return (bridge, device);
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("{} did not appear", node.display());
+    }
+
+    #[test]
+    #[ignore = "needs write access to /dev/uinput, run with --ignored"]
+    fn reports_touches_on_the_event_node() {
+        let (mut bridge, mut device) = virtual_device();
+        let mut client = Client::default();
+
+        bridge
+            .emit(&[
+                event(TouchPhase::Down, 7, 100, 200),
+                event(TouchPhase::Down, 8, 300, 400),
+            ])
+            .unwrap();
+        client.read(&mut device);
+        assert_eq!(
+            client.slots,
+            BTreeMap::from([(0, (0, 100, 200)), (1, (1, 300, 400))])
+        );
+        assert!(client.touching);
+        assert_eq!(client.position, (100, 200));
+
+        bridge
+            .emit(&[event(TouchPhase::Move, 7, 110, 210)])
+            .unwrap();
+        client.read(&mut device);
+        assert_eq!(client.slots[&0], (0, 110, 210));
+        assert_eq!(client.position, (110, 210));
+
+        bridge.emit(&[event(TouchPhase::Up, 7, 110, 210)]).unwrap();
+        client.read(&mut device);
+        assert_eq!(client.slots, BTreeMap::from([(1, (1, 300, 400))]));
+        assert!(client.touching);
+
+        // The free slot is reused with a new tracking ID
+        bridge
+            .emit(&[
+                event(TouchPhase::Up, 8, 300, 400),
+                event(TouchPhase::Down, 9, 50, 60),
+            ])
+            .unwrap();
+        client.read(&mut device);
+        assert_eq!(client.slots, BTreeMap::from([(0, (2, 50, 60))]));
+
+        bridge.emit(&[event(TouchPhase::Up, 9, 50, 60)]).unwrap();
+        client.read(&mut device);
+        assert!(client.slots.is_empty());
+        assert!(!client.touching);
+    }
+}